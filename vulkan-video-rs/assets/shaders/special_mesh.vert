@@ -1,7 +1,6 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
-#include "utils/camera.glsl"
 #include "utils/common.glsl"
 
 layout(location = 0) out vec3 frag_color;
@@ -10,15 +9,27 @@ layout(location = 0) in vec3 pos;
 layout(location = 1) in vec3 col;
 layout(location = 2) in vec3 normals;
 
+// Per instance, MeshInstance in drawable_instanced.rs
+layout(location = 3) in mat4 instance_model;
+layout(location = 7) in vec4 instance_col;
+
+// The scene's own orbiting camera, used unless GlobalCamera is positive
 layout(set = 1, binding = 0) uniform Shared
 {
+    mat4  View;
     float Time;
-    float Aspect;
     float GlobalCamera;
 } S;
 
+// Transform of the DrawableMesh, shared by its instances
+layout(push_constant) uniform Model
+{
+    mat4 Model;
+} M;
+
 
-vec3 light = vec3(-1, 0, -1);
+// Towards the light, the normals point out of the front faces
+vec3 light = vec3(1, 0, 1);
 
 #define TARGET_COL_COLOUR  0
 #define TARGET_COL_NORMAL  1
@@ -27,52 +38,31 @@ vec3 light = vec3(-1, 0, -1);
 #define TARGET 0
 
 
-#define PI 3.141592653589793
-
-
 void main() {
 
-    float time   = S.Time;
-    float period = radians(180) / 10;
-    float ST = sin(time*period/2);
-    float CT = cos(time*period/2);
-
-    float STO = 1 - ST*ST;
-    vec3  camera_pos = vec3(0, 0, 7);
-    vec3  camera_dir = normalize(vec3(ST, 0, CT));
-    vec3  camera_up  = vec3(0, 1, 0);
-
-    if (S.GlobalCamera > 0) {
-        camera_pos = G.CamPos;
-        camera_dir = G.CamDir;
-        // camera_up  = G.CamUp;
-    }
-
-    float FOV   = PI / 4;
-
-    mat4 view = create_view_matrix(camera_pos, camera_dir, camera_up);
-
-    vec4 world_pos = view * vec4(pos, 1.0);
-    world_pos.z *= -1;
+    mat4 view = S.GlobalCamera > 0 ? G.View : S.View;
 
+    mat4 model = M.Model * instance_model;
+    vec3 model_pos = (model * vec4(pos, 1.0)).xyz;
+    vec3 model_normal = normalize(transpose(inverse(mat3(model))) * normals);
+    vec3 model_col = col * instance_col.rgb;
 
-    mat4 proj = create_projection_matrix(FOV, S.Aspect);
-    vec4 proj_pos = proj * world_pos;
+    vec4 view_pos = view * vec4(model_pos, 1.0);
 
-    gl_Position = proj_pos;
+    gl_Position = G.Projection * view_pos;
 
     if (TARGET == TARGET_COL_COLOUR) {
-        float light_cos = dot(normals,light);
+        float light_cos = dot(model_normal,light);
         float alpha = ((light_cos * -1) + 1) / 2;
         float dark_factor = 0.8 * alpha;
 
-        frag_color = 0.2 * vec3(col.x, col.y, col.z) * ( 1 - dark_factor);
+        frag_color = 0.2 * model_col * ( 1 - dark_factor);
 
     } else if (TARGET == TARGET_COL_DEPTH) {
-        float dz_col = (world_pos.z - camera_pos.z - 1) / 8.0;
+        float dz_col = (-view_pos.z - 1) / 8.0;
         frag_color = vec3(dz_col, dz_col, dz_col);
 
     } else {
-        frag_color = normals;
+        frag_color = model_normal;
     }
 }
@@ -7,6 +7,7 @@ layout(location = 0) in vec3 frag_color;
 layout(location = 0) out vec4 out_color;
 
 void main() {
-    out_color = vec4(frag_color, 1.0);
+    // Drawable2d blends with BlendMode::Alpha
+    out_color = vec4(frag_color, 0.6);
     // out_color = vec4(1.0, 1.0, 1.0, 1.0);    
 }
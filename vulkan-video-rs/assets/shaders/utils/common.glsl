@@ -3,4 +3,9 @@ layout(set = 0, binding = 0) uniform GlobalParameters
     vec3 CamPos;
     vec3 CamDir;
     vec3 CamUp;
+    vec4 Viewport;
+
+    // Computed on the CPU, see Camera::update_matrices
+    mat4 View;
+    mat4 Projection;
 } G;
@@ -46,7 +46,9 @@ impl RecordPlayer {
         })
     }
 
+    /* Also keeps current_frame on the polled frame */
     pub fn poll(&mut self) -> Option<Vec<u8>> {
+        self.current_frame = self.record_data.get_frame(self.stream_pos);
         let res = Some(self.record_data.get_frame_bytes(self.stream_pos));
         self.stream_pos = (self.stream_pos + 1) % self.record_data.frame_count;
         res
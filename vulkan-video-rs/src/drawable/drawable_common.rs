@@ -1,11 +1,75 @@
+use std::any::Any;
+
 use ash::vk;
 
+use crate::geometry::mat4::Mat4;
+use crate::mesh::Indices;
+use crate::rhi::core::GraphicsPSO;
+use crate::rhi::ring::UploadRing;
+use crate::rhi::transfer::UploadBatch;
+use crate::vk_bundles::{BufferBundle, GraphicsPipelineBundle};
 use crate::DeviceBundle;
 
 
-#[allow(unused)]
-pub trait Drawable {
+/* Something with GPU buffers that can upload its changes and draw itself, see drawable::registry */
+pub trait Drawable: Any {
+    /* Used for debug labels */
+    fn name(&self) -> &'static str;
+
     fn dirty(&self) -> bool;
-    fn update(&self, device: &DeviceBundle);
-    fn record_update(&self, device: &DeviceBundle, command_buffer: &vk::CommandBuffer);
+
+    /* Record uploads of whatever changed since the last call and clear the dirty flags */
+    fn upload(&mut self, device: &DeviceBundle, batch: &mut UploadBatch, ring: &mut UploadRing);
+
+    /* The pipeline draw expects to be bound, VkBase::request_pipeline creates it on first use */
+    fn pipeline_state(&self) -> GraphicsPSO;
+
+    fn draw(&self, device: &DeviceBundle, command_buffer: vk::CommandBuffer, graphics_pipeline: &GraphicsPipelineBundle, current_image: usize);
+}
+
+/*
+ * Push data to the upload ring and copy it to dst, which the graphics queue reads at dst_stage.
+ * dst is a range of a shared allocator block, so data larger than it would overwrite its neighbours.
+ */
+pub fn upload_range<T: Copy>(
+    device: &DeviceBundle, batch: &mut UploadBatch, ring: &mut UploadRing, data: &[T],
+    dst: &BufferBundle, dst_stage: vk::PipelineStageFlags, dst_access: vk::AccessFlags
+) {
+    if data.is_empty() {
+        return;
+    }
+
+    let size = std::mem::size_of_val(data) as u64;
+    assert!(size <= dst.size, "Upload of {} bytes doesn't fit the {} bytes allocated for it", size, dst.size);
+
+    let staging = ring.stage_slice(device, data).expect("Failed to stage the upload.");
+
+    let copy_region = [
+        vk::BufferCopy::default()
+            .src_offset(staging.offset)
+            .dst_offset(dst.offset)
+            .size(staging.size)
+    ];
+
+    unsafe {
+        device.logical.cmd_copy_buffer(batch.cb, staging.buffer, dst.buffer, &copy_region);
+    }
+
+    batch.release_bundle(dst, dst_stage, dst_access);
+}
+
+/* Like upload_range, in whichever index type the mesh uses */
+pub fn upload_indices(device: &DeviceBundle, batch: &mut UploadBatch, ring: &mut UploadRing, indices: &Indices, dst: &BufferBundle) {
+    match indices {
+        Indices::U16(indices) => upload_range(device, batch, ring, indices, dst, vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::INDEX_READ),
+        Indices::U32(indices) => upload_range(device, batch, ring, indices, dst, vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::INDEX_READ),
+    }
+}
+
+/* The model matrix is the vertex stage push constant of ShaderMesh and ShaderSpecialMesh */
+pub fn push_model(device: &DeviceBundle, command_buffer: vk::CommandBuffer, layout: vk::PipelineLayout, model: &Mat4) {
+    unsafe {
+        let bytes = std::slice::from_raw_parts(model as *const Mat4 as *const u8, std::mem::size_of::<Mat4>());
+        device.logical.cmd_push_constants(command_buffer, layout, vk::ShaderStageFlags::VERTEX, 0, bytes);
+    }
 }
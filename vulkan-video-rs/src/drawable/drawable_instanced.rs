@@ -0,0 +1,69 @@
+use anyhow::Result;
+use ash::vk;
+
+use crate::geometry::mat4::Mat4;
+use crate::geometry::vec4::Vec4;
+use crate::mesh::Mesh;
+use crate::rhi::allocator::Allocator;
+use crate::rhi::ring::UploadRing;
+use crate::DeviceBundle;
+
+use super::drawable_common::push_model;
+use super::drawable_mesh::DrawableMesh;
+
+/* Per instance vertex input of ShaderSpecialMesh, the colour multiplies the vertex colours */
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct MeshInstance {
+    pub model: Mat4,
+    pub colour: Vec4,
+}
+
+impl MeshInstance {
+    pub fn new(model: Mat4, colour: Vec4) -> Self {
+        Self { model, colour }
+    }
+}
+
+/* One mesh drawn once per instance, the instances are pushed to the upload ring every frame so moving them is free.
+ * The mesh's transform applies to every instance on top of its own model matrix. */
+pub struct InstancedMesh {
+    pub drawable: DrawableMesh,
+    pub instances: Vec<MeshInstance>,
+
+    /* Where this frame's instances are in the upload ring */
+    instance_offset: u64,
+}
+
+impl InstancedMesh {
+    pub fn new(device: &DeviceBundle, allocator: &mut Allocator, mesh: Mesh, instances: Vec<MeshInstance>) -> Self {
+        Self { drawable: DrawableMesh::new(device, allocator, mesh), instances, instance_offset: 0 }
+    }
+
+    pub fn push_instances(&mut self, ring: &mut UploadRing) -> Result<()> {
+        if !self.instances.is_empty() {
+            self.instance_offset = ring.push_slice(&self.instances)?.offset;
+        }
+
+        Ok(())
+    }
+
+    /* The instances go in binding 3, after the mesh's own vertex buffers */
+    pub fn draw(&self, device: &DeviceBundle, command_buffer: vk::CommandBuffer, layout: vk::PipelineLayout, ring: &UploadRing) {
+        if self.instances.is_empty() {
+            return;
+        }
+
+        let mesh = &self.drawable;
+        push_model(device, command_buffer, layout, &mesh.transform.matrix());
+
+        unsafe {
+            device.logical.cmd_bind_vertex_buffers(
+                command_buffer, 0,
+                &[mesh.vbo.buffer, mesh.col.buffer, mesh.normals.buffer, ring.buffer.buffer],
+                &[mesh.vbo.offset, mesh.col.offset, mesh.normals.offset, self.instance_offset]);
+            device.logical.cmd_bind_index_buffer(command_buffer, mesh.ind.buffer, mesh.ind.offset, mesh.mesh.indices.index_type());
+            device.logical.cmd_draw_indexed(command_buffer, mesh.mesh.indices.len() as u32, self.instances.len() as u32, 0, 0, 0);
+        }
+    }
+}
@@ -1,121 +1,68 @@
 use ash::vk;
 
 use crate::mesh::Rect;
-use crate::utils::image::{copy_buffer_to_image, transition_image_layout, ImageLayout_ShaderReadOnlyOptimal, ImageLayout_TransferDstOptimal, ImageLayout_Undefined};
+use crate::rhi::allocator::{Allocator, BufferType};
+use crate::rhi::core::GraphicsPSO;
+use crate::rhi::ring::UploadRing;
+use crate::rhi::debug;
+use crate::rhi::transfer::{self, UploadBatch};
+use crate::utils::image::{copy_buffer_to_image, transition_image_layout, ImageLayout_ShaderReadOnlyOptimal, ImageLayout_Undefined};
+use crate::rhi::release::Owned;
 use crate::vk_bundles::BufferBundle;
 use crate::{utils, DeviceBundle, GraphicsPipelineBundle, TextureBundle};
 use crate::primitives::texture2d::Texture2d;
+use crate::shader::ShaderTexture;
+
+use super::drawable_common::{upload_range, Drawable};
 
 
 pub struct DrawableTexture {
     pub rect: Rect,
     pub texture_data: Texture2d,
-    pub texture: TextureBundle,
-    pub vbo: BufferBundle,
-    pub ind: BufferBundle,
-    pub coords: BufferBundle,
-    pub desc_set: Vec<vk::DescriptorSet>,
+    pub texture: Owned<TextureBundle>,
+    pub vbo: Owned<BufferBundle>,
+    pub ind: Owned<BufferBundle>,
+    pub coords: Owned<BufferBundle>,
+    pub desc_set: Owned<Vec<vk::DescriptorSet>>,
 }
 
 impl DrawableTexture {
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        device: &DeviceBundle, descriptor_pool: vk::DescriptorPool, command_buffer: vk::CommandBuffer,
+        device: &DeviceBundle, allocator: &mut Allocator, descriptor_pool: vk::DescriptorPool, command_buffer: vk::CommandBuffer,
         desc_layout: vk::DescriptorSetLayout, swapchain_image_size: usize,
         rect: Rect, texture_data: Texture2d
     ) -> Self {
 
-        let texture = utils::image::create_texture_image(device, texture_data.width, texture_data.height, texture_data.size, texture_data.format);
-
-        let required_memory_flags = vk::MemoryPropertyFlags::HOST_VISIBLE;
-        let usage = vk::BufferUsageFlags::VERTEX_BUFFER;
-        let vbo = utils::buffer::create_buffer(device, rect.size_vrt() as u64, usage, required_memory_flags).expect("Failed to create vertex buffer.");
+        let texture = utils::image::create_texture_image(device, allocator, texture_data.width, texture_data.height, texture_data.format, "DrawableTexture image");
 
         //TODO: FIX THIS SILLY GOOSE
         let coord_mesh = Rect::new(0.0, 0.0, 1.0, 1.0, [1.0, 1.0, 1.0]);
 
-        let required_memory_flags = vk::MemoryPropertyFlags::HOST_VISIBLE;
-        let usage = vk::BufferUsageFlags::VERTEX_BUFFER;
-        let coords = utils::buffer::create_buffer(device, coord_mesh.size_vrt() as u64, usage, required_memory_flags).expect("Failed to create vertex buffer.");
+        let size_vrt = rect.size_vrt() as u64;
+        let size_coords = coord_mesh.size_vrt() as u64;
+        let size_ind = rect.size_ind() as u64;
 
-        let required_memory_flags = vk::MemoryPropertyFlags::HOST_VISIBLE;
-        let usage = vk::BufferUsageFlags::INDEX_BUFFER;
-        let ind = utils::buffer::create_buffer(device, coord_mesh.size_vrt() as u64, usage, required_memory_flags).expect("Failed to create vertex buffer.");
+        let vbo = allocator.alloc(device, BufferType::DeviceVertex, size_vrt, "DrawableTexture vertices").expect("Failed to allocate vertex buffer.");
+        let coords = allocator.alloc(device, BufferType::DeviceVertex, size_coords, "DrawableTexture texture coordinates").expect("Failed to allocate vertex buffer.");
+        let ind = allocator.alloc(device, BufferType::DeviceIndex, size_ind, "DrawableTexture indices").expect("Failed to allocate index buffer.");
 
         let desc_set = Self::create_descriptor_sets(device, descriptor_pool, desc_layout, &texture, swapchain_image_size);
-        transition_image_layout::<ImageLayout_Undefined, ImageLayout_ShaderReadOnlyOptimal>(device, command_buffer, &texture);
-        DrawableTexture { rect, texture_data, texture, vbo, coords, ind, desc_set }
-    }
-
-    pub fn dirty(&self) -> bool {
-        return self.rect.dirty_colour || self.rect.dirty_indices || self.rect.dirty_vertices || self.texture_data.dirty;
-    }
-
-    pub fn update(device: &DeviceBundle, command_buffer: vk::CommandBuffer, entities: &mut Vec<Self>) -> bool {
-        let mut recorded = false;
-
-        for entity in entities.iter_mut() {
-            if !entity.dirty() {
-                continue;
-            }
-
-            recorded = true;
 
-            let size_vrt = entity.rect.size_vrt() as u64;
-            let size_ind = entity.rect.size_ind() as u64;
-            let texture_size = entity.texture_data.size;
+        debug::name_object(device, texture.resource.image, "DrawableTexture image");
+        debug::name_object(device, texture.image_view, "DrawableTexture image view");
+        debug::name_object(device, texture.sampler, "DrawableTexture sampler");
 
-            unsafe {
-                if entity.rect.dirty_vertices {
-                    let data_ptr = device.logical.map_memory(entity.vbo.memory, 0, size_vrt, vk::MemoryMapFlags::empty()).unwrap() as *mut [f32; 2];
-                    data_ptr.copy_from_nonoverlapping(entity.rect.vertices.as_ptr(), entity.rect.vertices.len());
-                    device.logical.unmap_memory(entity.vbo.memory);
-
-                    let coord_mesh = Rect::new(0.0, 0.0, 1.0, 1.0, [1.0, 1.0, 1.0]);
-                    let data_ptr = device.logical.map_memory(entity.coords.memory, 0, coord_mesh.size_vrt() as u64, vk::MemoryMapFlags::empty()).unwrap() as *mut [f32; 2];
-                    data_ptr.copy_from_nonoverlapping(coord_mesh.vertices.as_ptr(), entity.rect.vertices.len());
-                    device.logical.unmap_memory(entity.coords.memory);
-                }
-
-                if entity.rect.dirty_indices {
-                    let data_ptr = device.logical.map_memory(entity.ind.memory, 0, size_ind, vk::MemoryMapFlags::empty()).unwrap() as *mut u16;
-                    data_ptr.copy_from_nonoverlapping(entity.rect.indices.as_ptr(), entity.rect.indices.len());
-                    device.logical.unmap_memory(entity.ind.memory);
-                }
-
-                if entity.texture_data.dirty {
-                    let data_ptr = device.logical.map_memory(entity.texture.staging.memory, 0, texture_size, vk::MemoryMapFlags::empty()).unwrap() as *mut u8;
-                    data_ptr.copy_from_nonoverlapping(entity.texture_data.data.as_ptr(), texture_size as usize);
-                    device.logical.unmap_memory(entity.texture.staging.memory);
-
-                    transition_image_layout::<ImageLayout_ShaderReadOnlyOptimal, ImageLayout_TransferDstOptimal>(device, command_buffer, &entity.texture);
-                    copy_buffer_to_image(device, command_buffer, &entity.texture, entity.texture.staging.buffer, entity.texture_data.width, entity.texture_data.height);
-                    transition_image_layout::<ImageLayout_TransferDstOptimal, ImageLayout_ShaderReadOnlyOptimal>(device, command_buffer, &entity.texture);
-                }
-            }
-
-            entity.rect.dirty_colour = false;
-            entity.rect.dirty_vertices = false;
-            entity.rect.dirty_indices = false;
+        for set in desc_set.iter() {
+            debug::name_object(device, *set, "DrawableTexture descriptor set");
         }
 
-        return recorded;
-    }
-
-    pub fn draw(device: &DeviceBundle, command_buffer: vk::CommandBuffer, graphics_pipeline: &GraphicsPipelineBundle, current_swap_image: usize, entities: &[Self])  {
-        unsafe {
-            device.logical.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, graphics_pipeline.graphics);
-            for i in 0..entities.len() {
-                device.logical.cmd_bind_vertex_buffers(command_buffer, 0, &[entities[i].vbo.buffer, entities[i].coords.buffer], &[0, 0]);
-                device.logical.cmd_bind_index_buffer(command_buffer, entities[i].ind.buffer, 0, vk::IndexType::UINT16);
-
-                device.logical.cmd_bind_descriptor_sets(
-                    command_buffer, vk::PipelineBindPoint::GRAPHICS, graphics_pipeline.layout, 0,
-                    &entities[i].desc_set[current_swap_image..current_swap_image+1], &[]);
+        transition_image_layout::<ImageLayout_Undefined, ImageLayout_ShaderReadOnlyOptimal>(device, command_buffer, &texture);
 
-                device.logical.cmd_draw_indexed(command_buffer, entities[i].rect.indices.len() as u32, 1, 0, 0, 0);
-            }
-        }
+        let texture = allocator.own(texture);
+        let desc_set = allocator.own(desc_set);
+        DrawableTexture { rect, texture_data, texture, vbo, coords, ind, desc_set }
     }
 
     fn create_descriptor_sets(
@@ -161,25 +108,66 @@ impl DrawableTexture {
         descriptor_sets
 
     }
+}
 
-    pub fn release(device: &DeviceBundle, textures: &mut [Self])
-    {
-        unsafe
-        {
-            for texture in textures.iter() {
-                device.logical.destroy_buffer(texture.vbo.buffer, None);
-                device.logical.free_memory(texture.vbo.memory, None);
-                device.logical.destroy_buffer(texture.texture.staging.buffer, None);
-                device.logical.free_memory(texture.texture.staging.memory, None);
-                device.logical.destroy_buffer(texture.coords.buffer, None);
-                device.logical.free_memory(texture.coords.memory, None);
-                device.logical.destroy_buffer(texture.ind.buffer, None);
-                device.logical.free_memory(texture.ind.memory, None);
-                device.logical.destroy_image(texture.texture.resource.image, None);
-                device.logical.free_memory(texture.texture.resource.memory, None);
-                device.logical.destroy_image_view(texture.texture.image_view, None);
-                device.logical.destroy_sampler(texture.texture.sampler, None);
-            }
+impl Drawable for DrawableTexture {
+    fn name(&self) -> &'static str {
+        "DrawableTexture"
+    }
+
+    fn dirty(&self) -> bool {
+        return self.rect.dirty_colour || self.rect.dirty_indices || self.rect.dirty_vertices || self.texture_data.dirty;
+    }
+
+    fn upload(&mut self, device: &DeviceBundle, batch: &mut UploadBatch, ring: &mut UploadRing) {
+        let rect = &self.rect;
+
+        if rect.dirty_vertices {
+            upload_range(device, batch, ring, &rect.vertices, &self.vbo,
+                         vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ);
+
+            let coord_mesh = Rect::new(0.0, 0.0, 1.0, 1.0, [1.0, 1.0, 1.0]);
+            upload_range(device, batch, ring, &coord_mesh.vertices, &self.coords,
+                         vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ);
+        }
+
+        if rect.dirty_indices {
+            upload_range(device, batch, ring, &rect.indices, &self.ind,
+                         vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::INDEX_READ);
+        }
+
+        if self.texture_data.dirty {
+            let texture_size = self.texture_data.size as usize;
+            let staging = ring.stage_slice(device, &self.texture_data.data[..texture_size]).expect("Failed to stage the texture.");
+
+            transfer::record_image_write(device, batch.cb, self.texture.resource.image, self.texture.aspect_flags);
+            copy_buffer_to_image(device, batch.cb, &self.texture, &staging, self.texture_data.width, self.texture_data.height);
+
+            batch.release_image(self.texture.resource.image, self.texture.aspect_flags,
+                                vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                                vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ);
+        }
+
+        self.rect.dirty_colour = false;
+        self.rect.dirty_vertices = false;
+        self.rect.dirty_indices = false;
+        self.texture_data.dirty = false;
+    }
+
+    fn pipeline_state(&self) -> GraphicsPSO {
+        GraphicsPSO::new(ShaderTexture::ID)
+    }
+
+    fn draw(&self, device: &DeviceBundle, command_buffer: vk::CommandBuffer, graphics_pipeline: &GraphicsPipelineBundle, current_image: usize) {
+        unsafe {
+            device.logical.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vbo.buffer, self.coords.buffer], &[self.vbo.offset, self.coords.offset]);
+            device.logical.cmd_bind_index_buffer(command_buffer, self.ind.buffer, self.ind.offset, vk::IndexType::UINT16);
+
+            device.logical.cmd_bind_descriptor_sets(
+                command_buffer, vk::PipelineBindPoint::GRAPHICS, graphics_pipeline.layout, 0,
+                &self.desc_set[current_image..current_image+1], &[]);
+
+            device.logical.cmd_draw_indexed(command_buffer, self.rect.indices.len() as u32, 1, 0, 0, 0);
         }
     }
 }
@@ -2,3 +2,5 @@ pub mod drawable2d;
 pub mod drawable_tex;
 pub mod drawable_common;
 pub mod drawable_mesh;
+pub mod drawable_instanced;
+pub mod registry;
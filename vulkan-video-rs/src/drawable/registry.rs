@@ -0,0 +1,98 @@
+use std::any::Any;
+use std::cell::Cell;
+
+use ash::vk;
+
+use crate::rhi::debug;
+use crate::rhi::ring::UploadRing;
+use crate::rhi::transfer::UploadBatch;
+use crate::scene::layout::{Pane, PaneContent};
+use crate::vk_base::VkBase;
+use crate::DeviceBundle;
+
+use super::drawable_common::Drawable;
+
+/* Stays valid for the lifetime of the registry, drawables are never removed one by one */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DrawableId(usize);
+
+struct DrawableEntry {
+    content: PaneContent,
+    drawable: Box<dyn Drawable>,
+
+    /* Render value of the last frame that drew it, its uploads wait for that frame */
+    read_value: Cell<u64>,
+}
+
+/* Drawables of any kind, grouped by the pane content they are drawn for */
+#[derive(Default)]
+pub struct DrawableRegistry {
+    entries: Vec<DrawableEntry>,
+}
+
+impl DrawableRegistry {
+    pub fn add(&mut self, content: PaneContent, drawable: impl Drawable) -> DrawableId {
+        self.entries.push(DrawableEntry { content, drawable: Box::new(drawable), read_value: Cell::new(0) });
+        DrawableId(self.entries.len() - 1)
+    }
+
+    /* None when the drawable is of another kind */
+    pub fn get_mut<T: Drawable>(&mut self, id: DrawableId) -> Option<&mut T> {
+        let drawable: &mut dyn Any = self.entries.get_mut(id.0)?.drawable.as_mut();
+        drawable.downcast_mut::<T>()
+    }
+
+    pub fn iter_mut<T: Drawable>(&mut self) -> impl Iterator<Item = &mut T> {
+        self.entries.iter_mut().filter_map(|entry| {
+            let drawable: &mut dyn Any = entry.drawable.as_mut();
+            drawable.downcast_mut::<T>()
+        })
+    }
+
+    /* Records the uploads of every dirty drawable, returns whether there were any */
+    pub fn upload(&mut self, device: &DeviceBundle, batch: &mut UploadBatch, ring: &mut UploadRing) -> bool {
+        let mut recorded = false;
+
+        for entry in self.entries.iter_mut().filter(|entry| entry.drawable.dirty()) {
+            debug::begin_label(device, batch.cb, entry.drawable.name(), debug::UPLOAD_COLOUR);
+            entry.drawable.upload(device, batch, ring);
+            debug::end_label(device, batch.cb);
+
+            batch.read_by(entry.read_value.get());
+            recorded = true;
+        }
+
+        recorded
+    }
+
+    /*
+     * Draws everything for content, binding each drawable's pipeline when it differs from the last one.
+     * Pipelines with global uniforms see the pane's camera.
+     */
+    pub fn draw(&self, content: PaneContent, pane: &Pane, base: &mut VkBase, command_buffer: vk::CommandBuffer, current_image: usize) {
+        let mut bound = None;
+
+        for entry in self.entries.iter().filter(|entry| entry.content == content) {
+            let state = entry.drawable.pipeline_state();
+            base.request_pipeline(state);
+
+            let device = &base.device;
+            let pipeline = base.pipeline(&state);
+
+            if bound != Some(state) {
+                unsafe { device.logical.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.graphics); }
+                bound = Some(state);
+
+                if pipeline.global_uniforms {
+                    let camera = [pane.descriptor_sets[current_image]];
+                    unsafe { device.logical.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.layout, 0, &camera, &[pane.camera_offset]); }
+                }
+            }
+
+            entry.drawable.draw(device, command_buffer, pipeline, current_image);
+
+            // Recorded for the frame VkBase::render submits next
+            entry.read_value.set(base.render_value + 1);
+        }
+    }
+}
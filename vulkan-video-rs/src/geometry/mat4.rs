@@ -1,5 +1,10 @@
+use std::ops;
+
+use super::vec3::Vec3;
 use super::vec4::Vec4;
 
+/* Column major like GLSL, x, y, z and w are the columns */
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub struct Mat4 {
     pub x: Vec4,
@@ -14,4 +19,319 @@ impl Mat4 {
     pub const fn new(x: Vec4, y: Vec4, z: Vec4, w: Vec4) -> Self {
         Self { x, y, z, w }
     }
+
+    /* For writing matrices down the way they are printed */
+    pub const fn from_rows(a: Vec4, b: Vec4, c: Vec4, d: Vec4) -> Self {
+        Self::new(
+            Vec4::new(a.x, b.x, c.x, d.x),
+            Vec4::new(a.y, b.y, c.y, d.y),
+            Vec4::new(a.z, b.z, c.z, d.z),
+            Vec4::new(a.w, b.w, c.w, d.w),
+        )
+    }
+
+    pub const fn translation(t: Vec3) -> Self {
+        Self::new(
+            Vec4::new(1.0, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 1.0, 0.0),
+            Vec4::new(t.x, t.y, t.z, 1.0),
+        )
+    }
+
+    pub const fn scale(s: Vec3) -> Self {
+        Self::new(
+            Vec4::new(s.x, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, s.y, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, s.z, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    /*
+     * Right handed, counter clockwise when looking down the axis towards the origin like Quat::from_axis_angle.
+     * Mesh::rotate_x, rotate_y and rotate_z turn the same way.
+     */
+    pub fn rotation_x(theta: f32) -> Self {
+        let (s, c) = theta.sin_cos();
+        Self::new(
+            Vec4::new(1.0, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, c, s, 0.0),
+            Vec4::new(0.0, -s, c, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    pub fn rotation_y(theta: f32) -> Self {
+        let (s, c) = theta.sin_cos();
+        Self::new(
+            Vec4::new(c, 0.0, -s, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(s, 0.0, c, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    pub fn rotation_z(theta: f32) -> Self {
+        let (s, c) = theta.sin_cos();
+        Self::new(
+            Vec4::new(c, s, 0.0, 0.0),
+            Vec4::new(-s, c, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 1.0, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    /*
+     * Right handed view matrix, the camera looks down -z with y up.
+     * The rows are the camera's basis, so the dot products project a point onto it, after moving the camera to the origin.
+     */
+    pub fn look_at(location: Vec3, direction: Vec3, up: Vec3) -> Self {
+        let f = Vec3::norm(&direction);
+        let r = Vec3::norm(&Vec3::cross(f, up));
+        let u = Vec3::cross(r, f);
+
+        Self::from_rows(
+            Vec4::new( r.x,  r.y,  r.z, -Vec3::dot(r, location)),
+            Vec4::new( u.x,  u.y,  u.z, -Vec3::dot(u, location)),
+            Vec4::new(-f.x, -f.y, -f.z,  Vec3::dot(f, location)),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    /*
+     * Perspective projection for Vulkan's clip space, y points down and depth goes from 0 at near to 1 at far.
+     * The z row solves (A*z + B) / -z = 0 at z = -near and 1 at z = -far.
+     */
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let c = 1.0 / (fov_y / 2.0).tan();
+        let a = far / (near - far);
+        let b = near * far / (near - far);
+
+        Self::from_rows(
+            Vec4::new(c / aspect, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, -c, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, a, b),
+            Vec4::new(0.0, 0.0, -1.0, 0.0),
+        )
+    }
+
+    /* Same clip space as perspective, the box maps onto x and y in [-1, 1] and depth in [0, 1] */
+    pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        Self::from_rows(
+            Vec4::new(2.0 / (right - left), 0.0, 0.0, -(right + left) / (right - left)),
+            Vec4::new(0.0, -2.0 / (top - bottom), 0.0, (top + bottom) / (top - bottom)),
+            Vec4::new(0.0, 0.0, -1.0 / (far - near), -near / (far - near)),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self::from_rows(self.x, self.y, self.z, self.w)
+    }
+
+    /* Gauss-Jordan elimination with partial pivoting, None when the matrix is singular */
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.columns();
+        let mut inv = Self::IDENT.columns();
+
+        // Rows are swapped and combined by doing it to each column
+        for pivot_row in 0..4 {
+            let best = (pivot_row..4).max_by(|&i, &j| a[pivot_row][i].abs().total_cmp(&a[pivot_row][j].abs()))?;
+            if a[pivot_row][best].abs() < 1e-12 {
+                return None;
+            }
+
+            for column in 0..4 {
+                a[column].swap(pivot_row, best);
+                inv[column].swap(pivot_row, best);
+            }
+
+            let pivot = a[pivot_row][pivot_row];
+            for column in 0..4 {
+                a[column][pivot_row] /= pivot;
+                inv[column][pivot_row] /= pivot;
+            }
+
+            for row in (0..4).filter(|&row| row != pivot_row) {
+                let factor = a[pivot_row][row];
+                for column in 0..4 {
+                    a[column][row] -= factor * a[column][pivot_row];
+                    inv[column][row] -= factor * inv[column][pivot_row];
+                }
+            }
+        }
+
+        Some(Self::from_columns(inv))
+    }
+
+    pub fn transform(&self, v: Vec4) -> Vec4 {
+        Vec4::new(
+            self.x.x * v.x + self.y.x * v.y + self.z.x * v.z + self.w.x * v.w,
+            self.x.y * v.x + self.y.y * v.y + self.z.y * v.z + self.w.y * v.w,
+            self.x.z * v.x + self.y.z * v.y + self.z.z * v.z + self.w.z * v.w,
+            self.x.w * v.x + self.y.w * v.y + self.z.w * v.z + self.w.w * v.w,
+        )
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        self.transform(Vec4::from_vec3(p, 1.0)).xyz()
+    }
+
+    fn columns(&self) -> [[f32; 4]; 4] {
+        [self.x, self.y, self.z, self.w].map(|c| [c.x, c.y, c.z, c.w])
+    }
+
+    fn from_columns(c: [[f32; 4]; 4]) -> Self {
+        let [x, y, z, w] = c.map(|c| Vec4::new(c[0], c[1], c[2], c[3]));
+        Self::new(x, y, z, w)
+    }
+}
+
+#[cfg(test)]
+impl Mat4 {
+    pub fn approx_eq(&self, other: &Mat4, epsilon: f32) -> bool {
+        self.x.approx_eq(&other.x, epsilon) && self.y.approx_eq(&other.y, epsilon)
+            && self.z.approx_eq(&other.z, epsilon) && self.w.approx_eq(&other.w, epsilon)
+    }
+}
+
+/* a * b applies b first */
+impl ops::Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Self::Output {
+        Self::new(self.transform(rhs.x), self.transform(rhs.y), self.transform(rhs.z), self.transform(rhs.w))
+    }
+}
+
+impl ops::Mul<Vec4> for Mat4 {
+    type Output = Vec4;
+
+    fn mul(self, rhs: Vec4) -> Self::Output {
+        self.transform(rhs)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use proptest::prelude::*;
+
+    use crate::geometry::vec3::Vec3;
+    use crate::geometry::vec4::Vec4;
+    use crate::mesh::prism;
+
+    use super::Mat4;
+
+    pub fn vec3(range: f32) -> impl Strategy<Value = Vec3> {
+        (-range..range, -range..range, -range..range).prop_map(|(x, y, z)| Vec3::new(x, y, z))
+    }
+
+    /* Scale, rotate, then translate, with scales far enough from 0 to invert */
+    pub fn transform() -> impl Strategy<Value = Mat4> {
+        (vec3(10.0), vec3(3.0), vec3(2.0), prop::array::uniform3(prop::bool::ANY)).prop_map(|(t, r, s, flip)| {
+            let s = Vec3::new(
+                if flip[0] { -0.5 - s.x.abs() } else { 0.5 + s.x.abs() },
+                if flip[1] { -0.5 - s.y.abs() } else { 0.5 + s.y.abs() },
+                if flip[2] { -0.5 - s.z.abs() } else { 0.5 + s.z.abs() },
+            );
+            Mat4::translation(t) * Mat4::rotation_z(r.z) * Mat4::rotation_y(r.y) * Mat4::rotation_x(r.x) * Mat4::scale(s)
+        })
+    }
+
+    fn mat4() -> impl Strategy<Value = Mat4> {
+        prop::array::uniform16(-10.0f32..10.0).prop_map(|m| Mat4::from_rows(
+            Vec4::new(m[0], m[1], m[2], m[3]),
+            Vec4::new(m[4], m[5], m[6], m[7]),
+            Vec4::new(m[8], m[9], m[10], m[11]),
+            Vec4::new(m[12], m[13], m[14], m[15]),
+        ))
+    }
+
+    #[test]
+    fn test_matches_mesh_transforms() {
+        let mut mesh = prism::make_prism(Vec3::ZERO, Vec3::new(1.0, 2.0, 3.0), Vec3::of(1.0));
+        let original = mesh.vertices.clone();
+
+        mesh.rotate_x(0.3);
+        mesh.rotate_y(0.5);
+        mesh.rotate_z(0.7);
+        mesh.translate(Vec3::new(1.0, -2.0, 3.0));
+
+        let model = Mat4::translation(Vec3::new(1.0, -2.0, 3.0)) * Mat4::rotation_z(0.7) * Mat4::rotation_y(0.5) * Mat4::rotation_x(0.3);
+
+        for (vertex, expected) in original.iter().zip(mesh.vertices.iter()) {
+            let transformed = model.transform_point(*vertex);
+            assert!((transformed - *expected).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_singular() {
+        assert!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn test_perspective_depth() {
+        let proj = Mat4::perspective(90_f32.to_radians(), 2.0, 0.1, 30.0);
+
+        let near = proj * Vec4::new(0.0, 0.0, -0.1, 1.0);
+        let far = proj * Vec4::new(0.0, 0.0, -30.0, 1.0);
+        assert!((near.z / near.w).abs() < 1e-6);
+        assert!((far.z / far.w - 1.0).abs() < 1e-6);
+
+        // The edge of a 90 degree frustum is at x = -z * aspect, and y = -z is the top of the screen
+        let corner = proj * Vec4::new(2.0, 1.0, -1.0, 1.0);
+        assert!(Vec4::new(corner.x / corner.w, corner.y / corner.w, 0.0, 0.0).approx_eq(&Vec4::new(1.0, -1.0, 0.0, 0.0), 1e-6));
+    }
+
+    #[test]
+    fn test_orthographic_box() {
+        let proj = Mat4::orthographic(-2.0, 4.0, -1.0, 3.0, 0.5, 10.0);
+
+        assert!((proj * Vec4::new(-2.0, 3.0, -0.5, 1.0)).approx_eq(&Vec4::new(-1.0, -1.0, 0.0, 1.0), 1e-6));
+        assert!((proj * Vec4::new(4.0, -1.0, -10.0, 1.0)).approx_eq(&Vec4::new(1.0, 1.0, 1.0, 1.0), 1e-6));
+    }
+
+    proptest! {
+        #[test]
+        fn test_identity(m in mat4()) {
+            prop_assert_eq!(Mat4::IDENT * m, m);
+            prop_assert_eq!(m * Mat4::IDENT, m);
+        }
+
+        #[test]
+        fn test_transpose(a in mat4(), b in mat4()) {
+            prop_assert_eq!(a.transpose().transpose(), a);
+            prop_assert!((a * b).transpose().approx_eq(&(b.transpose() * a.transpose()), 1e-3));
+        }
+
+        #[test]
+        fn test_mul_is_associative(a in transform(), b in transform(), c in transform()) {
+            let scale = 1e-4 * (a * b * c).w.length().max(1.0);
+            prop_assert!(((a * b) * c).approx_eq(&(a * (b * c)), scale));
+        }
+
+        #[test]
+        fn test_inverse(m in transform()) {
+            let inverse = m.inverse().unwrap();
+            prop_assert!((m * inverse).approx_eq(&Mat4::IDENT, 1e-4));
+            prop_assert!((inverse * m).approx_eq(&Mat4::IDENT, 1e-4));
+        }
+
+        #[test]
+        fn test_look_at(location in vec3(20.0), direction in vec3(1.0), distance in 0.1f32..10.0) {
+            prop_assume!(direction.length() > 0.1 && Vec3::cross(Vec3::norm(&direction), Vec3::Y).length() > 0.1);
+
+            let view = Mat4::look_at(location, direction, Vec3::Y);
+            prop_assert!(view.transform_point(location).length() < 1e-4);
+
+            // Straight ahead ends up on -z at the same distance
+            let ahead = view.transform_point(location + Vec3::norm(&direction) * distance);
+            prop_assert!((ahead - Vec3::new(0.0, 0.0, -distance)).length() < 1e-3);
+
+            // Up stays up, rotated towards the view direction
+            prop_assert!(view.transform_point(location + Vec3::Y).y > 0.0);
+        }
+    }
 }
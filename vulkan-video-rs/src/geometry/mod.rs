@@ -1,3 +1,5 @@
 pub mod vec3;
 pub mod vec4;
 pub mod mat4;
+pub mod quat;
+pub mod transform;
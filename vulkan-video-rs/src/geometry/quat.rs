@@ -0,0 +1,144 @@
+use std::ops;
+
+use super::mat4::Mat4;
+use super::vec3::Vec3;
+use super::vec4::Vec4;
+
+/* A rotation, w is the scalar part */
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    pub const IDENT: Self = Self::new(0.0, 0.0, 0.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self { x, y, z, w }
+    }
+
+    /* Counter clockwise when looking down the axis towards the origin */
+    pub fn from_axis_angle(axis: Vec3, theta: f32) -> Self {
+        let axis = Vec3::norm(&axis);
+        let (s, c) = (theta / 2.0).sin_cos();
+        Self::new(axis.x * s, axis.y * s, axis.z * s, c)
+    }
+
+    /*
+     * Angles in radians around x (pitch), y (yaw) and z (roll), applied as roll, then pitch, then yaw.
+     * That keeps yaw around the world's up axis, like turning a camera.
+     */
+    pub fn from_euler(angles: Vec3) -> Self {
+        Self::from_axis_angle(Vec3::Y, angles.y) * Self::from_axis_angle(Vec3::X, angles.x) * Self::from_axis_angle(Vec3::Z, angles.z)
+    }
+
+    pub fn dot(a: Quat, b: Quat) -> f32 {
+        a.x*b.x + a.y*b.y + a.z*b.z + a.w*b.w
+    }
+
+    pub fn length(&self) -> f32 {
+        Quat::dot(*self, *self).sqrt()
+    }
+
+    pub fn normalised(&self) -> Self {
+        let l = self.length();
+        let l = if l > 1e-6 { l } else { 1.0 };
+        Self::new(self.x / l, self.y / l, self.z / l, self.w / l)
+    }
+
+    /* The inverse rotation for unit quaternions */
+    pub fn conjugate(&self) -> Self {
+        Self::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        let p = *self * Quat::new(v.x, v.y, v.z, 0.0) * self.conjugate();
+        Vec3::new(p.x, p.y, p.z)
+    }
+
+    pub fn to_mat4(self) -> Mat4 {
+        let Self { x, y, z, w } = self.normalised();
+
+        Mat4::new(
+            Vec4::new(1.0 - 2.0*(y*y + z*z), 2.0*(x*y + w*z), 2.0*(x*z - w*y), 0.0),
+            Vec4::new(2.0*(x*y - w*z), 1.0 - 2.0*(x*x + z*z), 2.0*(y*z + w*x), 0.0),
+            Vec4::new(2.0*(x*z + w*y), 2.0*(y*z - w*x), 1.0 - 2.0*(x*x + y*y), 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+}
+
+/* a * b rotates by b first */
+impl ops::Mul<Quat> for Quat {
+    type Output = Quat;
+
+    fn mul(self, rhs: Quat) -> Self::Output {
+        Self::new(
+            self.w*rhs.x + self.x*rhs.w + self.y*rhs.z - self.z*rhs.y,
+            self.w*rhs.y - self.x*rhs.z + self.y*rhs.w + self.z*rhs.x,
+            self.w*rhs.z + self.x*rhs.y - self.y*rhs.x + self.z*rhs.w,
+            self.w*rhs.w - self.x*rhs.x - self.y*rhs.y - self.z*rhs.z,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::geometry::mat4::tests::vec3;
+    use crate::geometry::mat4::Mat4;
+    use crate::geometry::vec3::Vec3;
+
+    use super::Quat;
+
+    fn quat() -> impl Strategy<Value = Quat> {
+        (vec3(1.0), -10.0f32..10.0)
+            .prop_filter("axis too short", |(axis, _)| axis.length() > 0.1)
+            .prop_map(|(axis, theta)| Quat::from_axis_angle(axis, theta))
+    }
+
+    fn assert_same_rotation(a: &Mat4, b: &Mat4) {
+        for p in [Vec3::X, Vec3::Y, Vec3::Z, Vec3::new(1.0, -2.0, 3.0)] {
+            assert!((a.transform_point(p) - b.transform_point(p)).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_matches_mat4_rotations() {
+        assert_same_rotation(&Quat::from_axis_angle(Vec3::Z, 0.7).to_mat4(), &Mat4::rotation_z(0.7));
+        assert_same_rotation(&Quat::from_axis_angle(Vec3::Y, 0.7).to_mat4(), &Mat4::rotation_y(0.7));
+        assert_same_rotation(&Quat::from_axis_angle(Vec3::X, 0.7).to_mat4(), &Mat4::rotation_x(0.7));
+
+        let q = Quat::from_axis_angle(Vec3::Z, 0.4) * Quat::from_axis_angle(Vec3::X, 0.3);
+        assert_same_rotation(&q.to_mat4(), &(Mat4::rotation_z(0.4) * Mat4::rotation_x(0.3)));
+    }
+
+    proptest! {
+        #[test]
+        fn test_mul_matches_mat4(a in quat(), b in quat()) {
+            prop_assert!((a * b).to_mat4().approx_eq(&(a.to_mat4() * b.to_mat4()), 1e-4));
+        }
+
+        #[test]
+        fn test_rotate_matches_mat4(q in quat(), v in vec3(10.0)) {
+            prop_assert!((q.rotate(v) - q.to_mat4().transform_point(v)).length() < 1e-3);
+            prop_assert!((q.rotate(v).length() - v.length()).abs() < 1e-3);
+        }
+
+        #[test]
+        fn test_conjugate_undoes(q in quat(), v in vec3(10.0)) {
+            prop_assert!((q.conjugate().rotate(q.rotate(v)) - v).length() < 1e-3);
+        }
+
+        #[test]
+        fn test_euler_order(pitch in -3.1f32..3.1, yaw in -3.1f32..3.1, roll in -3.1f32..3.1) {
+            let expected = Mat4::rotation_y(yaw) * Mat4::rotation_x(pitch) * Mat4::rotation_z(roll);
+            prop_assert!(Quat::from_euler(Vec3::new(pitch, yaw, roll)).to_mat4().approx_eq(&expected, 1e-4));
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use super::mat4::Mat4;
+use super::quat::Quat;
+use super::vec3::Vec3;
+
+/* Where a mesh is placed, applied on the GPU as scale, then rotation, then position */
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const IDENT: Self = Self { position: Vec3::ZERO, rotation: Quat::IDENT, scale: Vec3::of(1.0) };
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translation(self.position) * self.rotation.to_mat4() * Mat4::scale(self.scale)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENT
+    }
+}
@@ -1,6 +1,6 @@
 use std::{ops, fmt};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C, align(16))]
 pub struct Vec3 {
     pub x: f32,
@@ -37,6 +37,10 @@ impl Vec3 {
         }
     }
 
+    pub fn dot(a: Vec3, b: Vec3) -> f32 {
+        a.x*b.x + a.y*b.y + a.z*b.z
+    }
+
     pub fn length(&self) -> f32 {
         (self.x*self.x + self.y*self.y + self.z*self.z).sqrt()
     }
@@ -1,4 +1,8 @@
-#[allow(unused)]
+use std::{ops, fmt};
+
+use super::vec3::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub struct Vec4 {
     pub x: f32,
@@ -7,11 +11,8 @@ pub struct Vec4 {
     pub w: f32
 }
 
-#[allow(unused)]
 impl Vec4 {
-    pub const ZERO: Self = Self::of(0.0);
-
-    pub const X: Self = Self::new(0.0, 0.0, 0.0, 0.0);
+    pub const X: Self = Self::new(1.0, 0.0, 0.0, 0.0);
     pub const Y: Self = Self::new(0.0, 1.0, 0.0, 0.0);
     pub const Z: Self = Self::new(0.0, 0.0, 1.0, 0.0);
     pub const W: Self = Self::new(0.0, 0.0, 0.0, 1.0);
@@ -20,7 +21,100 @@ impl Vec4 {
         Self { x, y, z, w }
     }
 
+    /* w is 1 for points and 0 for directions */
+    pub const fn from_vec3(v: Vec3, w: f32) -> Self {
+        Self { x: v.x, y: v.y, z: v.z, w }
+    }
+
+    pub const fn xyz(&self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+}
+
+/* Only the tests compare and measure them */
+#[cfg(test)]
+impl Vec4 {
     pub const fn of(v: f32) -> Self {
         Self { x: v, y: v, z: v, w: v }
     }
+
+    pub fn dot(a: Vec4, b: Vec4) -> f32 {
+        a.x*b.x + a.y*b.y + a.z*b.z + a.w*b.w
+    }
+
+    pub fn length(&self) -> f32 {
+        Vec4::dot(*self, *self).sqrt()
+    }
+
+    pub fn approx_eq(&self, other: &Vec4, epsilon: f32) -> bool {
+        (self.x - other.x).abs() <= epsilon && (self.y - other.y).abs() <= epsilon
+            && (self.z - other.z).abs() <= epsilon && (self.w - other.w).abs() <= epsilon
+    }
+}
+
+impl ops::Add<Vec4> for Vec4 {
+    type Output = Vec4;
+
+    fn add(self, rhs: Vec4) -> Self::Output {
+        Self::Output { x: self.x + rhs.x, y: self.y + rhs.y, z: self.z + rhs.z, w: self.w + rhs.w }
+    }
+}
+
+impl ops::Sub<Vec4> for Vec4 {
+    type Output = Vec4;
+
+    fn sub(self, rhs: Vec4) -> Self::Output {
+        Self::Output { x: self.x - rhs.x, y: self.y - rhs.y, z: self.z - rhs.z, w: self.w - rhs.w }
+    }
+}
+
+impl ops::Mul<f32> for Vec4 {
+    type Output = Vec4;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self::Output { x: self.x * rhs, y: self.y * rhs, z: self.z * rhs, w: self.w * rhs }
+    }
+}
+
+impl ops::Neg for Vec4 {
+    type Output = Vec4;
+
+    fn neg(self) -> Self::Output {
+        self * -1.0
+    }
+}
+
+impl fmt::Display for Vec4 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({:>6.2}, {:>6.2}, {:>6.2}, {:>6.2})", self.x, self.y, self.z, self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::Vec4;
+
+    fn vec4() -> impl Strategy<Value = Vec4> {
+        (-100.0f32..100.0, -100.0f32..100.0, -100.0f32..100.0, -100.0f32..100.0).prop_map(|(x, y, z, w)| Vec4::new(x, y, z, w))
+    }
+
+    #[test]
+    fn test_axes() {
+        assert_eq!(Vec4::X + Vec4::Y + Vec4::Z + Vec4::W, Vec4::of(1.0));
+        assert_eq!(Vec4::dot(Vec4::X, Vec4::X), 1.0);
+    }
+
+    proptest! {
+        #[test]
+        fn test_sub_inverts_add(a in vec4(), b in vec4()) {
+            prop_assert!(((a + b) - b).approx_eq(&a, 1e-3));
+        }
+
+        #[test]
+        fn test_dot_is_squared_length(a in vec4()) {
+            prop_assert!((Vec4::dot(a, a) - a.length() * a.length()).abs() <= 1e-2 * Vec4::dot(a, a).max(1.0));
+        }
+    }
 }
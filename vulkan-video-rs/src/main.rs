@@ -11,26 +11,39 @@ mod scene_extensions;
 mod geometry;
 mod rhi;
 mod scene;
+mod options;
 
+use std::f32::consts::PI;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
 use devices::record_player::RecordPlayer;
 use drawable::{drawable_mesh::DrawableMesh, drawable_tex::DrawableTexture, drawable2d::Drawable2d};
+use drawable::registry::{DrawableId, DrawableRegistry};
+use geometry::quat::Quat;
 use geometry::vec3::Vec3;
-use mesh::{ Rect, cube};
+use options::Options;
+use mesh::{ Rect, axes, cube};
+use mesh::depth_mesher::DepthMesher;
 use primitives::texture2d::{PixelFormat, Texture2d};
-use scene::camera::{Camera, CameraParams, CameraAction};
+use scene::camera::{Camera, CameraAction};
+use scene::layout::{LayoutKind, Pane, PaneContent, ViewportLayout};
 use scene_extensions::simple_scene::SimpleScene;
 use utils::{image::{begin_single_time_command, end_single_time_command}, keyboard::KeyboardState};
 use vk_bundles::*;
-use rhi::allocator::{Allocator, AllocatorSizeInfo, BufferType};
+use rhi::allocator::AllocatorSizeInfo;
+use rhi::core::{BlendMode, GraphicsPSO};
+use rhi::debug;
+use rhi::profiler::ProfiledQueue;
 
 use ash::vk;
+use log::LevelFilter;
+use simple_logger::SimpleLogger;
 
 use vk_base::VkBase;
 
 use winit::{
-    event::{ElementState, Event, KeyEvent, WindowEvent},
+    event::{ElementState, Event, KeyEvent, MouseButton, WindowEvent},
     event_loop::EventLoop,
     keyboard::{KeyCode, PhysicalKey},
     window::{Window, WindowBuilder},
@@ -39,20 +52,30 @@ use winit::{
 const CAMERA_LOCATION: Vec3 = Vec3::new(0.0, 0.0, 10.0);
 const CAMERA_DIRECTION: Vec3 = Vec3::new(0.0, 0.0, -1.0);
 
+const MAX_PANES: usize = 4;
+
+/* Depth step in millimetres past which the depth mesh is torn apart */
+const DEPTH_MESH_MAX_STEP: u16 = 100;
+
+/* The depth mesh is drawn as a surface or as the point cloud of its vertices, toggled with C */
+const DEPTH_MESH_PSO: GraphicsPSO = GraphicsPSO::new(ShaderMesh::ID);
+const DEPTH_POINTS_PSO: GraphicsPSO = GraphicsPSO::new(ShaderMesh::ID).topology(vk::PrimitiveTopology::POINT_LIST);
+
+const GIZMO_PSO: GraphicsPSO = GraphicsPSO::new(ShaderMesh::ID).topology(vk::PrimitiveTopology::LINE_LIST).blend(BlendMode::Additive);
+
 struct App {
-    base: VkBase,
-    rect_bundles: Vec<Drawable2d>,
-    mesh_bundles: Vec<DrawableMesh>,
-    textures: Vec<DrawableTexture>,
+    drawables: DrawableRegistry,
+
+    /* Fed with the frames of video_device */
+    depth_texture: DrawableId,
+    depth_mesh: DrawableId,
+    depth_mesher: DepthMesher,
     scenes: Vec<SimpleScene>,
     video_device: RecordPlayer,
 
+    layout: ViewportLayout,
+    cursor_position: (f32, f32),
 
-    camera_staging: BufferBundle,
-    camera_uniform: BufferBundle,
-    camera: Camera,
-
-    global_descriptor_set: Vec<vk::DescriptorSet>,
     close: bool,
 
 
@@ -62,8 +85,14 @@ struct App {
 
     keyboard_state: KeyboardState,
 
-    allocator: Allocator,
-    shader_poll_time: Instant
+    shader_poll_time: Instant,
+
+    /* Shows the rolling profile in the window title, toggled with P */
+    show_profile: bool,
+    profile_title_time: Instant,
+
+    /* Dropped after everything that hands its resources to base.release_queue, see App::drop */
+    base: VkBase,
 }
 
 use shader::*;
@@ -71,67 +100,115 @@ use shader::*;
 
 const SHADER_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
+const WINDOW_TITLE: &str = "Vulkan Video";
+const PROFILE_TITLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/* Written with F9 */
+const PROFILE_CSV_FILE: &str = "profile.csv";
+
+/* Written with F11 as <stem><scene index>.glb */
+const SCENE_EXPORT_STEM: &str = "scene";
+
+/* Requested multisampling, lowered to what the device supports */
+const MSAA_SAMPLES: vk::SampleCountFlags = vk::SampleCountFlags::TYPE_4;
+
 impl App {
-    fn new(window: Window) -> Self {
+    fn new(window: Window, options: &Options) -> anyhow::Result<Self> {
 
         ShaderRegistry::describe_registed_shaders();
 
+        // The pane cameras live in the upload ring, bound with a dynamic offset
         let global_descriptor_set_binding = DescSetBinding {
             binding: 0,
-            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
             descriptor_count: 1,
             stage_flags: vk::ShaderStageFlags::VERTEX,
         };
 
 
         let video_device = RecordPlayer::from_buffer(include_bytes!("../assets/recordings/record1.rdbin")).unwrap();
-        let base = VkBase::new(window, 3, "./assets/shaders", global_descriptor_set_binding);
-        let mut allocator = Allocator::new(&base, AllocatorSizeInfo {
-            staging: 10*1024,
-            device_vertex: 10*1024,
-            device_index: 10*1024,
-            uniform_buffer: 10*1024,
-        });
-
-
-        let rect_bundles = vec![
-            Drawable2d::new(&base.device, Rect::new(-0.9, -0.9, 0.5, 0.5, [1.0, 0.0, 0.0])),
-            Drawable2d::new(&base.device, Rect::new(0.0, 0.0, 0.5, 0.5, [0.0, 0.0, 1.0])),
-            Drawable2d::new(&base.device, Rect::new(-0.25, -0.25, 0.5, 0.5, [0.0, 1.0, 1.0]))
-        ];
+        let record = &video_device.record_data;
+        let depth_mesher = DepthMesher::new(record.width as usize, record.height as usize, &record.projection_data, DEPTH_MESH_MAX_STEP)?;
+
+        // Drawables share these blocks, per frame uploads go through the upload ring instead of staging
+        let allocator_sizes = AllocatorSizeInfo {
+            staging: 64*1024,
+            device_vertex: 256*1024,
+            device_index: 64*1024,
+            uniform_buffer: 16*1024,
+            image_block: 16*1024*1024,
+            // The depth mesh goes up whole every frame, next to the texture, cameras and smaller meshes
+            upload_ring_frame: 8*1024*1024 + depth_mesher.max_upload_size(),
+        };
+        let mut base = VkBase::new(window, 3, "./assets/shaders", global_descriptor_set_binding, MSAA_SAMPLES, allocator_sizes, options);
+
+        for heap in base.memory_budget() {
+            log::debug!("{}", heap.describe());
+        }
 
-        let mesh_bundles = vec![
-            DrawableMesh::new(&base.device, cube::make_cube(0.0, 0.0, 0.25, 0.5, [1.0, 0.2, 1.0]))
+
+        let mut drawables = DrawableRegistry::default();
+
+        let rects = [
+            Rect::new(-0.9, -0.9, 0.5, 0.5, [1.0, 0.0, 0.0]),
+            Rect::new(0.0, 0.0, 0.5, 0.5, [0.0, 0.0, 1.0]),
+            Rect::new(-0.25, -0.25, 0.5, 0.5, [0.0, 1.0, 1.0]),
         ];
 
+        for rect in rects {
+            drawables.add(PaneContent::Rects, Drawable2d::new(&base.device, &mut base.allocator, rect));
+        }
+
+        drawables.add(PaneContent::Meshes, DrawableMesh::new(&base.device, &mut base.allocator, cube::make_cube(0.0, 0.0, 0.25, 0.5, [1.0, 0.2, 1.0])));
+
+        // Added on top of what is behind them so they stay visible over the meshes
+        let mut gizmo = DrawableMesh::new(&base.device, &mut base.allocator, axes::make_axes(Vec3::ZERO, 1.0));
+        gizmo.pipeline_state = GIZMO_PSO;
+        drawables.add(PaneContent::Meshes, gizmo);
+
+        for path in &options.meshes {
+            match mesh::import::load(path) {
+                Ok(mesh) => {
+                    log::info!("Loaded {}: {} vertices, {} triangles", path.display(), mesh.vertices.len(), mesh.indices.len() / 3);
+                    drawables.add(PaneContent::Meshes, DrawableMesh::new(&base.device, &mut base.allocator, mesh));
+                }
+                Err(err) => log::warn!("{:#}", err),
+            }
+        }
+
         let scenes = vec![
-            SimpleScene::new(&base, &mut allocator)
+            SimpleScene::new(&mut base)
         ];
 
         let data = unsafe { video_device.current_frame[0..video_device.size() / 2].align_to::<u8>().1.to_vec() };
         let texture = Texture2d::new(data, video_device.width(), video_device.height(), PixelFormat::Z16);
 
-        //TODO: Cleanup descriptor pool
-
         let cb = begin_single_time_command(&base.device, base.spare_command.pool);
         let ubo = base.graphics_pipelines[ShaderTexture::ID].ubo.as_ref().unwrap();
 
-        let textures = vec![
-            DrawableTexture::new(&base.device, base.descriptor_pool,  cb, ubo[0], base.swapchain.images.len(), Rect::new(-1.0, -1.0, 2.0, 2.0, [1.0, 1.0, 1.0]), texture)
-        ];
+        let depth_texture = drawables.add(PaneContent::DepthTexture,
+            DrawableTexture::new(&base.device, &mut base.allocator, base.descriptor_pool,  cb, ubo[0], base.swapchain.images.len(), Rect::new(-1.0, -1.0, 2.0, 2.0, [1.0, 1.0, 1.0]), texture));
 
         end_single_time_command(&base.device, base.spare_command.pool, base.device.present_queue, cb);
 
-        let camera_staging = allocator.alloc(BufferType::Staging, std::mem::size_of::<CameraParams>() as u64).unwrap();
-        let camera_uniform = allocator.alloc(BufferType::Uniform, std::mem::size_of::<CameraParams>() as u64).unwrap();
-        let camera = Self::make_camera();
+        // The sensor looks down +z with y pointing down, placed where the pane cameras start and turned to look the same way
+        let mut depth_mesh = DrawableMesh::with_index_capacity(&base.device, &mut base.allocator, depth_mesher.mesh(&video_device.current_frame)?, depth_mesher.max_indices());
+        depth_mesh.transform.position = CAMERA_LOCATION;
+        depth_mesh.transform.rotation = Quat::from_axis_angle(Vec3::X, PI);
+        let depth_mesh = drawables.add(PaneContent::DepthMesh, depth_mesh);
+
+        let pane_contents = [
+            vec![PaneContent::Scene],
+            vec![PaneContent::DepthTexture],
+            vec![PaneContent::Meshes, PaneContent::Rects],
+            vec![PaneContent::DepthMesh],
+        ];
 
-        let global_descriptor_set = VkBase::create_descriptor_sets(&base.device, base.descriptor_pool, base.global_descriptor_set_layout, base.max_in_flight);
+        let panes: Vec<_> = pane_contents.into_iter().take(MAX_PANES).map(|contents| {
+            Pane::new(&base, Self::make_camera(), contents)
+        }).collect();
 
-        // TODO: FIX THE DESC SET BINDING JANK
-        for descriptor_set in global_descriptor_set.iter() {
-            VkBase::update_descriptor_set_buffers(&base.device, *descriptor_set, &[&camera_uniform], 0);
-        }
+        let layout = ViewportLayout::new(LayoutKind::SplitVertical(0.5), panes);
 
         let current_time = Instant::now();
         let delta_time   = 16.0e-3;
@@ -140,19 +217,17 @@ impl App {
         let keyboard_state = KeyboardState::new();
 
 
-        Self {
+        Ok(Self {
             video_device,
             base,
-            rect_bundles,
-            mesh_bundles,
-            textures,
+            drawables,
+            depth_texture,
+            depth_mesh,
+            depth_mesher,
             scenes,
-            camera_staging,
-            camera_uniform,
-            camera,
-            allocator,
 
-            global_descriptor_set,
+            layout,
+            cursor_position: (0.0, 0.0),
 
             current_time,
             delta_time,
@@ -161,8 +236,10 @@ impl App {
             keyboard_state,
 
             shader_poll_time: Instant::now() + SHADER_POLL_INTERVAL,
+            show_profile: false,
+            profile_title_time: Instant::now(),
             close: false,
-        }
+        })
     }
 
     fn update(&mut self) {
@@ -173,71 +250,43 @@ impl App {
 
         self.handle_down_keys();
 
-        for mesh_bundle in self.rect_bundles.iter_mut() {
-            mesh_bundle.mesh.transform(0.001, [0.0, 0.0]);
-        }
-
-        self.base.cleanup_in_flight_buffers();
-
-        if self.base.sync_objects.spare_fences.len() == 0 {
-            return;
+        for rect in self.drawables.iter_mut::<Drawable2d>() {
+            rect.mesh.transform(0.001, [0.0, 0.0]);
         }
 
-        let cb = match self.base.spare_command.buffers.pop() {
-            Some(cb) => cb,
-            None => { return; }
-        };
-
-        let cb_begin_info =  vk::CommandBufferBeginInfo::default()
-            .flags(vk::CommandBufferUsageFlags::SIMULTANEOUS_USE);
+        if let Some(new_frame) = self.video_device.poll() {
+            let texture = self.drawables.get_mut::<DrawableTexture>(self.depth_texture).unwrap();
+            texture.texture_data.update_data(new_frame);
 
-        unsafe {
-            let _ = self.base.device.logical.reset_command_buffer(cb, vk::CommandBufferResetFlags::empty());
-            self.base.device.logical.begin_command_buffer(cb, &cb_begin_info).unwrap();
+            let depth_mesh = self.drawables.get_mut::<DrawableMesh>(self.depth_mesh).unwrap();
+            if let Err(e) = self.depth_mesher.update(&self.video_device.current_frame, &mut depth_mesh.mesh) {
+                log::warn!("{:#}", e);
+            }
         }
 
-        Drawable2d::update(&self.base.device, &cb, &mut self.rect_bundles);
-        DrawableMesh::update(&self.base.device, &cb, &mut self.mesh_bundles);
-
-        let w = self.base.window.inner_size();
-        SimpleScene::update(&mut self.scenes, &self.base, &cb, w.width as f32 / w.height as f32);
-
-
-        if let Some(new_frame) = self.video_device.poll() {
-            self.textures[0].texture_data.update_data(new_frame);
+        // Pushed before the batch so every rendered frame has its uniforms in the current region of the ring
+        let extent = self.base.swapchain.extent;
+        for (i, rect) in self.layout.visible() {
+            self.layout.panes[i].push_camera(&mut self.base.upload_ring, rect.to_pixels(extent)).unwrap();
         }
 
-        DrawableTexture::update(&self.base.device, cb, &mut self.textures);
-
-        unsafe {
-            let data_ptr = self.base.device.logical.map_memory(self.camera_staging.memory, self.camera_staging.offset, self.camera_staging.size, vk::MemoryMapFlags::empty()).unwrap() as *mut CameraParams;
-            data_ptr.copy_from_nonoverlapping(&self.camera.params  as *const CameraParams, self.camera_staging.size as usize);
-            self.base.device.logical.unmap_memory(self.camera_staging.memory);
-
-            let copy_region = [
-                vk::BufferCopy::default()
-                    .src_offset(self.camera_staging.offset)
-                    .dst_offset(self.camera_uniform.offset)
-                    .size(self.camera_staging.size)
-            ];
-
-            self.base.device.logical.cmd_copy_buffer(cb, self.camera_staging.buffer, self.camera_uniform.buffer, &copy_region);
-        }
+        SimpleScene::update(&mut self.scenes, &mut self.base.upload_ring).unwrap();
 
+        let mut batch = match self.base.begin_upload() {
+            Some(batch) => batch,
+            None => { return; }
+        };
 
-        unsafe { self.base.device.logical.end_command_buffer(cb).unwrap(); }
+        let device = &self.base.device;
+        let ring = &mut self.base.upload_ring;
+        debug::begin_label(device, batch.cb, "Upload", debug::UPLOAD_COLOUR);
 
-        let cbs = [cb];
-        let submit_info = vk::SubmitInfo::default()
-            .command_buffers(&cbs);
+        self.drawables.upload(device, &mut batch, ring);
+        SimpleScene::upload(&mut self.scenes, device, ring, &mut batch);
 
-        let fences = [self.base.sync_objects.spare_fences.pop().unwrap()];
-        unsafe {
-            self.base.device.logical.reset_fences(&fences).expect("Failed to reset fences.");
-            self.base.device.logical.queue_submit(self.base.device.present_queue, &[submit_info], fences[0]).expect("Failure submitting to the queue.");
-        }
+        debug::end_label(device, batch.cb);
 
-        self.base.in_flight_buffers.push((cb, fences[0]));
+        self.base.submit_upload(batch);
 
         if self.shader_poll_time < ct {
             self.base.check_and_recompile_shaders();
@@ -253,12 +302,70 @@ impl App {
             None => { return; }
         };
 
-        // DrawableTexture::draw(&self.base.device, cb, &self.base.graphics_pipelines[ShaderTexture::ID], self.base.current_frame, &self.textures);
-        // Drawable2d::draw(&self.base.device, &cb, &self.base.graphics_pipelines[ShaderRect::ID], &self.rect_bundles);
-        // DrawableMesh::draw(&self.base.device, &cb, &self.base.graphics_pipelines[ShaderMesh::ID], &self.mesh_bundles);
         let current_image = self.base.current_frame;
-        SimpleScene::draw(&self.scenes, &mut self.base, &cb, current_image, self.global_descriptor_set[current_image]);
+        let extent = self.base.swapchain.extent;
+
+        for (i, rect) in self.layout.visible() {
+            let (viewport, scissor) = ViewportLayout::viewport(&rect, extent);
+            self.base.set_viewport(&cb, viewport, scissor);
+
+            let pane = &self.layout.panes[i];
+            for content in pane.contents.iter() {
+                let label = match content {
+                    PaneContent::Scene => "Scene",
+                    PaneContent::DepthTexture => "Depth texture",
+                    PaneContent::Meshes => "Meshes",
+                    PaneContent::Rects => "Rects",
+                    PaneContent::DepthMesh => "Depth mesh",
+                };
+                debug::begin_label(&self.base.device, cb, label, debug::DRAW_COLOUR);
+                self.base.profiler.begin_scope(&self.base.device, cb, ProfiledQueue::Graphics, label);
+
+                match content {
+                    PaneContent::Scene => {
+                        SimpleScene::draw(&self.scenes, &mut self.base, &cb, current_image, pane.descriptor_sets[current_image], pane.camera_offset);
+                    }
+
+                    content => {
+                        self.drawables.draw(*content, pane, &mut self.base, cb, current_image);
+                    }
+                }
+
+                self.base.profiler.end_scope(&self.base.device, cb);
+                debug::end_label(&self.base.device, cb);
+            }
+        }
+
+        let present_start = Instant::now();
         self.base.render(&cb, image_index);
+        self.base.profiler.record_cpu("submit+present", present_start.elapsed());
+    }
+
+    fn update_profile_title(&mut self) {
+        let now = Instant::now();
+        if !self.show_profile || now < self.profile_title_time {
+            return;
+        }
+
+        self.profile_title_time = now + PROFILE_TITLE_INTERVAL;
+        self.base.window.set_title(&format!("{} - {}", WINDOW_TITLE, self.base.profiler.history.summary()));
+    }
+
+    fn dump_profile(&self) {
+        match std::fs::write(PROFILE_CSV_FILE, self.base.profiler.history.to_csv()) {
+            Ok(()) => println!("Wrote the profile to {}", PROFILE_CSV_FILE),
+            Err(e) => println!("Failed to write the profile to {}: {}", PROFILE_CSV_FILE, e),
+        }
+    }
+
+    fn export_scenes(&self) {
+        for (i, scene) in self.scenes.iter().enumerate() {
+            let path = format!("{}{}.glb", SCENE_EXPORT_STEM, i);
+            match scene.export(Path::new(&path)) {
+                Ok(()) => log::info!("Wrote scene {} to {}", i, path),
+                Err(e) => log::warn!("Failed to write scene {} to {}: {:#}", i, path, e),
+            }
+        }
     }
 
     fn handle_event(&mut self, event: WindowEvent) {
@@ -268,14 +375,31 @@ impl App {
             }
 
             WindowEvent::RedrawRequested => {
+                self.base.begin_frame();
+
+                let update_start = Instant::now();
                 self.update();
+                let render_start = Instant::now();
                 self.render();
+
+                self.base.profiler.record_cpu("update", render_start - update_start);
+                self.base.profiler.record_cpu("render", render_start.elapsed());
+                self.update_profile_title();
             }
 
             WindowEvent::Resized(_) => {
                 self.base.window.request_redraw();
             }
 
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = (position.x as f32, position.y as f32);
+            }
+
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. } => {
+                let (x, y) = self.cursor_position;
+                self.layout.focus_at(x, y, self.base.swapchain.extent);
+            }
+
             WindowEvent::KeyboardInput {
                 device_id: _,
                 event,
@@ -290,27 +414,27 @@ impl App {
 
     fn handle_down_keys(&mut self) {
         if self.keyboard_state[KeyCode::KeyA] {
-            self.camera.update(CameraAction::Left, self.delta_time * self.speed);
+            self.layout.focused_mut().camera.update(CameraAction::Left, self.delta_time * self.speed);
         }
 
         if self.keyboard_state[KeyCode::KeyD] {
-            self.camera.update(CameraAction::Right, self.delta_time * self.speed);
+            self.layout.focused_mut().camera.update(CameraAction::Right, self.delta_time * self.speed);
         }
 
         if self.keyboard_state[KeyCode::KeyW] {
-            self.camera.update(CameraAction::Forward, self.delta_time * self.speed);
+            self.layout.focused_mut().camera.update(CameraAction::Forward, self.delta_time * self.speed);
         }
 
         if self.keyboard_state[KeyCode::KeyS] {
-            self.camera.update(CameraAction::Backward, self.delta_time * self.speed);
+            self.layout.focused_mut().camera.update(CameraAction::Backward, self.delta_time * self.speed);
         }
 
         if self.keyboard_state[KeyCode::KeyE] {
-            self.camera.update(CameraAction::Up, self.delta_time * self.speed);
+            self.layout.focused_mut().camera.update(CameraAction::Up, self.delta_time * self.speed);
         }
 
         if self.keyboard_state[KeyCode::KeyQ] {
-            self.camera.update(CameraAction::Down, self.delta_time * self.speed);
+            self.layout.focused_mut().camera.update(CameraAction::Down, self.delta_time * self.speed);
         }
     }
 
@@ -331,8 +455,63 @@ impl App {
                         self.reset_camera();
                     }
 
+                    KeyCode::KeyO if event.state == ElementState::Pressed => {
+                        self.layout.focused_mut().camera.update(CameraAction::ToggleType, 0.0);
+                    }
+
+                    KeyCode::Tab if event.state == ElementState::Pressed => {
+                        self.layout.focus_next();
+                    }
+
+                    KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 | KeyCode::Digit4 if event.state == ElementState::Pressed => {
+                        let kind = match a {
+                            KeyCode::Digit1 => LayoutKind::Single,
+                            KeyCode::Digit2 => LayoutKind::SplitVertical(0.5),
+                            KeyCode::Digit3 => LayoutKind::SplitHorizontal(0.5),
+                            _ => LayoutKind::Grid { rows: 2, cols: 2 },
+                        };
+                        self.layout.set_kind(kind);
+                    }
+
+                    KeyCode::KeyM if event.state == ElementState::Pressed => {
+                        // Cycle through the supported sample counts
+                        let counts = VkBase::supported_sample_counts(&self.base.device);
+                        let pos = counts.iter().position(|samples| *samples == self.base.render_targets.samples).unwrap_or(0);
+                        self.base.set_msaa_samples(counts[(pos + 1) % counts.len()]);
+                        println!("MSAA: {:?}", self.base.render_targets.samples);
+                    }
+
+                    KeyCode::KeyP if event.state == ElementState::Pressed => {
+                        self.show_profile = !self.show_profile;
+                        if !self.show_profile {
+                            self.base.window.set_title(WINDOW_TITLE);
+                        }
+                    }
+
+                    KeyCode::KeyC if event.state == ElementState::Pressed => {
+                        // Between the depth surface and its point cloud
+                        let depth_mesh = self.drawables.get_mut::<DrawableMesh>(self.depth_mesh).unwrap();
+                        depth_mesh.pipeline_state = if depth_mesh.pipeline_state == DEPTH_MESH_PSO { DEPTH_POINTS_PSO } else { DEPTH_MESH_PSO };
+                    }
+
+                    KeyCode::F9 if event.state == ElementState::Pressed => {
+                        self.dump_profile();
+                    }
+
+                    KeyCode::F10 if event.state == ElementState::Pressed => {
+                        self.base.print_memory_report();
+                        self.base.allocator.print_stats();
+                    }
+
+                    KeyCode::F11 if event.state == ElementState::Pressed => {
+                        self.export_scenes();
+                    }
+
                     k => {
-                        SimpleScene::handle_key(&mut self.scenes, k, event.state, event.repeat);
+                        // Scene controls only apply when the focused pane shows the scene
+                        if self.layout.focused().shows(PaneContent::Scene) {
+                            SimpleScene::handle_key(&mut self.scenes, k, event.state, event.repeat);
+                        }
                     }
                 }
             }
@@ -341,7 +520,7 @@ impl App {
     }
 
     fn reset_camera(&mut self) {
-        self.camera = Self::make_camera();
+        self.layout.focused_mut().camera = Self::make_camera();
     }
 
     fn make_camera() -> Camera{
@@ -349,42 +528,55 @@ impl App {
     }
 }
 
+/* Everything holding Owned resources goes before base, which destroys them once the GPU is idle */
 impl Drop for App {
     fn drop(&mut self) {
-        unsafe {
-
-            let _ = self.base.device.logical.device_wait_idle();
-
-
-            Drawable2d::release(&self.base.device, &mut self.rect_bundles);
-            self.rect_bundles.clear();
-
-            DrawableMesh::release(&self.base.device, &mut self.mesh_bundles);
-            self.mesh_bundles.clear();
+        self.drawables = DrawableRegistry::default();
+        self.scenes.clear();
+        self.layout.panes.clear();
+    }
+}
 
-            DrawableTexture::release(&self.base.device, &mut self.textures);
-            self.textures.clear();
+/* The devices are listed for a surface since presenting to it is one of the requirements */
+fn list_gpus(window: &Window) {
+    let (entry, instance, _) = VkBase::create_instance(window, false);
+    let surface = VkBase::create_surface(&entry, &instance, window);
 
-            SimpleScene::release(&mut self.scenes, &self.base);
-            self.scenes.clear();
+    for adapter in rhi::adapter::enumerate_adapters(&instance, &surface) {
+        println!("{}", adapter.describe());
+    }
 
-            self.allocator.release(&self.base.device);
-        }
+    unsafe {
+        surface.loader.destroy_surface(surface.surface, None);
+        instance.destroy_instance(None);
     }
 }
 
-
 fn main() {
-    // SimpleLogger::new().init().unwrap();
+    // RUST_LOG overrides the level, e.g. RUST_LOG=trace for verbose validation output
+    SimpleLogger::new().with_level(LevelFilter::Warn).env().init().unwrap();
 
     let event_loop = EventLoop::new().unwrap();
 
     let window = WindowBuilder::new()
-        .with_title("Vulkan Video")
+        .with_title(WINDOW_TITLE)
         .build(&event_loop)
         .unwrap();
 
-    let mut app = App::new(window);
+    let options = Options::from_env();
+
+    if options.list_gpus {
+        list_gpus(&window);
+        return;
+    }
+
+    let mut app = match App::new(window, &options) {
+        Ok(app) => app,
+        Err(err) => {
+            log::error!("{:#}", err);
+            return;
+        }
+    };
 
     let mut closing = false;
 
@@ -0,0 +1,96 @@
+use crate::geometry::quat::Quat;
+use crate::geometry::vec3::Vec3;
+
+use super::cylinder::{make_cone, make_cylinder};
+use super::mesh::{Mesh, Shading};
+
+/*
+ * Cylinder shaft with a cone head pointing from start to end, for axes and vectors in a scene.
+ * The head is head_length long unless the arrow is shorter than that.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn make_arrow(start: Vec3, end: Vec3, shaft_radius: f32, head_radius: f32, head_length: f32, segments: usize, col: Vec3, shading: Shading) -> Mesh {
+    let length = (end - start).length();
+    let head_length = head_length.min(length);
+    let shaft_length = length - head_length;
+
+    // Built up the y axis from the origin, then turned towards end
+    let shaft = make_cylinder(Vec3::Y * (shaft_length / 2.0), shaft_radius, shaft_length, segments, col, Shading::Smooth);
+    let head = make_cone(Vec3::Y * (length - head_length / 2.0), head_radius, head_length, segments, col, Shading::Smooth);
+
+    let rotation = rotation_from_y(end - start);
+
+    let mut vertices = Vec::with_capacity(shaft.vertices.len() + head.vertices.len());
+    let mut normals = Vec::with_capacity(vertices.capacity());
+    let mut indices = Vec::with_capacity(shaft.indices.len() + head.indices.len());
+
+    for part in [&shaft, &head] {
+        let base = vertices.len() as u32;
+        vertices.extend(part.vertices.iter().map(|&v| start + rotation.rotate(v)));
+        normals.extend(part.normals.iter().map(|&n| rotation.rotate(n)));
+        indices.extend(part.indices.iter().map(|i| base + i));
+    }
+
+    Mesh::from_parts(start, vertices, normals, indices, col, shading)
+}
+
+/* Turns +y onto direction, around x when they are opposite and not at all when direction has no length */
+fn rotation_from_y(direction: Vec3) -> Quat {
+    if direction.length() < 1e-6 {
+        return Quat::IDENT;
+    }
+
+    let direction = Vec3::norm(&direction);
+    let axis = Vec3::cross(Vec3::Y, direction);
+    let cos = Vec3::dot(Vec3::Y, direction);
+
+    if axis.length() > 1e-6 {
+        Quat::from_axis_angle(axis, cos.clamp(-1.0, 1.0).acos())
+    } else if cos < 0.0 {
+        Quat::from_axis_angle(Vec3::X, std::f32::consts::PI)
+    } else {
+        Quat::IDENT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::mesh::tests::assert_front_faces_out;
+    use crate::mesh::Shading;
+
+    use super::make_arrow;
+
+    #[test]
+    fn test_arrow() {
+        let start = Vec3::new(1.0, 2.0, 3.0);
+
+        for end in [Vec3::new(1.0, 2.0, 6.0), Vec3::new(1.0, 5.0, 3.0), Vec3::new(1.0, -1.0, 3.0), Vec3::new(4.0, 5.0, 6.0)] {
+            let arrow = make_arrow(start, end, 0.1, 0.2, 0.5, 8, Vec3::of(1.0), Shading::Smooth);
+
+            assert_eq!(arrow.vertices.len(), (4 * 8 + 4) + (3 * 8 + 2));
+            assert_eq!(arrow.indices.len(), 12 * 8 + 6 * 8);
+
+            // Nothing goes past the tip or behind the start
+            let direction = Vec3::norm(&(end - start));
+            let along: Vec<f32> = arrow.vertices.iter().map(|&v| Vec3::dot(v - start, direction)).collect();
+            let tip = along.iter().cloned().fold(f32::MIN, f32::max);
+            assert!((tip - (end - start).length()).abs() < 1e-4, "Tip at {} for {}", tip, end);
+            assert!(along.iter().all(|&a| a > -1e-4));
+
+            assert_front_faces_out(&arrow);
+        }
+
+        // Collapses to a point instead of NaN
+        let arrow = make_arrow(start, start, 0.1, 0.2, 0.5, 8, Vec3::of(1.0), Shading::Smooth);
+        assert!(arrow.vertices.iter().chain(arrow.normals.iter()).all(|v| v.x.is_finite() && v.y.is_finite() && v.z.is_finite()));
+    }
+
+    #[test]
+    fn test_flat_arrow() {
+        let arrow = make_arrow(Vec3::ZERO, Vec3::X, 0.1, 0.2, 0.3, 6, Vec3::of(1.0), Shading::Flat);
+
+        assert_eq!(arrow.vertices.len(), arrow.indices.len());
+        assert_front_faces_out(&arrow);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::geometry::vec3::Vec3;
+use crate::utils::colours::{BLUE, GREEN, RED};
+
+use super::indices::Indices;
+use super::mesh::Mesh;
+
+/*
+ * Lines from location along x, y and z in red, green and blue. The indices are pairs for a LINE_LIST pipeline,
+ * not triangles, so the normals are just the directions of the lines.
+ */
+pub fn make_axes(location: Vec3, length: f32) -> Mesh {
+    let axes = [(Vec3::X, RED), (Vec3::Y, GREEN), (Vec3::Z, BLUE)];
+
+    let vertices: Vec<Vec3> = axes.iter().flat_map(|&(axis, _)| [location, location + axis * length]).collect();
+    let colour = axes.iter().flat_map(|&(_, col)| [col, col]).collect();
+    let normals = axes.iter().flat_map(|&(axis, _)| [axis, axis]).collect();
+    let indices = Indices::new((0..vertices.len() as u32).collect(), vertices.len());
+
+    Mesh {
+        center: location,
+        vertices,
+        colour,
+        normals,
+        indices,
+        dirty_vertices: true,
+        dirty_colour: true,
+        dirty_normals: true,
+        dirty_indices: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+
+    use super::make_axes;
+
+    #[test]
+    fn test_axes_are_line_pairs() {
+        let location = Vec3::new(1.0, 2.0, 3.0);
+        let axes = make_axes(location, 2.0);
+
+        assert_eq!(axes.indices.len(), 6);
+        for (pair, axis) in axes.vertices.chunks_exact(2).zip([Vec3::X, Vec3::Y, Vec3::Z]) {
+            assert_eq!(pair[0], location);
+            assert_eq!(pair[1] - pair[0], axis * 2.0);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::f32::consts::PI;
+
+use crate::geometry::vec3::Vec3;
+
+use super::mesh::{Mesh, Shading};
+
+/* Direction of segment s of segments around the y axis */
+fn around_y(s: usize, segments: usize) -> Vec3 {
+    let phi = 2.0 * PI * s as f32 / segments as f32;
+    Vec3::new(phi.cos(), 0.0, phi.sin())
+}
+
+/*
+ * A flat disc of segments triangles around centre facing normal, which is +y or -y.
+ * The rim doesn't repeat its first vertex, so a cap has segments + 1 vertices.
+ */
+fn add_cap(centre: Vec3, radius: f32, segments: usize, normal: Vec3, vertices: &mut Vec<Vec3>, normals: &mut Vec<Vec3>, indices: &mut Vec<u32>) {
+    let first = vertices.len() as u32;
+
+    vertices.push(centre);
+    normals.push(normal);
+    for s in 0..segments {
+        vertices.push(centre + around_y(s, segments) * radius);
+        normals.push(normal);
+    }
+
+    for s in 0..segments as u32 {
+        let (a, b) = (first + 1 + s, first + 1 + (s + 1) % segments as u32);
+
+        // Going round the rim is clockwise seen from above
+        if normal.y > 0.0 {
+            indices.extend_from_slice(&[first, a, b]);
+        } else {
+            indices.extend_from_slice(&[first, b, a]);
+        }
+    }
+}
+
+/* Capped cylinder along the y axis, centred on location. Has 4 * segments + 4 vertices when smooth. */
+pub fn make_cylinder(location: Vec3, radius: f32, height: f32, segments: usize, col: Vec3, shading: Shading) -> Mesh {
+    let segments = segments.max(3);
+    let half = Vec3::Y * (height / 2.0);
+
+    let mut vertices = Vec::with_capacity(4 * segments + 4);
+    let mut normals = Vec::with_capacity(4 * segments + 4);
+    let mut indices = Vec::with_capacity(12 * segments);
+
+    // Bottom and top of each segment, the seam repeats the first pair
+    for s in 0..=segments {
+        let n = around_y(s, segments);
+        vertices.push(location - half + n * radius);
+        vertices.push(location + half + n * radius);
+        normals.push(n);
+        normals.push(n);
+    }
+
+    for s in 0..segments as u32 {
+        let (a, c) = (2 * s, 2 * s + 1);
+        let (b, d) = (a + 2, c + 2);
+        indices.extend_from_slice(&[a, b, c, b, d, c]);
+    }
+
+    add_cap(location + half, radius, segments, Vec3::Y, &mut vertices, &mut normals, &mut indices);
+    add_cap(location - half, radius, segments, Vec3::Y * -1.0, &mut vertices, &mut normals, &mut indices);
+
+    Mesh::from_parts(location, vertices, normals, indices, col, shading)
+}
+
+/*
+ * Cone along the y axis with its base height / 2 below location and its tip above.
+ * The tip has a vertex per segment so the side shades smoothly, 3 * segments + 2 vertices in all when smooth.
+ */
+pub fn make_cone(location: Vec3, radius: f32, height: f32, segments: usize, col: Vec3, shading: Shading) -> Mesh {
+    let segments = segments.max(3);
+    let half = Vec3::Y * (height / 2.0);
+
+    let mut vertices = Vec::with_capacity(3 * segments + 2);
+    let mut normals = Vec::with_capacity(3 * segments + 2);
+    let mut indices = Vec::with_capacity(6 * segments);
+
+    // Perpendicular to the slope, which rises height over radius
+    let slope_normal = |n: Vec3| Vec3::norm(&(n * height + Vec3::Y * radius));
+
+    for s in 0..=segments {
+        let n = around_y(s, segments);
+        vertices.push(location - half + n * radius);
+        normals.push(slope_normal(n));
+    }
+
+    let tip = vertices.len() as u32;
+    for s in 0..segments {
+        let n = Vec3::norm(&(around_y(s, segments) + around_y(s + 1, segments)));
+        vertices.push(location + half);
+        normals.push(slope_normal(n));
+    }
+
+    for s in 0..segments as u32 {
+        indices.extend_from_slice(&[s, s + 1, tip + s]);
+    }
+
+    add_cap(location - half, radius, segments, Vec3::Y * -1.0, &mut vertices, &mut normals, &mut indices);
+
+    Mesh::from_parts(location, vertices, normals, indices, col, shading)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::mesh::tests::{assert_front_faces_out, assert_outward};
+    use crate::mesh::Shading;
+
+    use super::{make_cone, make_cylinder};
+
+    #[test]
+    fn test_cylinder() {
+        let centre = Vec3::new(0.0, 1.0, 0.0);
+
+        for shading in [Shading::Smooth, Shading::Flat] {
+            let cylinder = make_cylinder(centre, 0.5, 2.0, 12, Vec3::of(1.0), shading);
+
+            let expected = if shading == Shading::Smooth { 4 * 12 + 4 } else { 3 * 12 * 4 };
+            assert_eq!(cylinder.vertices.len(), expected);
+            assert_eq!(cylinder.indices.len(), 12 * 12);
+
+            assert_outward(&cylinder, |v| v - centre);
+            assert_front_faces_out(&cylinder);
+        }
+    }
+
+    #[test]
+    fn test_cone() {
+        let cone = make_cone(Vec3::ZERO, 1.0, 2.0, 8, Vec3::of(1.0), Shading::Smooth);
+
+        assert_eq!(cone.vertices.len(), 3 * 8 + 2);
+        assert_eq!(cone.indices.len(), 6 * 8);
+        assert!(cone.vertices.iter().all(|v| v.y.abs() == 1.0));
+
+        // At 45 degrees the side's normals lean halfway up
+        let side = Vec3::norm(&Vec3::new(2.0, 1.0, 0.0));
+        assert!((cone.normals[0] - side).length() < 1e-5);
+
+        assert_outward(&cone, |v| v);
+        assert_front_faces_out(&cone);
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{anyhow, Result};
+
+use crate::geometry::vec3::Vec3;
+use crate::utils::colours::WHITE;
+
+use super::indices::Indices;
+use super::mesh::Mesh;
+
+/*
+ * Turns Z16 depth frames into a surface with one vertex per pixel, placed along the pixel's ray at its depth.
+ * Every 2x2 block of pixels becomes two triangles unless a corner has no depth or the depths across the triangle
+ * differ by more than max_step, which keeps foreground and background apart.
+ * The vertex count never changes, so a mesh made once can be updated in place for every frame.
+ */
+pub struct DepthMesher {
+    pub width: usize,
+    pub height: usize,
+
+    /* Largest depth difference within a triangle, in the frame's units (millimetres for Z16) */
+    pub max_step: u16,
+
+    /* Direction of each pixel at a depth of 1, RecordData::projection_data */
+    rays: Vec<Vec3>,
+}
+
+/* Z16 depths are millimetres, the mesh is in metres */
+const DEPTH_SCALE: f32 = 1e-3;
+
+impl DepthMesher {
+    pub fn new(width: usize, height: usize, projection_data: &[f32], max_step: u16) -> Result<Self> {
+        if projection_data.len() != width * height * 3 {
+            return Err(anyhow!("Depth mesher: {} projection values for a {}x{} frame", projection_data.len(), width, height));
+        }
+
+        let rays = projection_data.chunks_exact(3).map(|r| Vec3::new(r[0], r[1], r[2])).collect();
+        Ok(Self { width, height, max_step, rays })
+    }
+
+    /* Indices of a frame where every triangle is kept, what the index buffer has to hold */
+    pub fn max_indices(&self) -> usize {
+        self.width.saturating_sub(1) * self.height.saturating_sub(1) * 6
+    }
+
+    /* Bytes an update stages at most, the vertices, normals and a full index buffer */
+    pub fn max_upload_size(&self) -> u64 {
+        let vertex_count = self.width * self.height;
+        (2 * vertex_count * std::mem::size_of::<Vec3>() + self.max_indices() * std::mem::size_of::<u32>()) as u64
+    }
+
+    pub fn mesh(&self, depth: &[u16]) -> Result<Mesh> {
+        let vertex_count = self.width * self.height;
+
+        let mut mesh = Mesh {
+            center: Vec3::ZERO,
+            vertices: vec![Vec3::ZERO; vertex_count],
+            colour: vec![WHITE; vertex_count],
+            normals: vec![Vec3::ZERO; vertex_count],
+            indices: Indices::new(vec![], vertex_count),
+            dirty_vertices: true,
+            dirty_colour: true,
+            dirty_normals: true,
+            dirty_indices: true,
+        };
+
+        self.update(depth, &mut mesh)?;
+        Ok(mesh)
+    }
+
+    /* For a mesh from DepthMesher::mesh, reuses its buffers. The indices are only marked dirty when they change. */
+    pub fn update(&self, depth: &[u16], mesh: &mut Mesh) -> Result<()> {
+        let (w, h) = (self.width, self.height);
+        if depth.len() != w * h || mesh.vertices.len() != w * h {
+            return Err(anyhow!("Depth mesher: Expected {} pixels, got a frame of {} for a mesh of {}", w * h, depth.len(), mesh.vertices.len()));
+        }
+
+        // Pixels without depth stay at the sensor and no triangle uses them
+        for ((vertex, ray), &d) in mesh.vertices.iter_mut().zip(&self.rays).zip(depth) {
+            *vertex = *ray * (d as f32 * DEPTH_SCALE);
+        }
+
+        // Overwritten in place, most triangles are the same as in the last frame
+        let (mut indices, mut changed) = match std::mem::take(&mut mesh.indices) {
+            Indices::U32(indices) => (indices, false),
+            Indices::U16(indices) => (indices.into_iter().map(u32::from).collect(), false),
+        };
+        let mut count = 0;
+
+        let mut emit = |triangle: [u32; 3]| {
+            match indices.get_mut(count..count + 3) {
+                Some(old) if old == triangle => {}
+                Some(old) => { old.copy_from_slice(&triangle); changed = true; }
+                None => { indices.extend_from_slice(&triangle); changed = true; }
+            }
+            count += 3;
+        };
+
+        let connected = |a: usize, b: usize, c: usize| {
+            let (da, db, dc) = (depth[a], depth[b], depth[c]);
+            da != 0 && db != 0 && dc != 0 && da.max(db).max(dc) - da.min(db).min(dc) <= self.max_step
+        };
+
+        for y in 0..h.saturating_sub(1) {
+            for x in 0..w.saturating_sub(1) {
+                // Corners of the block, clockwise seen from the sensor with the rays' y pointing down
+                let tl = y * w + x;
+                let (tr, bl, br) = (tl + 1, tl + w, tl + w + 1);
+
+                if connected(tl, tr, br) {
+                    emit([tl as u32, tr as u32, br as u32]);
+                }
+                if connected(tl, br, bl) {
+                    emit([tl as u32, br as u32, bl as u32]);
+                }
+            }
+        }
+
+        changed |= count != indices.len();
+        indices.truncate(count);
+
+        mesh.indices = Indices::new(indices, w * h);
+        Mesh::update_normals(&mesh.vertices, &mesh.indices, &mut mesh.normals);
+
+        // Still set when the last update hasn't been uploaded yet
+        mesh.dirty_indices |= changed;
+        mesh.dirty_vertices = true;
+        mesh.dirty_normals = true;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+
+    use super::DepthMesher;
+
+    /* Pinhole rays of a w x h frame with y pointing down */
+    fn rays(w: usize, h: usize) -> Vec<f32> {
+        (0..w * h).flat_map(|i| [(i % w) as f32 - 1.0, (i / w) as f32 - 1.0, 1.0]).collect()
+    }
+
+    #[test]
+    fn test_flat_frame() {
+        let mesher = DepthMesher::new(3, 3, &rays(3, 3), 50).unwrap();
+        let mesh = mesher.mesh(&[1000; 9]).unwrap();
+
+        assert_eq!(mesh.indices.len(), mesher.max_indices());
+        assert_eq!(mesh.vertices[8], Vec3::new(1.0, 1.0, 1.0));
+
+        // Facing back at the sensor
+        for normal in &mesh.normals {
+            assert!((*normal - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_drops_discontinuities() {
+        let mut mesher = DepthMesher::new(3, 2, &rays(3, 2), 50).unwrap();
+
+        // The right column is far behind the rest
+        let mut mesh = mesher.mesh(&[1000, 1000, 3000, 1000, 1010, 3000]).unwrap();
+        assert_eq!(mesh.indices.iter().collect::<Vec<_>>(), vec![0, 1, 4, 0, 4, 3]);
+
+        // A pixel without depth takes its triangles with it
+        mesher.update(&[1000, 1000, 3000, 0, 1010, 3000], &mut mesh).unwrap();
+        assert_eq!(mesh.indices.iter().collect::<Vec<_>>(), vec![0, 1, 4]);
+        assert_eq!(mesh.vertices[3], Vec3::ZERO);
+
+        // A larger step connects the whole grid
+        mesher.max_step = 2000;
+        mesher.update(&[1000, 1000, 3000, 1000, 1010, 3000], &mut mesh).unwrap();
+        assert_eq!(mesh.indices.len(), mesher.max_indices());
+    }
+
+    #[test]
+    fn test_indices_dirty_on_change() {
+        let mesher = DepthMesher::new(3, 2, &rays(3, 2), 50).unwrap();
+        let mut mesh = mesher.mesh(&[1000; 6]).unwrap();
+
+        // Moving within max_step keeps the triangles, so only the vertices go up again
+        mesh.dirty_indices = false;
+        mesher.update(&[1010; 6], &mut mesh).unwrap();
+        assert!(!mesh.dirty_indices && mesh.dirty_vertices);
+
+        mesher.update(&[1010, 1010, 1010, 0, 1010, 1010], &mut mesh).unwrap();
+        assert!(mesh.dirty_indices);
+    }
+
+    #[test]
+    fn test_rejects_frame_size() {
+        let mesher = DepthMesher::new(3, 2, &rays(3, 2), 50).unwrap();
+        let mut mesh = mesher.mesh(&[1000; 6]).unwrap();
+
+        assert!(mesher.update(&[1000; 4], &mut mesh).is_err());
+    }
+
+    #[test]
+    fn test_rejects_projection_size() {
+        assert!(DepthMesher::new(3, 3, &rays(3, 2), 50).is_err());
+    }
+}
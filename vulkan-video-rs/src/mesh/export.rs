@@ -0,0 +1,84 @@
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::geometry::mat4::Mat4;
+use crate::geometry::vec3::Vec3;
+use crate::geometry::vec4::Vec4;
+
+use super::import::MeshData;
+use super::mesh::Mesh;
+use super::{gltf, obj, ply};
+
+/* Writes a mesh file, the format is picked by extension like mesh::import::load */
+pub fn save(mesh: &Mesh, path: &Path) -> Result<()> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+
+    let bytes = match extension.as_str() {
+        "obj" => {
+            let mut bytes = vec![];
+            obj::write(mesh, &mut bytes)?;
+            bytes
+        }
+        "ply" => ply::write(mesh),
+        "gltf" => gltf::write(mesh, false)?,
+        "glb" => gltf::write(mesh, true)?,
+        _ => return Err(anyhow!("Unknown mesh format '{}'", extension)),
+    };
+
+    let file = std::fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&bytes).and_then(|_| writer.flush()).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/* Meshes baked into one, each placed by its model matrix with its colours multiplied by a tint */
+pub fn merge<'a>(parts: impl IntoIterator<Item = (&'a Mesh, Mat4, Vec3)>) -> Result<Mesh> {
+    let mut data = MeshData::default();
+    let mut colour = vec![];
+    let mut normals = vec![];
+
+    for (mesh, model, tint) in parts {
+        // Normals go through the inverse transpose so they stay perpendicular under non uniform scales
+        let normal_matrix = model.inverse().map(|m| m.transpose()).unwrap_or(model);
+
+        let base = data.vertices.len() as u32;
+        data.vertices.extend(mesh.vertices.iter().map(|&v| model.transform_point(v)));
+        colour.extend(mesh.colour.iter().map(|c| Vec3::new(c.x * tint.x, c.y * tint.y, c.z * tint.z)));
+        normals.extend(mesh.normals.iter().map(|&n| Vec3::norm(&normal_matrix.transform(Vec4::from_vec3(n, 0.0)).xyz())));
+        data.indices.extend(mesh.indices.iter().map(|i| base + i));
+    }
+
+    data.colour = Some(colour);
+    data.normals = Some(normals);
+    data.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::mat4::Mat4;
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::prism::make_prism;
+
+    use super::merge;
+
+    #[test]
+    fn test_merge() {
+        let prism = make_prism(Vec3::ZERO, Vec3::of(1.0), Vec3::of(1.0));
+        let stretch = Mat4::translation(Vec3::X * 10.0) * Mat4::scale(Vec3::new(4.0, 1.0, 1.0));
+
+        let mesh = merge([(&prism, Mat4::IDENT, Vec3::of(1.0)), (&prism, stretch, Vec3::Y)]).unwrap();
+        assert_eq!(mesh.vertices.len(), 2 * prism.vertices.len());
+        assert_eq!(mesh.indices.len(), 2 * prism.indices.len());
+
+        let second = prism.vertices.len();
+        assert_eq!(mesh.vertices[second], stretch.transform_point(prism.vertices[0]));
+        assert_eq!(mesh.indices.get(prism.indices.len()), prism.indices.get(0) + second as u32);
+        assert_eq!(mesh.colour[second], Vec3::Y);
+
+        // Scaling along x doesn't turn the faces, the normals stay the prism's
+        for i in 0..second {
+            assert!((mesh.normals[second + i] - prism.normals[i]).length() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::geometry::vec3::Vec3;
+
+use super::import::{flip_winding, MeshData};
+use super::indices::Indices;
+use super::mesh::Mesh;
+
+const GLB_MAGIC: u32 = 0x46546C67;
+const GLB_JSON: u32 = 0x4E4F534A;
+const GLB_BIN: u32 = 0x004E4942;
+
+/*
+ * glTF 2.0, as .gltf with external or data URI buffers or as .glb. The triangle primitives of every mesh are
+ * merged into one Mesh with POSITION, NORMAL and COLOR_0. Node transforms, materials and textures are ignored.
+ * Buffers given by relative URIs are read from dir.
+ */
+pub fn parse(bytes: &[u8], dir: Option<&Path>) -> Result<Mesh> {
+    let (json, bin) = if bytes.len() >= 4 && read_u32(bytes, 0)? == GLB_MAGIC {
+        split_glb(bytes)?
+    } else {
+        (bytes, None)
+    };
+
+    let doc: Value = serde_json::from_slice(json).context("glTF: Invalid JSON")?;
+
+    let version = doc["asset"]["version"].as_str().unwrap_or("");
+    if !version.starts_with("2.") {
+        return Err(anyhow!("glTF: Unsupported version '{}'", version));
+    }
+
+    let buffers = array(&doc, "buffers").iter().enumerate()
+        .map(|(i, buffer)| load_buffer(buffer, i, bin, dir))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut data = MeshData::default();
+    let mut colour = vec![];
+    let mut normals = vec![];
+
+    for mesh in array(&doc, "meshes") {
+        for primitive in array(mesh, "primitives") {
+            let mode = primitive["mode"].as_u64().unwrap_or(4);
+            if mode != 4 {
+                return Err(anyhow!("glTF: Only triangle primitives are supported, found mode {}", mode));
+            }
+
+            let attributes = &primitive["attributes"];
+            let position = attributes["POSITION"].as_u64().ok_or_else(|| anyhow!("glTF: Primitive has no POSITION"))?;
+            let vertices = vec3s(&read_accessor(&doc, &buffers, position)?);
+
+            let mut indices: Vec<u32> = match primitive["indices"].as_u64() {
+                Some(accessor) => read_indices(&doc, &buffers, accessor)?,
+                None => (0..vertices.len() as u32).collect(),
+            };
+            flip_winding(&mut indices);
+
+            if let Some(&i) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+                return Err(anyhow!("glTF: Index {} is out of range of {} vertices", i, vertices.len()));
+            }
+
+            // Computed per primitive so they don't smooth across primitive boundaries
+            match attributes["NORMAL"].as_u64() {
+                Some(accessor) => normals.extend(vec3s(&read_accessor(&doc, &buffers, accessor)?)),
+                None => normals.extend(Mesh::create_normals(&vertices, &Indices::U32(indices.clone()))),
+            }
+
+            match attributes["COLOR_0"].as_u64() {
+                Some(accessor) => colour.extend(vec3s(&read_accessor(&doc, &buffers, accessor)?)),
+                None => colour.extend(vec![Vec3::of(1.0); vertices.len()]),
+            }
+
+            let base = data.vertices.len() as u32;
+            data.indices.extend(indices.iter().map(|i| base + i));
+            data.vertices.extend(vertices);
+        }
+    }
+
+    data.colour = Some(colour);
+    data.normals = Some(normals);
+    data.build()
+}
+
+/* A .glb when binary, otherwise .gltf JSON with the buffer embedded as a data URI. One mesh in one node. */
+pub fn write(mesh: &Mesh, binary: bool) -> Result<Vec<u8>> {
+    if mesh.vertices.is_empty() {
+        return Err(anyhow!("glTF: Mesh has no vertices"));
+    }
+
+    let mut buffer: Vec<u8> = vec![];
+    let mut views = vec![];
+
+    // Attributes one after the other, each view 4 byte aligned
+    let mut add_view = |bytes: &[u8], target: u32| {
+        views.push(json!({ "buffer": 0, "byteOffset": buffer.len(), "byteLength": bytes.len(), "target": target }));
+        buffer.extend_from_slice(bytes);
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+        views.len() - 1
+    };
+
+    let vec3_bytes = |values: &[Vec3]| values.iter().flat_map(|v| [v.x, v.y, v.z]).flat_map(f32::to_le_bytes).collect::<Vec<u8>>();
+    let positions = add_view(&vec3_bytes(&mesh.vertices), 34962);
+    let normals = add_view(&vec3_bytes(&mesh.normals), 34962);
+    let colours = add_view(&vec3_bytes(&mesh.colour), 34962);
+
+    let mut flipped: Vec<u32> = mesh.indices.iter().collect();
+    flip_winding(&mut flipped);
+
+    let (index_bytes, index_type) = match &mesh.indices {
+        Indices::U16(_) => (flipped.iter().flat_map(|&i| (i as u16).to_le_bytes()).collect::<Vec<u8>>(), 5123),
+        Indices::U32(_) => (flipped.iter().flat_map(|&i| i.to_le_bytes()).collect::<Vec<u8>>(), 5125),
+    };
+    let indices = add_view(&index_bytes, 34963);
+
+    // Viewers need the bounds of the positions
+    let (min, max) = mesh.vertices.iter().fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), v| (
+        [min[0].min(v.x), min[1].min(v.y), min[2].min(v.z)],
+        [max[0].max(v.x), max[1].max(v.y), max[2].max(v.z)],
+    ));
+
+    let count = mesh.vertices.len();
+    let mut doc = json!({
+        "asset": { "version": "2.0", "generator": "vkvisualize" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "meshes": [{
+            "primitives": [{ "attributes": { "POSITION": 0, "NORMAL": 1, "COLOR_0": 2 }, "indices": 3, "mode": 4 }]
+        }],
+        "accessors": [
+            { "bufferView": positions, "componentType": 5126, "count": count, "type": "VEC3", "min": min, "max": max },
+            { "bufferView": normals, "componentType": 5126, "count": count, "type": "VEC3" },
+            { "bufferView": colours, "componentType": 5126, "count": count, "type": "VEC3" },
+            { "bufferView": indices, "componentType": index_type, "count": mesh.indices.len(), "type": "SCALAR" },
+        ],
+        "bufferViews": views,
+        "buffers": [{ "byteLength": buffer.len() }],
+    });
+
+    if !binary {
+        doc["buffers"][0]["uri"] = json!(format!("data:application/octet-stream;base64,{}", encode_base64(&buffer)));
+        return Ok(serde_json::to_vec_pretty(&doc)?);
+    }
+
+    // The JSON chunk is padded with spaces, the binary one already is with zeros
+    let mut json = serde_json::to_vec(&doc)?;
+    json.resize(json.len().next_multiple_of(4), b' ');
+
+    let mut glb = Vec::with_capacity(12 + 8 + json.len() + 8 + buffer.len());
+    for word in [GLB_MAGIC, 2, (12 + 8 + json.len() + 8 + buffer.len()) as u32, json.len() as u32, GLB_JSON] {
+        glb.extend_from_slice(&word.to_le_bytes());
+    }
+    glb.extend_from_slice(&json);
+    glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+    glb.extend_from_slice(&GLB_BIN.to_le_bytes());
+    glb.extend_from_slice(&buffer);
+
+    Ok(glb)
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value[key].as_array().map(|a| a.as_slice()).unwrap_or(&[])
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Result<u32> {
+    let raw = bytes.get(pos..pos + 4).ok_or_else(|| anyhow!("glTF: File ends early"))?;
+    Ok(u32::from_le_bytes(raw.try_into()?))
+}
+
+/* The JSON chunk and the optional binary chunk of a .glb */
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+    let version = read_u32(bytes, 4)?;
+    if version != 2 {
+        return Err(anyhow!("glTF: Unsupported GLB version {}", version));
+    }
+
+    let mut json = None;
+    let mut bin = None;
+    let mut pos = 12;
+
+    while pos < bytes.len() {
+        let length = read_u32(bytes, pos)? as usize;
+        let kind = read_u32(bytes, pos + 4)?;
+        let chunk = bytes.get(pos + 8..pos + 8 + length).ok_or_else(|| anyhow!("glTF: GLB chunk runs past the end of the file"))?;
+
+        match kind {
+            GLB_JSON => json = Some(chunk),
+            GLB_BIN => bin = Some(chunk),
+            _ => {}
+        }
+        pos += 8 + length;
+    }
+
+    Ok((json.ok_or_else(|| anyhow!("glTF: GLB has no JSON chunk"))?, bin))
+}
+
+fn load_buffer(buffer: &Value, index: usize, bin: Option<&[u8]>, dir: Option<&Path>) -> Result<Vec<u8>> {
+    let data = match buffer["uri"].as_str() {
+        Some(uri) if uri.starts_with("data:") => {
+            let (_, encoded) = uri.split_once(";base64,").ok_or_else(|| anyhow!("glTF: Buffer {} data URI is not base64", index))?;
+            decode_base64(encoded).with_context(|| format!("glTF: Buffer {}", index))?
+        }
+        Some(uri) => {
+            let dir = dir.ok_or_else(|| anyhow!("glTF: Buffer {} refers to '{}' without a directory to read it from", index, uri))?;
+            std::fs::read(dir.join(uri)).with_context(|| format!("glTF: Failed to read buffer '{}'", uri))?
+        }
+        None if index == 0 => bin.ok_or_else(|| anyhow!("glTF: Buffer 0 has no URI and there is no GLB binary chunk"))?.to_vec(),
+        None => return Err(anyhow!("glTF: Buffer {} has no URI", index)),
+    };
+
+    let length = buffer["byteLength"].as_u64().unwrap_or(0) as usize;
+    if data.len() < length {
+        return Err(anyhow!("glTF: Buffer {} has {} bytes, expected {}", index, data.len(), length));
+    }
+
+    Ok(data)
+}
+
+/* Accessor elements flattened, normalized integers are brought to 0..1 */
+struct AccessorData {
+    values: Vec<f32>,
+    components: usize,
+}
+
+/* Where an accessor's elements are in its buffer, checked to lie inside it */
+struct AccessorView<'a> {
+    buffer: &'a [u8],
+    start: usize,
+    stride: usize,
+    count: usize,
+    components: usize,
+    component_type: u64,
+    size: usize,
+    normalized: bool,
+}
+
+impl AccessorView<'_> {
+    fn component(&self, element: usize, component: usize) -> &[u8] {
+        let at = self.start + element * self.stride + component * self.size;
+        &self.buffer[at..at + self.size]
+    }
+}
+
+fn accessor_view<'a>(doc: &Value, buffers: &'a [Vec<u8>], index: u64) -> Result<AccessorView<'a>> {
+    let accessor = &doc["accessors"][index as usize];
+    if accessor.is_null() {
+        return Err(anyhow!("glTF: No accessor {}", index));
+    }
+    if !accessor["sparse"].is_null() {
+        return Err(anyhow!("glTF: Sparse accessor {} is not supported", index));
+    }
+
+    let count = accessor["count"].as_u64().ok_or_else(|| anyhow!("glTF: Accessor {} has no count", index))? as usize;
+    let components = match accessor["type"].as_str() {
+        Some("SCALAR") => 1,
+        Some("VEC2") => 2,
+        Some("VEC3") => 3,
+        Some("VEC4") => 4,
+        other => return Err(anyhow!("glTF: Accessor {} has unsupported type {:?}", index, other)),
+    };
+
+    let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+    let size = match component_type {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        _ => return Err(anyhow!("glTF: Accessor {} has unknown component type {}", index, component_type)),
+    };
+    let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+
+    let view = &doc["bufferViews"][accessor["bufferView"].as_u64().ok_or_else(|| anyhow!("glTF: Accessor {} has no buffer view", index))? as usize];
+    let buffer = buffers.get(view["buffer"].as_u64().unwrap_or(u64::MAX) as usize)
+        .ok_or_else(|| anyhow!("glTF: Accessor {} refers to a missing buffer", index))?;
+
+    // The sizes come from the file, so a bad one fails here instead of overflowing or allocating without bound
+    let past_end = || anyhow!("glTF: Accessor {} reads past the end of its buffer", index);
+    let to_usize = |value: u64| usize::try_from(value).map_err(|_| past_end());
+
+    let start = to_usize(view["byteOffset"].as_u64().unwrap_or(0))?
+        .checked_add(to_usize(accessor["byteOffset"].as_u64().unwrap_or(0))?).ok_or_else(past_end)?;
+    // The spec allows strides of 4 to 252 in steps of 4, and they can't be shorter than an element
+    let element_size = size * components;
+    let stride = match &view["byteStride"] {
+        Value::Null => element_size,
+        stride => match stride.as_u64() {
+            Some(stride) if stride % 4 == 0 && (element_size as u64..=252).contains(&stride) => stride as usize,
+            _ => return Err(anyhow!("glTF: Accessor {} has an invalid byteStride of {}", index, stride)),
+        },
+    };
+
+    // Every element takes at least a byte, so this bounds the allocations below before the exact check
+    if count > buffer.len() {
+        return Err(past_end());
+    }
+
+    if count > 0 {
+        let end = stride.checked_mul(count - 1)
+            .and_then(|offset| offset.checked_add(element_size))
+            .and_then(|length| length.checked_add(start))
+            .ok_or_else(past_end)?;
+
+        if end > buffer.len() {
+            return Err(past_end());
+        }
+    }
+
+    Ok(AccessorView { buffer, start, stride, count, components, component_type, size, normalized })
+}
+
+fn read_accessor(doc: &Value, buffers: &[Vec<u8>], index: u64) -> Result<AccessorData> {
+    let view = accessor_view(doc, buffers, index)?;
+
+    let max = match view.component_type {
+        5120 => i8::MAX as f32,
+        5121 => u8::MAX as f32,
+        5122 => i16::MAX as f32,
+        5123 => u16::MAX as f32,
+        5125 => u32::MAX as f32,
+        _ => 1.0,
+    };
+
+    let mut values = Vec::with_capacity(view.count * view.components);
+    for element in 0..view.count {
+        for component in 0..view.components {
+            let b = view.component(element, component);
+
+            let value = match view.component_type {
+                5120 => b[0] as i8 as f32,
+                5121 => b[0] as f32,
+                5122 => i16::from_le_bytes([b[0], b[1]]) as f32,
+                5123 => u16::from_le_bytes([b[0], b[1]]) as f32,
+                5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+                _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            };
+            values.push(if view.normalized { (value / max).max(-1.0) } else { value });
+        }
+    }
+
+    Ok(AccessorData { values, components: view.components })
+}
+
+/* Indices are unsigned integers, read as they are since f32 can't hold every u32 */
+fn read_indices(doc: &Value, buffers: &[Vec<u8>], index: u64) -> Result<Vec<u32>> {
+    let view = accessor_view(doc, buffers, index)?;
+    if view.components != 1 {
+        return Err(anyhow!("glTF: Index accessor {} is not SCALAR", index));
+    }
+
+    (0..view.count).map(|element| {
+        let b = view.component(element, 0);
+
+        match view.component_type {
+            5121 => Ok(b[0] as u32),
+            5123 => Ok(u16::from_le_bytes([b[0], b[1]]) as u32),
+            5125 => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            other => Err(anyhow!("glTF: Index accessor {} has component type {}, expected an unsigned integer", index, other)),
+        }
+    }).collect()
+}
+
+/* The first three components of each element, COLOR_0 can be VEC4 */
+fn vec3s(data: &AccessorData) -> Vec<Vec3> {
+    data.values.chunks(data.components)
+        .map(|c| Vec3::new(c[0], c.get(1).copied().unwrap_or(0.0), c.get(2).copied().unwrap_or(0.0)))
+        .collect()
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            encoded.push(if i <= chunk.len() { ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char } else { '=' });
+        }
+    }
+
+    encoded
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut bits: u32 = 0;
+    let mut count = 0;
+
+    for c in encoded.bytes().take_while(|&c| c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(anyhow!("Invalid base64 character '{}'", c as char)),
+        };
+
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+
+    use crate::mesh::prism::make_prism;
+
+    use super::{decode_base64, encode_base64, parse, read_indices, write};
+
+    /* One triangle, positions then u16 indices padded to 4 bytes */
+    fn triangle_buffer() -> Vec<u8> {
+        let mut bytes = vec![];
+        for x in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        for i in [0u16, 1, 2, 0] {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn triangle_json(uri: &str) -> String {
+        format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ {uri} "byteLength": 44 }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+            ],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }},
+                {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+            ],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}] }}]
+        }}"#)
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64(&encode_base64(&triangle_buffer())).unwrap(), triangle_buffer());
+        assert!(decode_base64("a*b").is_err());
+    }
+
+    #[test]
+    fn test_data_uri() {
+        let uri = format!(r#""uri": "data:application/octet-stream;base64,{}","#, encode_base64(&triangle_buffer()));
+        let mesh = parse(triangle_json(&uri).as_bytes(), None).unwrap();
+
+        assert_eq!(mesh.vertices, vec![Vec3::ZERO, Vec3::X, Vec3::Y]);
+        assert_eq!(mesh.indices.iter().collect::<Vec<_>>(), vec![0, 2, 1]);
+        assert_eq!(mesh.normals, vec![Vec3::Z; 3]);
+    }
+
+    #[test]
+    fn test_glb() {
+        let mut json = triangle_json("").into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let bin = triangle_buffer();
+
+        let mut glb = vec![];
+        for word in [super::GLB_MAGIC, 2, (12 + 8 + json.len() + 8 + bin.len()) as u32, json.len() as u32, super::GLB_JSON] {
+            glb.extend_from_slice(&word.to_le_bytes());
+        }
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&super::GLB_BIN.to_le_bytes());
+        glb.extend_from_slice(&bin);
+
+        let mesh = parse(&glb, None).unwrap();
+        assert_eq!(mesh.vertices.len(), 3);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let prism = make_prism(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 2.0, 0.5), Vec3::new(0.25, 0.5, 1.0));
+
+        for binary in [false, true] {
+            let mesh = parse(&write(&prism, binary).unwrap(), None).unwrap();
+            assert_eq!(mesh.vertices, prism.vertices);
+            assert_eq!(mesh.colour, prism.colour);
+            assert_eq!(mesh.normals, prism.normals);
+            assert_eq!(mesh.indices, prism.indices);
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(parse(b"{ not json", None).is_err());
+        assert!(parse(br#"{ "asset": { "version": "1.0" } }"#, None).is_err());
+
+        // The buffer is a file next to the .gltf but there is no directory to look in
+        assert!(parse(triangle_json(r#""uri": "triangle.bin","#).as_bytes(), None).is_err());
+
+        // Too short for the accessors
+        let uri = format!(r#""uri": "data:application/octet-stream;base64,{}","#, encode_base64(&triangle_buffer()[..20]));
+        assert!(parse(triangle_json(&uri).as_bytes(), None).is_err());
+
+        // Counts and strides that overflow when multiplied out
+        let uri = format!(r#""uri": "data:application/octet-stream;base64,{}","#, encode_base64(&triangle_buffer()));
+        let huge = triangle_json(&uri).replace(r#""count": 3, "type": "VEC3""#, r#""count": 18446744073709551615, "type": "VEC3""#);
+        assert!(parse(huge.as_bytes(), None).is_err());
+        let huge = triangle_json(&uri).replace(r#""byteOffset": 0, "byteLength": 36"#, r#""byteOffset": 0, "byteLength": 36, "byteStride": 9223372036854775807"#);
+        assert!(parse(huge.as_bytes(), None).is_err());
+
+        // A zero stride would keep every element in bounds however many there are
+        let huge = triangle_json(&uri)
+            .replace(r#""byteOffset": 0, "byteLength": 36"#, r#""byteOffset": 0, "byteLength": 36, "byteStride": 0"#)
+            .replace(r#""count": 3, "type": "VEC3""#, r#""count": 18446744073709551615, "type": "VEC3""#);
+        assert!(parse(huge.as_bytes(), None).is_err());
+    }
+
+    #[test]
+    fn test_u32_indices() {
+        // Past 2^24, where f32 starts skipping integers
+        let index = (1u32 << 24) + 1;
+        let doc = serde_json::json!({
+            "bufferViews": [{ "buffer": 0, "byteOffset": 0, "byteLength": 4 }],
+            "accessors": [{ "bufferView": 0, "componentType": 5125, "count": 1, "type": "SCALAR" }],
+        });
+
+        assert_eq!(read_indices(&doc, &[index.to_le_bytes().to_vec()], 0).unwrap(), vec![index]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::geometry::vec3::Vec3;
+
+use super::indices::Indices;
+use super::mesh::Mesh;
+use super::{gltf, obj, ply};
+
+/* Reads a mesh file, the format is picked by extension: .obj, .ply, .gltf or .glb */
+pub fn load(path: &Path) -> Result<Mesh> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let mesh = match extension.as_str() {
+        "obj" => obj::parse(std::str::from_utf8(&bytes)?),
+        "ply" => ply::parse(&bytes),
+        "gltf" | "glb" => gltf::parse(&bytes, path.parent()),
+        _ => Err(anyhow!("Unknown mesh format '{}'", extension)),
+    };
+
+    mesh.with_context(|| format!("Failed to load {}", path.display()))
+}
+
+/* What a loader read from the file, colours and normals are optional */
+#[derive(Default)]
+pub struct MeshData {
+    pub vertices: Vec<Vec3>,
+    pub colour: Option<Vec<Vec3>>,
+    pub normals: Option<Vec<Vec3>>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /* Checks the data fits together, missing colours are white and missing normals are computed */
+    pub fn build(self) -> Result<Mesh> {
+        let MeshData { vertices, colour, normals, indices } = self;
+
+        if vertices.is_empty() {
+            return Err(anyhow!("Mesh has no vertices"));
+        }
+
+        if indices.len() % 3 != 0 {
+            return Err(anyhow!("{} indices is not a whole number of triangles", indices.len()));
+        }
+
+        if let Some(&i) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+            return Err(anyhow!("Index {} is out of range of {} vertices", i, vertices.len()));
+        }
+
+        for (name, attribute) in [("colours", &colour), ("normals", &normals)] {
+            if let Some(attribute) = attribute {
+                if attribute.len() != vertices.len() {
+                    return Err(anyhow!("{} {} for {} vertices", attribute.len(), name, vertices.len()));
+                }
+            }
+        }
+
+        let indices = Indices::new(indices, vertices.len());
+        let colour = colour.unwrap_or_else(|| vec![Vec3::of(1.0); vertices.len()]);
+        let normals = normals.unwrap_or_else(|| Mesh::create_normals(&vertices, &indices));
+
+        // Middle of the bounding box, the point the rotate_* functions turn around
+        let (min, max) = vertices.iter().fold((vertices[0], vertices[0]), |(min, max), v| (
+            Vec3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z)),
+            Vec3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z)),
+        ));
+        let center = (min + max) * 0.5;
+
+        Ok(Mesh {
+            center,
+            vertices,
+            colour,
+            normals,
+            indices,
+            dirty_vertices: true,
+            dirty_colour: true,
+            dirty_normals: true,
+            dirty_indices: true,
+        })
+    }
+}
+
+/*
+ * Splits a polygon into a fan of triangles around its first corner.
+ * The formats wind front faces counter clockwise, the triangles are turned around for the clockwise front face.
+ */
+pub fn triangulate(corners: &[u32], indices: &mut Vec<u32>) {
+    for i in 1..corners.len().saturating_sub(1) {
+        indices.extend_from_slice(&[corners[0], corners[i + 1], corners[i]]);
+    }
+}
+
+/* Between the counter clockwise triangles of the formats and the clockwise ones of Mesh, either way */
+pub fn flip_winding(indices: &mut [u32]) {
+    for triangle in indices.chunks_exact_mut(3) {
+        triangle.swap(1, 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+
+    use super::{triangulate, MeshData};
+
+    #[test]
+    fn test_build_fills_in() {
+        let data = MeshData {
+            vertices: vec![Vec3::ZERO, Vec3::X, Vec3::new(1.0, 2.0, 0.0)],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        };
+
+        // Clockwise seen from -z, so that is the front
+        let mesh = data.build().unwrap();
+        assert_eq!(mesh.center, Vec3::new(0.5, 1.0, 0.0));
+        assert_eq!(mesh.colour, vec![Vec3::of(1.0); 3]);
+        assert_eq!(mesh.normals, vec![Vec3::new(0.0, 0.0, -1.0); 3]);
+    }
+
+    #[test]
+    fn test_build_rejects() {
+        let vertices = vec![Vec3::ZERO, Vec3::X, Vec3::Y];
+        assert!(MeshData { vertices: vertices.clone(), indices: vec![0, 1], ..Default::default() }.build().is_err());
+        assert!(MeshData { vertices: vertices.clone(), indices: vec![0, 1, 3], ..Default::default() }.build().is_err());
+        assert!(MeshData { vertices, indices: vec![0, 1, 2], normals: Some(vec![Vec3::Z]), ..Default::default() }.build().is_err());
+    }
+
+    #[test]
+    fn test_triangulate_fan() {
+        let mut indices = vec![];
+        triangulate(&[4, 5, 6, 7], &mut indices);
+        assert_eq!(indices, vec![4, 6, 5, 4, 7, 6]);
+    }
+}
@@ -0,0 +1,99 @@
+use ash::vk;
+
+/*
+ * Index data of a mesh, u16 while every vertex can be addressed with one and u32 after that.
+ * 0xFFFF is left unused in u16 data so it stays free for primitive restart.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    /* The smallest type that addresses vertex_count vertices, every index has to be below it */
+    pub fn new(indices: Vec<u32>, vertex_count: usize) -> Self {
+        debug_assert!(indices.iter().all(|&i| (i as usize) < vertex_count), "Index out of range of {} vertices", vertex_count);
+
+        if Self::fits_u16(vertex_count) {
+            Indices::U16(indices.into_iter().map(|i| i as u16).collect())
+        } else {
+            Indices::U32(indices)
+        }
+    }
+
+    pub fn fits_u16(vertex_count: usize) -> bool {
+        vertex_count <= u16::MAX as usize
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, i: usize) -> u32 {
+        match self {
+            Indices::U16(indices) => indices[i] as u32,
+            Indices::U32(indices) => indices[i],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /* Bytes per index */
+    pub fn stride(&self) -> usize {
+        match self {
+            Indices::U16(_) => std::mem::size_of::<u16>(),
+            Indices::U32(_) => std::mem::size_of::<u32>(),
+        }
+    }
+
+    /* In bytes */
+    pub fn size(&self) -> usize {
+        match self {
+            Indices::U16(indices) => std::mem::size_of_val(&indices[..]),
+            Indices::U32(indices) => std::mem::size_of_val(&indices[..]),
+        }
+    }
+
+    pub fn index_type(&self) -> vk::IndexType {
+        match self {
+            Indices::U16(_) => vk::IndexType::UINT16,
+            Indices::U32(_) => vk::IndexType::UINT32,
+        }
+    }
+}
+
+impl Default for Indices {
+    fn default() -> Self {
+        Indices::U16(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+
+    use super::Indices;
+
+    #[test]
+    fn test_chooses_type() {
+        let small = Indices::new(vec![0, 1, 2], 3);
+        assert_eq!(small, Indices::U16(vec![0, 1, 2]));
+        assert_eq!(small.size(), 6);
+
+        // A triangulated 640x480 depth frame
+        let large = Indices::new(vec![0, 1, 640 * 480 - 1], 640 * 480);
+        assert_eq!(large.index_type(), vk::IndexType::UINT32);
+        assert_eq!(large.get(2), 640 * 480 - 1);
+        assert_eq!(large.size(), 12);
+    }
+}
@@ -1,5 +1,14 @@
 use crate::geometry::vec3::{self, Vec3};
 
+use super::indices::Indices;
+
+/* Normals of generated meshes, Flat gives every triangle its own vertices */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shading {
+    Smooth,
+    Flat,
+}
+
 pub struct Mesh {
     pub center: Vec3,
 
@@ -12,13 +21,73 @@ pub struct Mesh {
     pub normals: Vec<Vec3>,
     pub dirty_normals: bool,
 
-    pub indices: Vec<u16>,
+    pub indices: Indices,
     pub dirty_indices: bool,
 }
 
 
 impl Mesh {
 
+    /*
+     * For generated meshes, every vertex gets col. The triangles wind clockwise seen from the front like make_prism,
+     * the pipelines cull the other side. The smooth normals are used as given.
+     */
+    pub fn from_parts(center: Vec3, vertices: Vec<Vec3>, normals: Vec<Vec3>, indices: Vec<u32>, col: Vec3, shading: Shading) -> Mesh {
+        let vertex_count = vertices.len();
+
+        let mut mesh = Mesh {
+            center,
+            colour: vec![col; vertex_count],
+            indices: Indices::new(indices, vertex_count),
+            vertices,
+            normals,
+            dirty_vertices: true,
+            dirty_colour: true,
+            dirty_normals: true,
+            dirty_indices: true,
+        };
+
+        if shading == Shading::Flat {
+            mesh.flatten();
+        }
+
+        mesh
+    }
+
+    /* Gives every triangle its own vertices with the triangle's normal, so the faces show */
+    pub fn flatten(&mut self) {
+        let mut vertices = Vec::with_capacity(self.indices.len());
+        let mut colour = Vec::with_capacity(self.indices.len());
+        let mut normals = Vec::with_capacity(self.indices.len());
+
+        let indices: Vec<u32> = self.indices.iter().collect();
+        for triangle in indices.chunks_exact(3) {
+            let [v0, v1, v2] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize]);
+            let n = Vec3::norm(&Self::face_normal(v0, v1, v2));
+
+            for &i in triangle {
+                vertices.push(self.vertices[i as usize]);
+                colour.push(self.colour[i as usize]);
+                normals.push(n);
+            }
+        }
+
+        self.indices = Indices::new((0..vertices.len() as u32).collect(), vertices.len());
+        self.vertices = vertices;
+        self.colour = colour;
+        self.normals = normals;
+
+        self.dirty_vertices = true;
+        self.dirty_colour = true;
+        self.dirty_normals = true;
+        self.dirty_indices = true;
+    }
+
+    /* Points out of the front, the side the triangle winds clockwise on. The length is twice the area. */
+    pub fn face_normal(v0: Vec3, v1: Vec3, v2: Vec3) -> Vec3 {
+        Vec3::cross(v2 - v0, v1 - v0)
+    }
+
     pub fn translate(&mut self, t: Vec3) {
         self.center += t;
         for i in 0..self.vertices.len() {
@@ -59,9 +128,9 @@ impl Mesh {
             let [x, y, z] = [v.x - cn.x, v.y - cn.y, v.z - cn.z];
 
             self.vertices[i] = Vec3 {
-                x: cn.x + x * c - z * s,
+                x: cn.x + x * c + z * s,
                 y: cn.y + y,
-                z: cn.z + x * s + z * c,
+                z: cn.z - x * s + z * c,
             };
         }
 
@@ -80,8 +149,8 @@ impl Mesh {
 
             self.vertices[i] = Vec3 {
                 x: cn.x + x,
-                y: cn.y + z * s + y * c,
-                z: cn.z + z * c - y * s,
+                y: cn.y + y * c - z * s,
+                z: cn.z + y * s + z * c,
             };
         }
 
@@ -106,7 +175,7 @@ impl Mesh {
     }
 
     pub fn size_ind(&self) -> usize {
-        std::mem::size_of_val(&self.indices[..])
+        self.indices.size()
     }
 
     pub fn size_col(&self) -> usize {
@@ -117,7 +186,7 @@ impl Mesh {
         std::mem::size_of_val(&self.normals[..])
     }
 
-    pub fn create_normals(vertices: &Vec<Vec3>, indices: &Vec<u16>) -> Vec<Vec3> {
+    pub fn create_normals(vertices: &Vec<Vec3>, indices: &Indices) -> Vec<Vec3> {
 
         let mut normals = vec![];
         normals.resize(vertices.len(), Vec3::X);
@@ -129,23 +198,25 @@ impl Mesh {
     }
 
 
-    pub fn update_normals(vertices: &Vec<Vec3>, indices: &Vec<u16>, normals: &mut Vec<Vec3>) {
+    pub fn update_normals(vertices: &Vec<Vec3>, indices: &Indices, normals: &mut Vec<Vec3>) {
 
         let num_verts: usize = vertices.len();
         let num_tris : usize = indices.len() / 3;
 
-        normals.resize(num_verts, Vec3::X);
+        // Sums of the face normals around each vertex, so start from nothing
+        normals.clear();
+        normals.resize(num_verts, Vec3::ZERO);
 
         for t in 0..num_tris {
-            let i0 = indices[t * 3 + 0];
-            let i1 = indices[t * 3 + 1];
-            let i2 = indices[t * 3 + 2];
+            let i0 = indices.get(t * 3 + 0);
+            let i1 = indices.get(t * 3 + 1);
+            let i2 = indices.get(t * 3 + 2);
 
             let v0 = vertices[i0 as usize];
             let v1 = vertices[i1 as usize];
             let v2 = vertices[i2 as usize];
 
-            let n  = Vec3::cross(v1 - v0, v2 - v0);
+            let n  = Self::face_normal(v0, v1, v2);
 
             normals[i0 as usize] += n;
             normals[i1 as usize] += n;
@@ -159,3 +230,50 @@ impl Mesh {
 
 
 }
+
+#[cfg(test)]
+pub mod tests {
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::prism::make_prism;
+
+    use super::Mesh;
+
+    /* Every normal points along outward(vertex), away from the inside of the shape */
+    pub fn assert_outward(mesh: &Mesh, outward: impl Fn(Vec3) -> Vec3) {
+        for (v, n) in mesh.vertices.iter().zip(&mesh.normals) {
+            assert!(Vec3::dot(*n, outward(*v)) > 0.0, "Normal {} at {} points inwards", n, v);
+            assert!((n.length() - 1.0).abs() < 1e-4);
+        }
+    }
+
+    /* Every triangle's front, where it winds clockwise, is on the side its normals point to */
+    pub fn assert_front_faces_out(mesh: &Mesh) {
+        let indices: Vec<usize> = mesh.indices.iter().map(|i| i as usize).collect();
+
+        for t in indices.chunks_exact(3) {
+            let face = Mesh::face_normal(mesh.vertices[t[0]], mesh.vertices[t[1]], mesh.vertices[t[2]]);
+            let normals = mesh.normals[t[0]] + mesh.normals[t[1]] + mesh.normals[t[2]];
+            assert!(face.length() > 1e-9, "Triangle {:?} has no area", t);
+            assert!(Vec3::dot(face, normals) > 0.0, "Triangle {:?} faces away from its normals", t);
+        }
+    }
+
+    #[test]
+    fn test_prism_faces_out() {
+        let prism = make_prism(Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 2.0, 3.0), Vec3::of(1.0));
+        assert_outward(&prism, |v| v - Vec3::X);
+        assert_front_faces_out(&prism);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut prism = make_prism(Vec3::ZERO, Vec3::of(1.0), Vec3::of(1.0));
+        prism.flatten();
+
+        assert_eq!(prism.vertices.len(), 36);
+        assert_front_faces_out(&prism);
+
+        // The corners of a cube point out diagonally when smooth, flat they point straight out of their face
+        assert!(prism.normals.iter().all(|n| n.x.abs() + n.y.abs() + n.z.abs() == 1.0));
+    }
+}
@@ -1,7 +1,21 @@
 pub mod mesh;
+pub mod indices;
 pub mod rect;
 pub mod cube;
 pub mod prism;
+pub mod sphere;
+pub mod cylinder;
+pub mod plane;
+pub mod torus;
+pub mod arrow;
+pub mod axes;
+pub mod import;
+pub mod export;
+pub mod depth_mesher;
+pub mod obj;
+pub mod ply;
+pub mod gltf;
 
 pub use mesh::*;
+pub use indices::*;
 pub use rect::*;
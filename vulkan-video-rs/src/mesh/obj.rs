@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+
+use crate::geometry::vec3::Vec3;
+
+use super::import::{flip_winding, triangulate, MeshData};
+use super::mesh::Mesh;
+
+/*
+ * Wavefront OBJ: v, vn and f lines, everything else (texture coordinates, groups, materials) is skipped.
+ * Vertex colours are read from the common "v x y z r g b" extension. Polygons are split into triangles.
+ */
+pub fn parse(text: &str) -> Result<Mesh> {
+    let mut positions: Vec<Vec3> = vec![];
+    let mut colours: Vec<Option<Vec3>> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+
+    let mut data = MeshData::default();
+    let mut data_colour = vec![];
+    let mut data_normals = vec![];
+    let mut all_normals = true;
+
+    // Faces index positions and normals separately, each distinct pair becomes one vertex
+    let mut vertex_ids: HashMap<(usize, Option<usize>), u32> = HashMap::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line_num = n + 1;
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+
+        match words.next() {
+            Some("v") => {
+                let values = parse_floats(words, line_num)?;
+                match values.len() {
+                    3 | 4 => colours.push(None),
+                    6 => colours.push(Some(Vec3::new(values[3], values[4], values[5]))),
+                    count => return Err(anyhow!("OBJ line {}: Vertex has {} values", line_num, count)),
+                }
+                positions.push(Vec3::new(values[0], values[1], values[2]));
+            }
+            Some("vn") => {
+                let values = parse_floats(words, line_num)?;
+                if values.len() != 3 {
+                    return Err(anyhow!("OBJ line {}: Normal has {} values", line_num, values.len()));
+                }
+                normals.push(Vec3::new(values[0], values[1], values[2]));
+            }
+            Some("f") => {
+                let mut corners = vec![];
+
+                for word in words {
+                    let mut parts = word.split('/');
+                    let position = resolve(parts.next().unwrap_or(""), positions.len(), line_num)?;
+                    let normal = match parts.nth(1) {
+                        Some(part) if !part.is_empty() => Some(resolve(part, normals.len(), line_num)?),
+                        _ => None,
+                    };
+
+                    let id = *vertex_ids.entry((position, normal)).or_insert_with(|| {
+                        data.vertices.push(positions[position]);
+                        data_colour.push(colours[position].unwrap_or(Vec3::of(1.0)));
+                        data_normals.push(normal.map(|n| normals[n]).unwrap_or(Vec3::ZERO));
+                        all_normals &= normal.is_some();
+                        (data.vertices.len() - 1) as u32
+                    });
+                    corners.push(id);
+                }
+
+                if corners.len() < 3 {
+                    return Err(anyhow!("OBJ line {}: Face has {} corners", line_num, corners.len()));
+                }
+                triangulate(&corners, &mut data.indices);
+            }
+            _ => {}
+        }
+    }
+
+    if colours.iter().any(|c| c.is_some()) {
+        data.colour = Some(data_colour);
+    }
+
+    // Normals are all or nothing, a face without them gets them computed for the whole mesh
+    if all_normals {
+        data.normals = Some(data_normals);
+    }
+
+    data.build()
+}
+
+/* Vertex colours go on the v lines, every face corner refers to the vertex and normal of the same number */
+pub fn write(mesh: &Mesh, out: &mut impl Write) -> Result<()> {
+    writeln!(out, "# {} vertices, {} triangles", mesh.vertices.len(), mesh.indices.len() / 3)?;
+
+    for (v, c) in mesh.vertices.iter().zip(&mesh.colour) {
+        writeln!(out, "v {} {} {} {} {} {}", v.x, v.y, v.z, c.x, c.y, c.z)?;
+    }
+
+    for n in &mesh.normals {
+        writeln!(out, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+
+    let mut indices: Vec<u32> = mesh.indices.iter().collect();
+    flip_winding(&mut indices);
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+        writeln!(out, "f {a}//{a} {b}//{b} {c}//{c}")?;
+    }
+
+    Ok(())
+}
+
+fn parse_floats<'a>(words: impl Iterator<Item = &'a str>, line_num: usize) -> Result<Vec<f32>> {
+    words
+        .map(|w| w.parse::<f32>().map_err(|_| anyhow!("OBJ line {}: '{}' is not a number", line_num, w)))
+        .collect()
+}
+
+/* OBJ indices start at 1, negative ones count back from the last element so far */
+fn resolve(word: &str, count: usize, line_num: usize) -> Result<usize> {
+    let index: i64 = word.parse().map_err(|_| anyhow!("OBJ line {}: '{}' is not an index", line_num, word))?;
+
+    let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(anyhow!("OBJ line {}: Index {} is out of range of {} elements", line_num, index, count));
+    }
+
+    Ok(resolved as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::prism::make_prism;
+
+    use super::{parse, write};
+
+    const QUAD: &str = "
+        # A unit quad facing +z
+        o quad
+        v 0 0 0 1 0 0
+        v 1 0 0 0 1 0
+        v 1 1 0 0 0 1
+        v 0 1 0 1 1 1
+        vt 0 0
+        f 1/1 2/1 3/1 4/1
+    ";
+
+    #[test]
+    fn test_quad() {
+        let mesh = parse(QUAD).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices.iter().collect::<Vec<_>>(), vec![0, 2, 1, 0, 3, 2]);
+        assert_eq!(mesh.colour[1], Vec3::Y);
+        assert_eq!(mesh.normals, vec![Vec3::Z; 4]);
+    }
+
+    #[test]
+    fn test_normals_split_vertices() {
+        let text = "
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            v 0 0 1
+            vn 0 0 1
+            vn 1 0 0
+            f 1//1 2//1 3//1
+            f -4//2 -2//2 -1//2
+        ";
+
+        // The first corner is shared by both faces but with different normals
+        let mesh = parse(text).unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.normals[3], Vec3::X);
+        assert_eq!(mesh.colour, vec![Vec3::of(1.0); 6]);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let prism = make_prism(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 2.0, 0.5), Vec3::new(0.25, 0.5, 1.0));
+
+        let mut bytes = vec![];
+        write(&prism, &mut bytes).unwrap();
+        let mesh = parse(std::str::from_utf8(&bytes).unwrap()).unwrap();
+
+        // Vertices are numbered in the order the faces use them, so compare the corners
+        assert_eq!(mesh.indices.len(), prism.indices.len());
+        for (i, j) in mesh.indices.iter().zip(prism.indices.iter()) {
+            let (i, j) = (i as usize, j as usize);
+            assert_eq!((mesh.vertices[i], mesh.colour[i], mesh.normals[i]), (prism.vertices[j], prism.colour[j], prism.normals[j]));
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(parse("v 0 0\n").is_err());
+        assert!(parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n").is_err());
+        assert!(parse("v 0 0 0\nv 1 0 0\nf 1 2\n").is_err());
+        assert!(parse("v 0 zero 0\n").is_err());
+    }
+}
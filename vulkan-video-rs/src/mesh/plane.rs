@@ -0,0 +1,55 @@
+use crate::geometry::vec3::Vec3;
+
+use super::mesh::{Mesh, Shading};
+
+/*
+ * Flat grid in the xz plane centred on location, facing +y. Split into nx by nz cells of two triangles each,
+ * which gives (nx + 1) * (nz + 1) vertices. Flat and smooth shading are the same here.
+ */
+pub fn make_plane_grid(location: Vec3, width: f32, depth: f32, nx: usize, nz: usize, col: Vec3) -> Mesh {
+    let (nx, nz) = (nx.max(1), nz.max(1));
+    let corner = location - Vec3::new(width / 2.0, 0.0, depth / 2.0);
+
+    let mut vertices = Vec::with_capacity((nx + 1) * (nz + 1));
+    for j in 0..=nz {
+        for i in 0..=nx {
+            vertices.push(corner + Vec3::new(width * i as f32 / nx as f32, 0.0, depth * j as f32 / nz as f32));
+        }
+    }
+
+    let mut indices = Vec::with_capacity(6 * nx * nz);
+    let row = (nx + 1) as u32;
+
+    for j in 0..nz as u32 {
+        for i in 0..nx as u32 {
+            // Going along x then back along z is clockwise seen from above
+            let a = j * row + i;
+            let (b, c, d) = (a + 1, a + row, a + row + 1);
+            indices.extend_from_slice(&[a, b, c, b, d, c]);
+        }
+    }
+
+    let normals = vec![Vec3::Y; vertices.len()];
+    Mesh::from_parts(location, vertices, normals, indices, col, Shading::Smooth)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::mesh::tests::{assert_front_faces_out, assert_outward};
+
+    use super::make_plane_grid;
+
+    #[test]
+    fn test_plane_grid() {
+        let plane = make_plane_grid(Vec3::new(0.0, -1.0, 0.0), 4.0, 2.0, 4, 3, Vec3::of(1.0));
+
+        assert_eq!(plane.vertices.len(), 5 * 4);
+        assert_eq!(plane.indices.len(), 6 * 4 * 3);
+        assert_eq!(plane.vertices[0], Vec3::new(-2.0, -1.0, -1.0));
+        assert_eq!(plane.vertices[19], Vec3::new(2.0, -1.0, 1.0));
+
+        assert_outward(&plane, |_| Vec3::Y);
+        assert_front_faces_out(&plane);
+    }
+}
@@ -0,0 +1,398 @@
+use anyhow::{anyhow, Result};
+
+use crate::geometry::vec3::Vec3;
+
+use super::import::{flip_winding, triangulate, MeshData};
+use super::mesh::Mesh;
+
+/*
+ * PLY in ascii or binary: x, y, z and the optional nx, ny, nz and red, green, blue of the vertex element,
+ * and the vertex_indices list of the face element. Other elements and properties are read past.
+ */
+pub fn parse(bytes: &[u8]) -> Result<Mesh> {
+    let (header, body_start) = Header::parse(bytes)?;
+
+    let mut body = match header.format {
+        Format::Ascii => Body::Ascii(std::str::from_utf8(&bytes[body_start..])?.split_ascii_whitespace()),
+        Format::Binary { big_endian } => Body::Binary { bytes: &bytes[body_start..], pos: 0, big_endian },
+    };
+
+    let mut data = MeshData::default();
+    let mut colour = vec![];
+    let mut normals = vec![];
+
+    for element in &header.elements {
+        let find = |name: &str| element.properties.iter().position(|p| p.name == name);
+
+        match element.name.as_str() {
+            "vertex" => {
+                let position = [find("x"), find("y"), find("z")];
+                let normal = [find("nx"), find("ny"), find("nz")];
+                let rgb = [find("red"), find("green"), find("blue")];
+
+                if position.iter().any(|p| p.is_none()) {
+                    return Err(anyhow!("PLY: Vertices need x, y and z"));
+                }
+
+                let mut values = vec![0.0; element.properties.len()];
+                for _ in 0..element.count {
+                    for (i, property) in element.properties.iter().enumerate() {
+                        values[i] = match property.kind {
+                            PropertyKind::Scalar(ty) => ty.read_unit(&mut body, property.name.as_str())?,
+                            PropertyKind::List(..) => { body.skip_list(property)?; 0.0 }
+                        };
+                    }
+
+                    let get = |ids: [Option<usize>; 3]| ids.map(|i| values[i.unwrap()] as f32);
+                    data.vertices.push(Vec3::from_slice(get(position)));
+
+                    if normal.iter().all(|n| n.is_some()) {
+                        normals.push(Vec3::from_slice(get(normal)));
+                    }
+                    if rgb.iter().all(|c| c.is_some()) {
+                        colour.push(Vec3::from_slice(get(rgb)));
+                    }
+                }
+            }
+            "face" => {
+                let list = find("vertex_indices").or_else(|| find("vertex_index"))
+                    .ok_or_else(|| anyhow!("PLY: Faces need a vertex_indices list"))?;
+
+                for _ in 0..element.count {
+                    for (i, property) in element.properties.iter().enumerate() {
+                        match property.kind {
+                            PropertyKind::List(count_ty, item_ty) if i == list => {
+                                let count = count_ty.read(&mut body)? as usize;
+                                let corners = (0..count)
+                                    .map(|_| item_ty.read(&mut body).map(|v| v as u32))
+                                    .collect::<Result<Vec<_>>>()?;
+
+                                if corners.len() < 3 {
+                                    return Err(anyhow!("PLY: Face has {} corners", corners.len()));
+                                }
+                                triangulate(&corners, &mut data.indices);
+                            }
+                            PropertyKind::List(..) => body.skip_list(property)?,
+                            PropertyKind::Scalar(ty) => { ty.read(&mut body)?; }
+                        }
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        match property.kind {
+                            PropertyKind::List(..) => body.skip_list(property)?,
+                            PropertyKind::Scalar(ty) => { ty.read(&mut body)?; }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if !colour.is_empty() {
+        data.colour = Some(colour);
+    }
+    if !normals.is_empty() {
+        data.normals = Some(normals);
+    }
+
+    data.build()
+}
+
+/* Binary little endian with float positions and normals and uchar colours, the layout most tools read */
+pub fn write(mesh: &Mesh) -> Vec<u8> {
+    let header = format!(
+        "ply\nformat binary_little_endian 1.0\ncomment vkvisualize\n\
+         element vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+         property float nx\nproperty float ny\nproperty float nz\n\
+         property uchar red\nproperty uchar green\nproperty uchar blue\n\
+         element face {}\nproperty list uchar uint vertex_indices\nend_header\n",
+        mesh.vertices.len(), mesh.indices.len() / 3);
+
+    let mut bytes = header.into_bytes();
+    bytes.reserve(mesh.vertices.len() * 27 + mesh.indices.len() / 3 * 13);
+
+    for ((v, n), c) in mesh.vertices.iter().zip(&mesh.normals).zip(&mesh.colour) {
+        for x in [v.x, v.y, v.z, n.x, n.y, n.z] {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        for x in [c.x, c.y, c.z] {
+            bytes.push((x.clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+    }
+
+    let mut indices: Vec<u32> = mesh.indices.iter().collect();
+    flip_winding(&mut indices);
+
+    for triangle in indices.chunks_exact(3) {
+        bytes.push(3);
+        for i in triangle {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+    }
+
+    bytes
+}
+
+enum Format {
+    Ascii,
+    Binary { big_endian: bool },
+}
+
+#[derive(Clone, Copy)]
+enum Type {
+    I8, U8, I16, U16, I32, U32, F32, F64,
+}
+
+enum PropertyKind {
+    Scalar(Type),
+
+    /* The type of the count, then of the items */
+    List(Type, Type),
+}
+
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+impl Header {
+    /* Also returns where the body starts */
+    fn parse(bytes: &[u8]) -> Result<(Header, usize)> {
+        if !bytes.starts_with(b"ply") {
+            return Err(anyhow!("PLY: Missing the 'ply' magic"));
+        }
+
+        let end = b"end_header";
+        let end_pos = bytes.windows(end.len()).position(|w| w == end)
+            .ok_or_else(|| anyhow!("PLY: Missing end_header"))?;
+
+        // The body starts on the line after end_header
+        let body_start = bytes[end_pos..].iter().position(|&b| b == b'\n').map(|p| end_pos + p + 1).unwrap_or(bytes.len());
+
+        let mut format = None;
+        let mut elements: Vec<Element> = vec![];
+
+        for line in std::str::from_utf8(&bytes[..end_pos])?.lines().skip(1) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            match words.as_slice() {
+                ["format", "ascii", _] => format = Some(Format::Ascii),
+                ["format", "binary_little_endian", _] => format = Some(Format::Binary { big_endian: false }),
+                ["format", "binary_big_endian", _] => format = Some(Format::Binary { big_endian: true }),
+                ["format", ..] => return Err(anyhow!("PLY: Unknown format '{}'", line.trim())),
+                ["element", name, count] => {
+                    let count = count.parse().map_err(|_| anyhow!("PLY: Bad element count '{}'", count))?;
+                    elements.push(Element { name: name.to_string(), count, properties: vec![] });
+                }
+                ["property", "list", count_ty, item_ty, name] => {
+                    let kind = PropertyKind::List(Type::parse(count_ty)?, Type::parse(item_ty)?);
+                    Self::add_property(&mut elements, name, kind)?;
+                }
+                ["property", ty, name] => {
+                    Self::add_property(&mut elements, name, PropertyKind::Scalar(Type::parse(ty)?))?;
+                }
+                ["comment", ..] | ["obj_info", ..] | [] => {}
+                _ => return Err(anyhow!("PLY: Unexpected header line '{}'", line.trim())),
+            }
+        }
+
+        let format = format.ok_or_else(|| anyhow!("PLY: Missing format"))?;
+        Ok((Header { format, elements }, body_start))
+    }
+
+    fn add_property(elements: &mut [Element], name: &str, kind: PropertyKind) -> Result<()> {
+        let element = elements.last_mut().ok_or_else(|| anyhow!("PLY: Property '{}' before any element", name))?;
+        element.properties.push(Property { name: name.to_string(), kind });
+        Ok(())
+    }
+}
+
+impl Type {
+    fn parse(name: &str) -> Result<Type> {
+        Ok(match name {
+            "char" | "int8" => Type::I8,
+            "uchar" | "uint8" => Type::U8,
+            "short" | "int16" => Type::I16,
+            "ushort" | "uint16" => Type::U16,
+            "int" | "int32" => Type::I32,
+            "uint" | "uint32" => Type::U32,
+            "float" | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            _ => return Err(anyhow!("PLY: Unknown property type '{}'", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+
+    fn read(self, body: &mut Body) -> Result<f64> {
+        match body {
+            Body::Ascii(words) => {
+                let word = words.next().ok_or_else(|| anyhow!("PLY: Body ends early"))?;
+                word.parse().map_err(|_| anyhow!("PLY: '{}' is not a number", word))
+            }
+            Body::Binary { bytes, pos, big_endian } => {
+                let raw = bytes.get(*pos..*pos + self.size()).ok_or_else(|| anyhow!("PLY: Body ends early"))?;
+                *pos += self.size();
+
+                let mut b = [0u8; 8];
+                b[..raw.len()].copy_from_slice(raw);
+                if *big_endian {
+                    b[..raw.len()].reverse();
+                }
+
+                Ok(match self {
+                    Type::I8 => b[0] as i8 as f64,
+                    Type::U8 => b[0] as f64,
+                    Type::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::F64 => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+
+    /* Colours are stored as 0..255 integers or 0..1 floats, brought to 0..1 either way */
+    fn read_unit(self, body: &mut Body, name: &str) -> Result<f64> {
+        let value = self.read(body)?;
+        let scale = match (name, self) {
+            ("red" | "green" | "blue", Type::U8) => 255.0,
+            ("red" | "green" | "blue", Type::U16) => 65535.0,
+            _ => 1.0,
+        };
+        Ok(value / scale)
+    }
+}
+
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], pos: usize, big_endian: bool },
+}
+
+impl Body<'_> {
+    fn skip_list(&mut self, property: &Property) -> Result<()> {
+        if let PropertyKind::List(count_ty, item_ty) = property.kind {
+            let count = count_ty.read(self)? as usize;
+            for _ in 0..count {
+                item_ty.read(self)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::prism::make_prism;
+
+    use super::{parse, write};
+
+    #[test]
+    fn test_ascii() {
+        let text = "ply
+format ascii 1.0
+comment a quad with vertex colours
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+        let mesh = parse(text.as_bytes()).unwrap();
+        assert_eq!(mesh.indices.iter().collect::<Vec<_>>(), vec![0, 2, 1, 0, 3, 2]);
+        assert_eq!(mesh.colour[2], Vec3::Z);
+        assert_eq!(mesh.normals[0], Vec3::Z);
+    }
+
+    #[test]
+    fn test_binary() {
+        let mut bytes = b"ply
+format binary_big_endian 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+element face 1
+property uchar flags
+property list uchar ushort vertex_indices
+end_header
+".to_vec();
+
+        for v in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for x in v.iter().chain(&[1.0, 0.0, 0.0]) {
+                bytes.extend_from_slice(&x.to_be_bytes());
+            }
+        }
+        bytes.extend_from_slice(&[7, 3]);
+        for i in [0u16, 1, 2] {
+            bytes.extend_from_slice(&i.to_be_bytes());
+        }
+
+        let mesh = parse(&bytes).unwrap();
+        assert_eq!(mesh.vertices[1], Vec3::X);
+        assert_eq!(mesh.normals, vec![Vec3::X; 3]);
+        assert_eq!(mesh.colour, vec![Vec3::of(1.0); 3]);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let prism = make_prism(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 2.0, 0.5), Vec3::new(0.25, 0.5, 1.0));
+        let mesh = parse(&write(&prism)).unwrap();
+
+        assert_eq!(mesh.vertices, prism.vertices);
+        assert_eq!(mesh.normals, prism.normals);
+        assert!(mesh.indices.iter().eq(prism.indices.iter()));
+
+        // Colours are stored in 8 bits
+        for (a, b) in mesh.colour.iter().zip(&prism.colour) {
+            assert!((*a - *b).length() < 1.0 / 255.0);
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+        assert!(parse(b"not a ply").is_err());
+        assert!(parse(format!("{}0 0 0\n1 0 0\n0 1 0\n3 0 1\n", header).as_bytes()).is_err());
+        assert!(parse(format!("{}0 0 0\n1 0 0\n0 1 0\n3 0 1 5\n", header).as_bytes()).is_err());
+        assert!(parse(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n0\n").is_err());
+    }
+}
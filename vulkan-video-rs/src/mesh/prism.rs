@@ -1,5 +1,6 @@
 use crate::{geometry::vec3::Vec3, utils::colours::{BLUE, CYAN, GREEN, RED, VIOLET, YELLOW}};
 
+use super::indices::Indices;
 use super::mesh::Mesh;
 
 
@@ -44,14 +45,14 @@ pub fn make_prism(location: Vec3, dimensions: Vec3, col: Vec3) -> Mesh
     ];
 
 
-    let indices = vec![
+    let indices = Indices::U16(vec![
          0,  1,  2,  0,  2,  3,        // back
          4,  6,  5,  4,  7,  6,        // front
          8, 10,  9,  8, 11, 10,        // left
         12, 13, 14, 12, 14, 15,        // right
         16, 18, 17, 16, 19, 18,        // top
         20, 21, 22, 20, 22, 23,        // bottom
-    ];
+    ]);
 
     let center = location;
 
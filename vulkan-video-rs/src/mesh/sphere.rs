@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use crate::geometry::vec3::Vec3;
+
+use super::mesh::{Mesh, Shading};
+
+/*
+ * Sphere of latitude rings and longitude segments around the y axis. The seam and the poles repeat their vertices,
+ * so a sphere has (rings + 1) * (segments + 1) vertices.
+ */
+pub fn make_uv_sphere(location: Vec3, radius: f32, segments: usize, rings: usize, col: Vec3, shading: Shading) -> Mesh {
+    let segments = segments.max(3);
+    let rings = rings.max(2);
+
+    let mut vertices = Vec::with_capacity((rings + 1) * (segments + 1));
+    let mut normals = Vec::with_capacity((rings + 1) * (segments + 1));
+
+    for r in 0..=rings {
+        let theta = PI * r as f32 / rings as f32;
+        for s in 0..=segments {
+            let phi = 2.0 * PI * s as f32 / segments as f32;
+            let n = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+            vertices.push(location + n * radius);
+            normals.push(n);
+        }
+    }
+
+    let mut indices = Vec::with_capacity(6 * segments * (rings - 1));
+    let row = segments + 1;
+
+    for r in 0..rings {
+        for s in 0..segments {
+            let a = (r * row + s) as u32;
+            let (b, c, d) = (a + 1, a + row as u32, a + row as u32 + 1);
+
+            // One triangle of the quads touching a pole would have no area
+            if r != 0 {
+                indices.extend_from_slice(&[a, c, b]);
+            }
+            if r != rings - 1 {
+                indices.extend_from_slice(&[b, c, d]);
+            }
+        }
+    }
+
+    Mesh::from_parts(location, vertices, normals, indices, col, shading)
+}
+
+/*
+ * Sphere from an icosahedron whose triangles are split in four subdivisions times, evenly tessellated without poles.
+ * Has 10 * 4^subdivisions + 2 vertices when smooth.
+ */
+pub fn make_ico_sphere(location: Vec3, radius: f32, subdivisions: usize, col: Vec3, shading: Shading) -> Mesh {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+
+    let mut points: Vec<Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ].iter().map(|&(x, y, z)| Vec3::norm(&Vec3::new(x, y, z))).collect();
+
+    // Clockwise seen from outside
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 5, 11], [0, 1, 5], [0, 7, 1], [0, 10, 7], [0, 11, 10],
+        [1, 9, 5], [5, 4, 11], [11, 2, 10], [10, 6, 7], [7, 8, 1],
+        [3, 4, 9], [3, 2, 4], [3, 6, 2], [3, 8, 6], [3, 9, 8],
+        [4, 5, 9], [2, 11, 4], [6, 10, 2], [8, 7, 6], [9, 1, 8],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges are shared by two triangles, which have to share the new vertex too
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push(Vec3::norm(&(points[a as usize] + points[b as usize])));
+                (points.len() - 1) as u32
+            })
+        };
+
+        triangles = triangles.iter().flat_map(|&[a, b, c]| {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+        }).collect();
+    }
+
+    let vertices = points.iter().map(|&n| location + n * radius).collect();
+    let indices = triangles.into_iter().flatten().collect();
+
+    Mesh::from_parts(location, vertices, points, indices, col, shading)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::mesh::tests::{assert_front_faces_out, assert_outward};
+    use crate::mesh::Shading;
+
+    use super::{make_ico_sphere, make_uv_sphere};
+
+    #[test]
+    fn test_uv_sphere() {
+        let centre = Vec3::new(1.0, 2.0, 3.0);
+        let sphere = make_uv_sphere(centre, 2.0, 16, 8, Vec3::of(1.0), Shading::Smooth);
+
+        assert_eq!(sphere.vertices.len(), 9 * 17);
+        assert_eq!(sphere.indices.len(), 6 * 16 * 7);
+        assert!(sphere.vertices.iter().all(|v| ((*v - centre).length() - 2.0).abs() < 1e-5));
+
+        assert_outward(&sphere, |v| v - centre);
+        assert_front_faces_out(&sphere);
+    }
+
+    #[test]
+    fn test_ico_sphere() {
+        for subdivisions in 0..3 {
+            let sphere = make_ico_sphere(Vec3::ZERO, 1.0, subdivisions, Vec3::of(1.0), Shading::Smooth);
+
+            assert_eq!(sphere.vertices.len(), 10 * 4usize.pow(subdivisions as u32) + 2);
+            assert_eq!(sphere.indices.len(), 3 * 20 * 4usize.pow(subdivisions as u32));
+            assert_outward(&sphere, |v| v);
+            assert_front_faces_out(&sphere);
+        }
+    }
+
+    #[test]
+    fn test_flat_sphere() {
+        let sphere = make_ico_sphere(Vec3::ZERO, 1.0, 1, Vec3::of(1.0), Shading::Flat);
+
+        assert_eq!(sphere.vertices.len(), 3 * 80);
+        assert_outward(&sphere, |v| v);
+        assert_front_faces_out(&sphere);
+    }
+}
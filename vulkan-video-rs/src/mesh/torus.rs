@@ -0,0 +1,72 @@
+use std::f32::consts::PI;
+
+use crate::geometry::vec3::Vec3;
+
+use super::mesh::{Mesh, Shading};
+
+/*
+ * Ring around the y axis through location. The tube of minor_radius follows a circle of major_radius and is cut
+ * into major_segments along the ring and minor_segments around the tube. The seams repeat their vertices, so a torus
+ * has (major_segments + 1) * (minor_segments + 1) vertices.
+ */
+pub fn make_torus(location: Vec3, major_radius: f32, minor_radius: f32, major_segments: usize, minor_segments: usize, col: Vec3, shading: Shading) -> Mesh {
+    let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+    let count = (major_segments + 1) * (minor_segments + 1);
+
+    let mut vertices = Vec::with_capacity(count);
+    let mut normals = Vec::with_capacity(count);
+
+    for i in 0..=major_segments {
+        let u = 2.0 * PI * i as f32 / major_segments as f32;
+        let out = Vec3::new(u.cos(), 0.0, u.sin());
+
+        for j in 0..=minor_segments {
+            let v = 2.0 * PI * j as f32 / minor_segments as f32;
+            let n = out * v.cos() + Vec3::Y * v.sin();
+            vertices.push(location + out * major_radius + n * minor_radius);
+            normals.push(n);
+        }
+    }
+
+    let mut indices = Vec::with_capacity(6 * major_segments * minor_segments);
+    let row = (minor_segments + 1) as u32;
+
+    for i in 0..major_segments as u32 {
+        for j in 0..minor_segments as u32 {
+            let a = i * row + j;
+            let (b, c, d) = (a + row, a + 1, a + row + 1);
+            indices.extend_from_slice(&[a, b, c, b, d, c]);
+        }
+    }
+
+    Mesh::from_parts(location, vertices, normals, indices, col, shading)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::vec3::Vec3;
+    use crate::mesh::mesh::tests::{assert_front_faces_out, assert_outward};
+    use crate::mesh::Shading;
+
+    use super::make_torus;
+
+    #[test]
+    fn test_torus() {
+        let centre = Vec3::new(0.0, 0.0, 5.0);
+
+        for shading in [Shading::Smooth, Shading::Flat] {
+            let torus = make_torus(centre, 2.0, 0.5, 24, 8, Vec3::of(1.0), shading);
+
+            let expected = if shading == Shading::Smooth { 25 * 9 } else { 3 * 2 * 24 * 8 };
+            assert_eq!(torus.vertices.len(), expected);
+            assert_eq!(torus.indices.len(), 6 * 24 * 8);
+
+            // Away from the middle of the tube
+            assert_outward(&torus, |v| {
+                let p = v - centre;
+                p - Vec3::norm(&Vec3::new(p.x, 0.0, p.z)) * 2.0
+            });
+            assert_front_faces_out(&torus);
+        }
+    }
+}
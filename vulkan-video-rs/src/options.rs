@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+
+use crate::rhi::adapter::GpuSelector;
+
+/* Environment variable used when --gpu is not given */
+const GPU_ENV: &str = "VKV_GPU";
+
+/* Set to 1 or true to enable the validation layer without --validation */
+const VALIDATION_ENV: &str = "VKV_VALIDATION";
+
+#[derive(Default, Debug)]
+pub struct Options {
+    /* Device to render on, the best scoring device when not set */
+    pub gpu: Option<GpuSelector>,
+
+    /* Print the available devices and exit */
+    pub list_gpus: bool,
+
+    /* Request the validation layer, ignored with a warning when it is not installed */
+    pub validation: bool,
+
+    /* Mesh files drawn in the meshes pane, see mesh::import::load for the formats */
+    pub meshes: Vec<PathBuf>,
+}
+
+impl Options {
+    pub fn from_env() -> Self {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let mut options = Options::parse(&args, std::env::var(GPU_ENV).ok());
+
+        let validation_env = std::env::var(VALIDATION_ENV).map(|value| value == "1" || value.eq_ignore_ascii_case("true")).unwrap_or(false);
+        options.validation |= validation_env || cfg!(feature = "validation");
+
+        options
+    }
+
+    /* The command line takes precedence over the environment */
+    pub fn parse(args: &[String], env_gpu: Option<String>) -> Self {
+        let mut options = Options {
+            gpu: env_gpu.filter(|value| !value.is_empty()).map(|value| GpuSelector::parse(&value)),
+            ..Default::default()
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--list-gpus" => options.list_gpus = true,
+
+                "--validation" => options.validation = true,
+
+                "--mesh" => match args.next() {
+                    Some(value) => options.meshes.push(PathBuf::from(value)),
+                    None => println!("Warning: --mesh expects a file."),
+                },
+
+                "--gpu" => match args.next() {
+                    Some(value) => options.gpu = Some(GpuSelector::parse(value)),
+                    None => println!("Warning: --gpu expects an index, name or UUID."),
+                },
+
+                _ => match arg.strip_prefix("--gpu=") {
+                    Some(value) => options.gpu = Some(GpuSelector::parse(value)),
+                    None => println!("Warning: Ignoring unknown argument '{}'.", arg),
+                },
+            }
+        }
+
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+    use crate::rhi::adapter::GpuSelector;
+
+    #[test]
+    fn test_parse_args() {
+        let args: Vec<String> = ["--list-gpus", "--gpu=llvmpipe", "--mesh", "bunny.ply"].iter().map(|s| s.to_string()).collect();
+        let options = Options::parse(&args, Some("1".to_string()));
+        assert!(options.list_gpus);
+        assert_eq!(options.gpu, Some(GpuSelector::Name("llvmpipe".to_string())));
+        assert_eq!(options.meshes, vec![std::path::PathBuf::from("bunny.ply")]);
+
+        let options = Options::parse(&[], Some("1".to_string()));
+        assert_eq!(options.gpu, Some(GpuSelector::Index(1)));
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Result};
+use ash::vk;
+use uuid::Uuid;
+
+use crate::vk_bundles::SurfaceBundle;
+
+/* How the user asked for a device, from --gpu or VKV_GPU */
+#[derive(Clone, PartialEq, Debug)]
+pub enum GpuSelector {
+    /* Position in the enumeration order, as printed by --list-gpus */
+    Index(usize),
+
+    /* The device UUID, stable across reboots and driver updates */
+    Uuid([u8; vk::UUID_SIZE]),
+
+    /* Case insensitive part of the device name, e.g. "nvidia" or "llvmpipe" */
+    Name(String),
+}
+
+impl GpuSelector {
+    pub fn parse(value: &str) -> Self {
+        let value = value.trim();
+
+        if let Ok(index) = value.parse::<usize>() {
+            return GpuSelector::Index(index);
+        }
+
+        if let Ok(uuid) = Uuid::parse_str(value) {
+            return GpuSelector::Uuid(*uuid.as_bytes());
+        }
+
+        GpuSelector::Name(value.to_lowercase())
+    }
+
+    pub fn matches(&self, adapter: &AdapterInfo) -> bool {
+        match self {
+            GpuSelector::Index(index) => adapter.index == *index,
+            GpuSelector::Uuid(uuid) => adapter.uuid == *uuid,
+            GpuSelector::Name(name) => adapter.name.to_lowercase().contains(name.as_str()),
+        }
+    }
+}
+
+pub struct AdapterInfo {
+    pub index: usize,
+    pub physical: vk::PhysicalDevice,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub uuid: [u8; vk::UUID_SIZE],
+
+    /* First queue family with graphics and present support for the surface */
+    pub queue_family_index: Option<u32>,
+
+    /* A transfer only family, uploads go through the graphics family when there is none */
+    pub transfer_queue_family_index: Option<u32>,
+
+    /* Required features the device lacks, it can't be used when this is not empty */
+    pub missing_features: Vec<&'static str>,
+    pub fill_mode_non_solid: bool,
+
+    /* Lets the profiler reset its timestamp queries from the host */
+    pub host_query_reset: bool,
+
+    /* Vulkan 1.2 features can be enabled */
+    pub vulkan12: bool,
+
+    /* Orders uploads on the transfer family against rendering, without it uploads go through the graphics queue */
+    pub timeline_semaphore: bool,
+}
+
+impl AdapterInfo {
+    pub fn is_usable(&self) -> bool {
+        self.queue_family_index.is_some() && self.missing_features.is_empty()
+    }
+
+    /* Higher is better, None for devices that can't run the renderer */
+    pub fn score(&self) -> Option<u32> {
+        if !self.is_usable() {
+            return None;
+        }
+
+        let type_score = match self.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU   => 1000,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 500,
+            vk::PhysicalDeviceType::VIRTUAL_GPU    => 250,
+            vk::PhysicalDeviceType::CPU            => 100,
+            _ => 50,
+        };
+
+        // Optional features only break ties between devices of the same type
+        let feature_score = if self.fill_mode_non_solid { 10 } else { 0 }
+            + if self.timeline_semaphore && self.transfer_queue_family_index.is_some() { 5 } else { 0 };
+
+        Some(type_score + feature_score)
+    }
+
+    pub fn uuid_string(&self) -> String {
+        Uuid::from_bytes(self.uuid).hyphenated().to_string()
+    }
+
+    pub fn describe(&self) -> String {
+        let status = match self.score() {
+            Some(score) => format!("score {}", score),
+            None if self.queue_family_index.is_none() => "unusable: no graphics queue that can present".to_string(),
+            None => format!("unusable: missing {}", self.missing_features.join(", ")),
+        };
+
+        format!("[{}] {} ({:?}) {} - {}", self.index, self.name, self.device_type, self.uuid_string(), status)
+    }
+}
+
+pub fn enumerate_adapters(instance: &ash::Instance, surface: &SurfaceBundle) -> Vec<AdapterInfo> {
+    let devs = unsafe { instance.enumerate_physical_devices().unwrap() };
+
+    devs.iter().enumerate().map(|(index, dev)| {
+        let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut id_properties);
+        unsafe { instance.get_physical_device_properties2(*dev, &mut properties2) };
+        let properties = properties2.properties;
+
+        let queue_props = unsafe { instance.get_physical_device_queue_family_properties(*dev) };
+        let features = unsafe { instance.get_physical_device_features(*dev) };
+
+        let queue_family_index = queue_props.iter().enumerate().position(|(i, queue)| {
+            let surface_support = unsafe { surface.loader.get_physical_device_surface_support(*dev, i as u32, surface.surface).unwrap_or(false) };
+            surface_support && queue.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+        }).map(|i| i as u32);
+
+        // Prefer a family without compute as well, those are usually backed by the copy engines
+        let transfer_only = |queue: &vk::QueueFamilyProperties| queue.queue_flags.contains(vk::QueueFlags::TRANSFER) && !queue.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+        let transfer_queue_family_index = queue_props.iter().position(|queue| transfer_only(queue) && !queue.queue_flags.contains(vk::QueueFlags::COMPUTE))
+            .or_else(|| queue_props.iter().position(transfer_only))
+            .map(|i| i as u32);
+
+        let mut missing_features = Vec::new();
+        if features.sampler_anisotropy == vk::FALSE {
+            missing_features.push("samplerAnisotropy");
+        }
+
+        let vulkan12 = properties.api_version >= vk::API_VERSION_1_2;
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default();
+        if vulkan12 {
+            let mut features2 = vk::PhysicalDeviceFeatures2::default().push_next(&mut vulkan12_features);
+            unsafe { instance.get_physical_device_features2(*dev, &mut features2) };
+        }
+
+        AdapterInfo {
+            index,
+            physical: *dev,
+            name: properties.device_name_as_c_str().unwrap().to_string_lossy().into_owned(),
+            device_type: properties.device_type,
+            uuid: id_properties.device_uuid,
+            queue_family_index,
+            transfer_queue_family_index,
+            missing_features,
+            fill_mode_non_solid: features.fill_mode_non_solid == vk::TRUE,
+            host_query_reset: vulkan12_features.host_query_reset == vk::TRUE,
+            vulkan12,
+            timeline_semaphore: vulkan12_features.timeline_semaphore == vk::TRUE,
+        }
+    }).collect()
+}
+
+/* The adapter matching the selector, or the best scoring one when there is no selector */
+pub fn choose_adapter<'a>(adapters: &'a [AdapterInfo], selector: Option<&GpuSelector>) -> Result<&'a AdapterInfo> {
+    let Some(selector) = selector else {
+        return adapters.iter()
+            .filter(|adapter| adapter.is_usable())
+            .max_by_key(|adapter| (adapter.score(), std::cmp::Reverse(adapter.index)))
+            .ok_or_else(|| anyhow!("No device supports the renderer."));
+    };
+
+    let adapter = adapters.iter()
+        .find(|adapter| selector.matches(adapter))
+        .ok_or_else(|| anyhow!("No device matches {:?}.", selector))?;
+
+    if !adapter.is_usable() {
+        return Err(anyhow!("The requested device can't be used: {}", adapter.describe()));
+    }
+
+    Ok(adapter)
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+
+    use super::{choose_adapter, AdapterInfo, GpuSelector};
+
+    fn adapter(index: usize, name: &str, device_type: vk::PhysicalDeviceType) -> AdapterInfo {
+        AdapterInfo {
+            index,
+            physical: vk::PhysicalDevice::null(),
+            name: name.to_string(),
+            device_type,
+            uuid: [index as u8; 16],
+            queue_family_index: Some(0),
+            transfer_queue_family_index: None,
+            missing_features: vec![],
+            fill_mode_non_solid: true,
+            host_query_reset: true,
+            vulkan12: true,
+            timeline_semaphore: true,
+        }
+    }
+
+    #[test]
+    fn test_parse_selector() {
+        assert_eq!(GpuSelector::parse("1"), GpuSelector::Index(1));
+        assert_eq!(GpuSelector::parse("NVIDIA GeForce"), GpuSelector::Name("nvidia geforce".to_string()));
+        assert_eq!(GpuSelector::parse("01010101-0101-0101-0101-010101010101"), GpuSelector::Uuid([1; 16]));
+    }
+
+    #[test]
+    fn test_choose_adapter() {
+        let mut adapters = vec![
+            adapter(0, "Intel(R) Graphics", vk::PhysicalDeviceType::INTEGRATED_GPU),
+            adapter(1, "NVIDIA GeForce RTX", vk::PhysicalDeviceType::DISCRETE_GPU),
+            adapter(2, "llvmpipe (LLVM 17.0.6, 256 bits)", vk::PhysicalDeviceType::CPU),
+        ];
+
+        assert_eq!(choose_adapter(&adapters, None).unwrap().index, 1);
+        assert_eq!(choose_adapter(&adapters, Some(&GpuSelector::parse("LLVMpipe"))).unwrap().index, 2);
+        assert_eq!(choose_adapter(&adapters, Some(&GpuSelector::Index(0))).unwrap().index, 0);
+        assert!(choose_adapter(&adapters, Some(&GpuSelector::Index(5))).is_err());
+
+        // Unusable devices are skipped by scoring and refused when asked for
+        adapters[1].missing_features.push("samplerAnisotropy");
+        assert_eq!(choose_adapter(&adapters, None).unwrap().index, 0);
+        assert!(choose_adapter(&adapters, Some(&GpuSelector::Index(1))).is_err());
+
+        // Without timeline semaphores a device still works, uploading through its graphics queue
+        adapters[0].timeline_semaphore = false;
+        adapters[0].vulkan12 = false;
+        assert_eq!(choose_adapter(&adapters, None).unwrap().index, 0);
+    }
+}
@@ -1,8 +1,12 @@
+use anyhow::{anyhow, Result};
 use ash::vk;
 
-use crate::{utils::buffer, vk_base::VkBase, vk_bundles::{BufferBundle, DeviceBundle}};
+use crate::{utils::{buffer, common::find_memory_type}, vk_bundles::{BufferBundle, DeviceBundle, ImageBundle}};
+use crate::rhi::memory_tracker::TrackedKind;
+use crate::rhi::release::{Owned, Release, ReleaseQueue};
 
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum BufferType {
 
     /* Properties: HOST_VISIBLE | HOST_COHERENT
@@ -25,158 +29,548 @@ pub enum BufferType {
 
 }
 
+impl BufferType {
+    fn name(&self) -> &'static str {
+        match self {
+            BufferType::Staging => "staging",
+            BufferType::DeviceVertex => "vertex",
+            BufferType::DeviceIndex => "index",
+            BufferType::Uniform => "uniform",
+        }
+    }
+
+    fn usage(&self) -> vk::BufferUsageFlags {
+        match self {
+            BufferType::Staging => vk::BufferUsageFlags::TRANSFER_SRC,
+            BufferType::DeviceVertex => vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+            BufferType::DeviceIndex => vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+            BufferType::Uniform => vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::UNIFORM_BUFFER,
+        }
+    }
+
+    fn properties(&self) -> vk::MemoryPropertyFlags {
+        match self {
+            BufferType::Staging => vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            _ => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        }
+    }
+
+    /* Alignment of the ranges handed out, they are offsets into one buffer so the buffer's own memory alignment doesn't apply */
+    fn alignment(&self, limits: &vk::PhysicalDeviceLimits) -> u64 {
+        match self {
+            // Keeps staging ranges usable as the source of buffer to image copies
+            BufferType::Staging => limits.non_coherent_atom_size.max(16),
+            BufferType::DeviceVertex => 16,
+            // vkCmdBindIndexBuffer needs the offset to be a multiple of the index size
+            BufferType::DeviceIndex => 4,
+            BufferType::Uniform => limits.min_uniform_buffer_offset_alignment.max(16),
+        }
+    }
+}
+
 pub struct AllocatorSizeInfo {
     pub staging        : u64,
     pub device_vertex  : u64,
     pub device_index   : u64,
     pub uniform_buffer : u64,
+
+    /* Images larger than half a block get a dedicated allocation */
+    pub image_block    : u64,
+
+    /* Not the allocator's, VkBase sizes each frame's region of the upload ring with it */
+    pub upload_ring_frame : u64,
 }
 
-pub struct AllocatorHeap {
-    pub heap   : BufferBundle,
-    pub offset : u64,
-    pub size   : u64,
-    pub align  : u64,
-    pub waste  : u64
+/* Free ranges of a block as (offset, size), sorted by offset and never adjacent */
+#[derive(Debug)]
+pub struct FreeList {
+    size: u64,
+    ranges: Vec<(u64, u64)>,
+    allocations: usize,
 }
 
+impl FreeList {
+    pub fn new(size: u64) -> Self {
+        Self { size, ranges: vec![(0, size)], allocations: 0 }
+    }
+
+    /* First fit, the padding in front of an aligned offset stays free */
+    pub fn alloc(&mut self, size: u64, align: u64) -> Option<u64> {
+        let size = size.max(1);
+        let align = align.max(1);
 
-pub struct Allocator {
-    pub staging        : AllocatorHeap,
-    pub device_vertex  : AllocatorHeap,
-    pub device_index   : AllocatorHeap,
-    pub uniform_buffer : AllocatorHeap,
+        let (i, offset) = self.ranges.iter().enumerate().find_map(|(i, (start, len))| {
+            let offset = start.next_multiple_of(align);
+            (offset + size <= start + len).then_some((i, offset))
+        })?;
+
+        let (start, len) = self.ranges[i];
+        let end = start + len;
+
+        let before = (offset > start).then_some((start, offset - start));
+        let after = (offset + size < end).then_some((offset + size, end - offset - size));
+        self.ranges.splice(i..i + 1, before.into_iter().chain(after));
+
+        self.allocations += 1;
+        Some(offset)
+    }
+
+    /* False when the range is out of bounds or overlaps free space, i.e. it was not allocated */
+    pub fn free(&mut self, offset: u64, size: u64) -> bool {
+        let size = size.max(1);
+        if offset + size > self.size {
+            return false;
+        }
+
+        let i = self.ranges.partition_point(|(start, _)| *start < offset);
+        let prev = i.checked_sub(1).map(|p| self.ranges[p]);
+        let next = self.ranges.get(i).copied();
+
+        if prev.is_some_and(|(start, len)| start + len > offset) || next.is_some_and(|(start, _)| offset + size > start) {
+            return false;
+        }
+
+        let merge_prev = prev.is_some_and(|(start, len)| start + len == offset);
+        let merge_next = next.is_some_and(|(start, _)| offset + size == start);
+
+        match (merge_prev, merge_next) {
+            (true, true) => {
+                self.ranges[i - 1].1 += size + self.ranges[i].1;
+                self.ranges.remove(i);
+            }
+            (true, false) => self.ranges[i - 1].1 += size,
+            (false, true) => self.ranges[i] = (offset, size + self.ranges[i].1),
+            (false, false) => self.ranges.insert(i, (offset, size)),
+        }
+
+        self.allocations -= 1;
+        true
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.ranges.iter().map(|(_, len)| len).sum()
+    }
+
+    pub fn largest_free(&self) -> u64 {
+        self.ranges.iter().map(|(_, len)| *len).max().unwrap_or(0)
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub struct AllocatorStats {
+    pub blocks: usize,
+    pub capacity: u64,
+    pub used: u64,
+    pub allocations: usize,
+    pub free_ranges: usize,
+    pub largest_free: u64,
 }
 
+impl AllocatorStats {
+    fn of<'a>(free_lists: impl Iterator<Item = &'a FreeList>) -> Self {
+        free_lists.fold(AllocatorStats::default(), |stats, free_list| {
+            AllocatorStats {
+                blocks: stats.blocks + 1,
+                capacity: stats.capacity + free_list.size,
+                used: stats.used + free_list.size - free_list.free_bytes(),
+                allocations: stats.allocations + free_list.allocations,
+                free_ranges: stats.free_ranges + free_list.ranges.len(),
+                largest_free: stats.largest_free.max(free_list.largest_free()),
+            }
+        })
+    }
 
-impl Allocator {
+    /* 0 when the free space is one range, towards 1 the more it is split up */
+    pub fn fragmentation(&self) -> f32 {
+        let free = self.capacity - self.used;
+        if free == 0 {
+            return 0.0;
+        }
 
+        1.0 - self.largest_free as f32 / free as f32
+    }
+}
 
-    pub fn new(base: &VkBase, sizes: AllocatorSizeInfo) -> Self {
+/* A buffer that allocations are ranges of */
+pub struct AllocatorBlock {
+    pub heap: BufferBundle,
+    pub free_list: FreeList,
+}
 
-        let offset = 0;
+/* Blocks of one buffer type, a new block is added when none of them has room.
+ * Every block is a single buffer, so bufferImageGranularity never applies between allocations. */
+pub struct AllocatorPool {
+    pub buffer_type: BufferType,
+    pub blocks: Vec<AllocatorBlock>,
+    pub block_size: u64,
+    pub align: u64,
+}
 
-        let staging = {
-            let size = sizes.staging;
-            let required_memory_flags = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
-            let usage = vk::BufferUsageFlags::TRANSFER_SRC;
-            let heap = buffer::create_buffer(&base.device, size, usage, required_memory_flags).expect("Failed to create buffer.");
-            let mem_requirements = unsafe { base.device.logical.get_buffer_memory_requirements(heap.buffer) };
-            let align = mem_requirements.alignment;
-            AllocatorHeap { heap, offset, size, align, waste: 0 }
+impl AllocatorPool {
+    fn new(device: &DeviceBundle, buffer_type: BufferType, block_size: u64) -> Result<Self> {
+        let mut pool = Self {
+            buffer_type,
+            blocks: Vec::new(),
+            block_size,
+            align: buffer_type.alignment(&device.properties.limits),
         };
 
-        let device_vertex = {
-            let size = sizes.device_vertex;
-            let required_memory_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
-            let usage = vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER;
-            let heap = buffer::create_buffer(&base.device, size, usage, required_memory_flags).expect("Failed to create buffer.");
-            let mem_requirements = unsafe { base.device.logical.get_buffer_memory_requirements(heap.buffer) };
-            let align = mem_requirements.alignment;
-            AllocatorHeap { heap, offset, size, align, waste: 0 }
-        };
+        pool.add_block(device, block_size)?;
+        Ok(pool)
+    }
+
+    fn add_block(&mut self, device: &DeviceBundle, size: u64) -> Result<&mut AllocatorBlock> {
+        let label = format!("Allocator {} block {}", self.buffer_type.name(), self.blocks.len());
+        let heap = buffer::create_buffer(device, size, self.buffer_type.usage(), self.buffer_type.properties(), &label)?;
+
+        // Allocations are ranges of the block buffer, so the block is the only thing that can be named
+        crate::debug_name!(device, heap.buffer, "{}", label);
 
+        self.blocks.push(AllocatorBlock { heap, free_list: FreeList::new(size) });
+        Ok(self.blocks.last_mut().unwrap())
+    }
 
-        let device_index = {
-            let size = sizes.device_index;
-            let required_memory_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
-            let usage = vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER;
-            let heap = buffer::create_buffer(&base.device, size, usage, required_memory_flags).expect("Failed to create buffer.");
-            let mem_requirements = unsafe { base.device.logical.get_buffer_memory_requirements(heap.buffer) };
-            let align = mem_requirements.alignment;
-            AllocatorHeap { heap, offset, size, align, waste: 0 }
+    fn alloc(&mut self, device: &DeviceBundle, size: u64, label: &str) -> Result<BufferBundle> {
+        let align = self.align;
+        let existing = self.blocks.iter_mut().find_map(|block| block.free_list.alloc(size, align).map(|offset| (block.heap.buffer, block.heap.memory, offset)));
+
+        let (buffer, memory, offset) = match existing {
+            Some(allocation) => allocation,
+            None => {
+                // Larger requests get a block of their own size
+                let block = self.add_block(device, self.block_size.max(size.next_multiple_of(align)))?;
+                let offset = block.free_list.alloc(size, align)
+                    .ok_or_else(|| anyhow!("Allocator: {} bytes don't fit in a new {} block", size, block.heap.size))?;
+                (block.heap.buffer, block.heap.memory, offset)
+            }
         };
 
-        let uniform_buffer = {
-            let size = sizes.uniform_buffer;
-            let required_memory_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL;
-            let usage = vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::UNIFORM_BUFFER;
-            let heap = buffer::create_buffer(&base.device, size, usage, required_memory_flags).expect("Failed to create buffer.");
-            let mem_requirements = unsafe { base.device.logical.get_buffer_memory_requirements(heap.buffer) };
-            let align = mem_requirements.alignment;
-            AllocatorHeap { heap, offset, size, align, waste: 0 }
+        device.memory_tracker.track(TrackedKind::BufferRange, buffer, offset, size, label);
+        Ok(BufferBundle { buffer, memory, offset, size })
+    }
+
+    fn free(&mut self, device: &DeviceBundle, bundle: &BufferBundle) -> Result<()> {
+        let block = self.blocks.iter_mut().find(|block| block.heap.buffer == bundle.buffer)
+            .ok_or_else(|| anyhow!("Allocator: Buffer {:?} is not a {} block", bundle.buffer, self.buffer_type.name()))?;
+
+        if !block.free_list.free(bundle.offset, bundle.size) {
+            return Err(anyhow!("Allocator: Range {}..{} of a {} block was not allocated", bundle.offset, bundle.offset + bundle.size, self.buffer_type.name()));
+        }
+
+        device.memory_tracker.untrack(TrackedKind::BufferRange, bundle.buffer, bundle.offset);
+        Ok(())
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats::of(self.blocks.iter().map(|block| &block.free_list))
+    }
+
+    fn release(&mut self, device: &DeviceBundle) {
+        for block in self.blocks.drain(..) {
+            buffer::destroy_buffer(device, &block.heap);
+        }
+    }
+}
+
+
+/* Raw memory that images are bound to at an offset */
+pub struct ImageBlock {
+    pub memory: vk::DeviceMemory,
+    pub free_list: FreeList,
+}
+
+/* Blocks of one memory type for one tiling. Linear and optimal images never share a block,
+ * so bufferImageGranularity is met without padding between neighbours. */
+pub struct ImagePool {
+    pub memory_type_index: u32,
+    pub tiling: vk::ImageTiling,
+    pub blocks: Vec<ImageBlock>,
+}
+
+impl ImagePool {
+    fn alloc(&mut self, device: &DeviceBundle, requirements: &vk::MemoryRequirements, block_size: u64) -> Result<(vk::DeviceMemory, u64)> {
+        let existing = self.blocks.iter_mut().find_map(|block| block.free_list.alloc(requirements.size, requirements.alignment).map(|offset| (block.memory, offset)));
+        if let Some(allocation) = existing {
+            return Ok(allocation);
+        }
+
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(block_size)
+            .memory_type_index(self.memory_type_index);
+
+        let memory = unsafe { device.logical.allocate_memory(&allocate_info, None)? };
+
+        let label = format!("Allocator {:?} image block {}", self.tiling, self.blocks.len());
+        device.memory_tracker.track(TrackedKind::DeviceMemory, memory, 0, block_size, &label);
+        crate::debug_name!(device, memory, "{}", label);
+
+        let mut free_list = FreeList::new(block_size);
+        let offset = free_list.alloc(requirements.size, requirements.alignment)
+            .ok_or_else(|| anyhow!("Allocator: {} bytes don't fit in a new image block", requirements.size))?;
+
+        self.blocks.push(ImageBlock { memory, free_list });
+        Ok((memory, offset))
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats::of(self.blocks.iter().map(|block| &block.free_list))
+    }
+}
+
+/* Usage and budget of a memory heap, the budget is the heap size without VK_EXT_memory_budget */
+#[derive(Clone, Copy, Debug)]
+pub struct HeapBudget {
+    pub heap_index: usize,
+    pub size: u64,
+    pub budget: u64,
+
+    /* Usage by the whole process, None without VK_EXT_memory_budget */
+    pub usage: Option<u64>,
+    pub device_local: bool,
+}
+
+impl HeapBudget {
+    pub fn describe(&self) -> String {
+        const MB: f64 = 1024.0 * 1024.0;
+        let kind = if self.device_local { "device local" } else { "host" };
+        let usage = self.usage.map(|usage| format!("{:.1} MB used of ", usage as f64 / MB)).unwrap_or_default();
+
+        format!("Heap {} ({}): {}{:.1} MB budget, {:.1} MB total", self.heap_index, kind, usage, self.budget as f64 / MB, self.size as f64 / MB)
+    }
+}
+
+pub fn memory_budget(instance: &ash::Instance, device: &DeviceBundle) -> Vec<HeapBudget> {
+    let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties2 = vk::PhysicalDeviceMemoryProperties2::default();
+    if device.memory_budget {
+        properties2 = properties2.push_next(&mut budget_properties);
+    }
+
+    unsafe { instance.get_physical_device_memory_properties2(device.physical, &mut properties2) };
+    let heaps = properties2.memory_properties.memory_heaps;
+    let heap_count = properties2.memory_properties.memory_heap_count as usize;
+
+    heaps[..heap_count].iter().enumerate().map(|(heap_index, heap)| {
+        let (budget, usage) = if device.memory_budget {
+            (budget_properties.heap_budget[heap_index], Some(budget_properties.heap_usage[heap_index]))
+        } else {
+            (heap.size, None)
         };
 
+        HeapBudget {
+            heap_index,
+            size: heap.size,
+            budget,
+            usage,
+            device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+        }
+    }).collect()
+}
+
+
+pub struct Allocator {
+    pub staging        : AllocatorPool,
+    pub device_vertex  : AllocatorPool,
+    pub device_index   : AllocatorPool,
+    pub uniform_buffer : AllocatorPool,
+
+    /* Created on demand, one per memory type and tiling */
+    pub image_pools    : Vec<ImagePool>,
+    pub image_block    : u64,
+    pub dedicated      : Vec<vk::DeviceMemory>,
+
+    /* Where dropped ranges go, VkBase gives them back through free once the GPU is done with them */
+    release_queue      : ReleaseQueue,
+}
+
+
+impl Allocator {
 
+    /* The sizes are of the first block of each type, later blocks are the same size unless an allocation needs more */
+    pub fn new(device: &DeviceBundle, sizes: AllocatorSizeInfo, release_queue: ReleaseQueue) -> Self {
         Self {
-            staging,
-            device_vertex,
-            device_index,
-            uniform_buffer
+            staging: AllocatorPool::new(device, BufferType::Staging, sizes.staging).expect("Failed to create buffer."),
+            device_vertex: AllocatorPool::new(device, BufferType::DeviceVertex, sizes.device_vertex).expect("Failed to create buffer."),
+            device_index: AllocatorPool::new(device, BufferType::DeviceIndex, sizes.device_index).expect("Failed to create buffer."),
+            uniform_buffer: AllocatorPool::new(device, BufferType::Uniform, sizes.uniform_buffer).expect("Failed to create buffer."),
+            image_pools: Vec::new(),
+            image_block: sizes.image_block,
+            dedicated: Vec::new(),
+            release_queue,
         }
     }
 
-    pub fn alloc(&mut self, buffer_type: BufferType, size: u64) -> Result<BufferBundle, String> {
-
-        let heap = match buffer_type {
+    fn pool(&mut self, buffer_type: BufferType) -> &mut AllocatorPool {
+        match buffer_type {
             BufferType::Staging => &mut self.staging,
             BufferType::DeviceVertex => &mut self.device_vertex,
             BufferType::DeviceIndex => &mut self.device_index,
             BufferType::Uniform => &mut self.uniform_buffer,
-        };
-
-        if heap.offset + size >= heap.size {
-            return Err(format!("Allocator: Requested {} bytes from heap of size {} with {} remaining", size, heap.size, heap.size - heap.offset));
         }
+    }
+
+    /* The label is what the memory tracker reports the range as, the range is freed some frames after it is dropped */
+    pub fn alloc(&mut self, device: &DeviceBundle, buffer_type: BufferType, size: u64, label: &str) -> Result<Owned<BufferBundle>> {
+        let bundle = self.pool(buffer_type).alloc(device, size, label)?;
+        Ok(self.release_queue.own(bundle))
+    }
+
+    /* For things created outside the allocator that should be released the same way */
+    pub fn own<T: Release>(&self, value: T) -> Owned<T> {
+        self.release_queue.own(value)
+    }
+
+    /* The range must not be in use by the GPU anymore */
+    pub fn free(&mut self, device: &DeviceBundle, bundle: &BufferBundle) -> Result<()> {
+        let pool = [&mut self.staging, &mut self.device_vertex, &mut self.device_index, &mut self.uniform_buffer].into_iter()
+            .find(|pool| pool.blocks.iter().any(|block| block.heap.buffer == bundle.buffer))
+            .ok_or_else(|| anyhow!("Allocator: Buffer {:?} is not an allocator block", bundle.buffer))?;
+
+        pool.free(device, bundle)
+    }
 
-        let bundle = BufferBundle {
-            buffer: heap.heap.buffer,
-            memory: heap.heap.memory,
-            offset: heap.offset,
-            size
+    /* Creates the image and binds it to pooled memory, or to its own memory when it is large or the driver asks for that */
+    pub fn create_image(&mut self, device: &DeviceBundle, image_ci: &vk::ImageCreateInfo, properties: vk::MemoryPropertyFlags, label: &str) -> Result<ImageBundle> {
+        let image = unsafe { device.logical.create_image(image_ci, None)? };
+
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let mut requirements2 = vk::MemoryRequirements2::default().push_next(&mut dedicated_requirements);
+        let requirements_info = vk::ImageMemoryRequirementsInfo2::default().image(image);
+        unsafe { device.logical.get_image_memory_requirements2(&requirements_info, &mut requirements2) };
+        let requirements = requirements2.memory_requirements;
+
+        let memory_type_index = find_memory_type(requirements.memory_type_bits, properties, device.mem_properties)?;
+
+        let dedicated = dedicated_requirements.prefers_dedicated_allocation == vk::TRUE
+            || dedicated_requirements.requires_dedicated_allocation == vk::TRUE
+            || requirements.size > self.image_block / 2;
+
+        let (memory, offset) = if dedicated {
+            let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default().image(image);
+            let allocate_info = vk::MemoryAllocateInfo::default()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index)
+                .push_next(&mut dedicated_info);
+
+            let memory = unsafe { device.logical.allocate_memory(&allocate_info, None)? };
+            device.memory_tracker.track(TrackedKind::DeviceMemory, memory, 0, requirements.size, label);
+            self.dedicated.push(memory);
+            (memory, 0)
+        } else {
+            let pool = match self.image_pools.iter().position(|pool| pool.memory_type_index == memory_type_index && pool.tiling == image_ci.tiling) {
+                Some(i) => &mut self.image_pools[i],
+                None => {
+                    self.image_pools.push(ImagePool { memory_type_index, tiling: image_ci.tiling, blocks: Vec::new() });
+                    self.image_pools.last_mut().unwrap()
+                }
+            };
+
+            pool.alloc(device, &requirements, self.image_block)?
         };
 
-        let incr = if heap.align > 0 { size.next_multiple_of(heap.align) } else { size };
+        unsafe { device.logical.bind_image_memory(image, memory, offset)?; }
 
-        if incr != size {
-            heap.waste += incr - size;
+        device.memory_tracker.track(TrackedKind::Image, image, 0, requirements.size, label);
+        Ok(ImageBundle { image, memory, format: image_ci.format, offset, size: requirements.size })
+    }
+
+    /* Destroys the image and returns its memory, the GPU must be done with it */
+    pub fn destroy_image(&mut self, device: &DeviceBundle, image: &ImageBundle) -> Result<()> {
+        unsafe { device.logical.destroy_image(image.image, None); }
+        device.memory_tracker.untrack(TrackedKind::Image, image.image, 0);
+
+        if let Some(i) = self.dedicated.iter().position(|memory| *memory == image.memory) {
+            device.memory_tracker.untrack(TrackedKind::DeviceMemory, image.memory, 0);
+            unsafe { device.logical.free_memory(self.dedicated.swap_remove(i), None); }
+            return Ok(());
         }
 
-        heap.offset += incr;
+        let block = self.image_pools.iter_mut().flat_map(|pool| pool.blocks.iter_mut()).find(|block| block.memory == image.memory)
+            .ok_or_else(|| anyhow!("Allocator: Image memory {:?} is not an image block", image.memory))?;
+
+        if !block.free_list.free(image.offset, image.size) {
+            return Err(anyhow!("Allocator: Range {}..{} of an image block was not allocated", image.offset, image.offset + image.size));
+        }
 
-        Ok(bundle)
+        Ok(())
     }
 
+    fn print_pool_stats(name: &str, stats: AllocatorStats) {
+        println!("Pool '{}' stats: ", name);
+        println!("\t Blocks: {}", stats.blocks);
+        println!("\t Capacity: {}", stats.capacity);
+        println!("\t Used: {}", stats.used);
+        println!("\t Allocations: {}", stats.allocations);
+        println!("\t Free ranges: {}", stats.free_ranges);
+        println!("\t Largest free: {}", stats.largest_free);
+        println!("\t Fragmentation: {:.2}", stats.fragmentation());
+    }
 
-    pub fn print_heap_stats(heap: &AllocatorHeap, name: &str) {
+    pub fn print_stats(&self) {
+        for pool in [&self.staging, &self.device_vertex, &self.device_index, &self.uniform_buffer] {
+            Self::print_pool_stats(pool.buffer_type.name(), pool.stats());
+        }
 
-        println!("Heap '{}' stats: ", name);
-        println!("\t Size: {}", heap.size);
-        println!("\t Align: {}", heap.align);
-        println!("\t Offset: {}", heap.offset);
-        println!("\t Waste: {}", heap.waste);
-        println!("\t Remaining: {}", heap.size - heap.offset);
+        for pool in self.image_pools.iter() {
+            Self::print_pool_stats(&format!("{:?} images, memory type {}", pool.tiling, pool.memory_type_index), pool.stats());
+        }
 
+        println!("Dedicated image allocations: {}", self.dedicated.len());
     }
 
 
     pub fn release(&mut self, device: &DeviceBundle) {
-        unsafe {
-            Self::print_heap_stats(&self.staging, "Staging");
-            device.logical.destroy_buffer(self.staging.heap.buffer, None);
-            device.logical.free_memory(self.staging.heap.memory, None);
-            self.staging.offset = 0;
-            self.staging.size = 0;
+        self.print_stats();
+
+        for pool in [&mut self.staging, &mut self.device_vertex, &mut self.device_index, &mut self.uniform_buffer] {
+            pool.release(device);
+        }
+
+        let image_blocks = self.image_pools.drain(..).flat_map(|pool| pool.blocks.into_iter()).map(|block| block.memory);
+        for memory in image_blocks.chain(self.dedicated.drain(..)) {
+            device.memory_tracker.untrack(TrackedKind::DeviceMemory, memory, 0);
+            unsafe { device.logical.free_memory(memory, None); }
+        }
+    }
+}
 
-            Self::print_heap_stats(&self.device_vertex, "Vertex");
-            device.logical.destroy_buffer(self.device_vertex.heap.buffer, None);
-            device.logical.free_memory(self.device_vertex.heap.memory, None);
-            self.device_vertex.offset = 0;
-            self.device_vertex.size = 0;
+#[cfg(test)]
+mod tests {
+    use super::FreeList;
 
-            Self::print_heap_stats(&self.device_index, "Index");
-            device.logical.destroy_buffer(self.device_index.heap.buffer, None);
-            device.logical.free_memory(self.device_index.heap.memory, None);
-            self.device_index.offset = 0;
-            self.device_index.size = 0;
+    #[test]
+    fn test_free_list_alignment() {
+        let mut list = FreeList::new(256);
 
-            Self::print_heap_stats(&self.uniform_buffer, "Uniform_Buffer");
-            device.logical.destroy_buffer(self.uniform_buffer.heap.buffer, None);
-            device.logical.free_memory(self.uniform_buffer.heap.memory, None);
-            self.uniform_buffer.offset = 0;
-            self.uniform_buffer.size = 0;
+        assert_eq!(list.alloc(10, 16), Some(0));
+        assert_eq!(list.alloc(10, 16), Some(16));
+        assert_eq!(list.alloc(1, 64), Some(64));
 
+        // The padding in front of aligned allocations is still usable
+        assert_eq!(list.alloc(6, 1), Some(10));
+        assert_eq!(list.free_bytes(), 256 - 27);
+        assert_eq!(list.alloc(512, 1), None);
+    }
 
-        }
+    #[test]
+    fn test_free_list_coalesces() {
+        let mut list = FreeList::new(100);
+
+        let a = list.alloc(30, 1).unwrap();
+        let b = list.alloc(30, 1).unwrap();
+        let c = list.alloc(30, 1).unwrap();
+        assert_eq!(list.largest_free(), 10);
+
+        assert!(list.free(a, 30));
+        assert!(list.free(c, 30));
+        assert_eq!(list.ranges, vec![(0, 30), (60, 40)]);
+
+        // Double frees and ranges that were never allocated are refused
+        assert!(!list.free(a, 30));
+        assert!(!list.free(90, 20));
+
+        assert!(list.free(b, 30));
+        assert_eq!(list.ranges, vec![(0, 100)]);
+        assert_eq!(list.allocations, 0);
     }
 }
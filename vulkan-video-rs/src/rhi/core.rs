@@ -1,7 +1,85 @@
 use ash::vk;
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum BlendMode
+{
+    /* No blending, the fragment replaces the attachment */
+    Opaque,
+
+    /* src * src_alpha + dst * (1 - src_alpha) */
+    Alpha,
+
+    /* src * src_alpha + dst */
+    Additive,
+}
+
+impl BlendMode
+{
+    pub fn attachment_state(&self) -> vk::PipelineColorBlendAttachmentState {
+        let (blend_enable, dst_color_blend_factor) = match self {
+            BlendMode::Opaque   => (vk::FALSE, vk::BlendFactor::ZERO),
+            BlendMode::Alpha    => (vk::TRUE,  vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive => (vk::TRUE,  vk::BlendFactor::ONE),
+        };
+
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable,
+            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            dst_color_blend_factor,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+}
+
+/* Everything that selects a graphics pipeline, used as the key of the pipeline cache in VkBase */
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct GraphicsPSO
 {
-    shader_id : usize,
-    topology  : vk::PrimitiveTopology
+    pub shader_id    : usize,
+    pub topology     : vk::PrimitiveTopology,
+    pub cull_mode    : vk::CullModeFlags,
+    pub blend        : BlendMode,
+    pub polygon_mode : vk::PolygonMode,
+}
+
+impl GraphicsPSO
+{
+    /* The state every shader gets by default: filled, back face culled, opaque triangles */
+    pub const fn new(shader_id: usize) -> Self {
+        Self {
+            shader_id,
+            topology     : vk::PrimitiveTopology::TRIANGLE_LIST,
+            cull_mode    : vk::CullModeFlags::BACK,
+            blend        : BlendMode::Opaque,
+            polygon_mode : vk::PolygonMode::FILL,
+        }
+    }
+
+    pub const fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub const fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub const fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub const fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::new(self.shader_id)
+    }
 }
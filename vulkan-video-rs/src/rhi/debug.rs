@@ -0,0 +1,64 @@
+/*
+ * Object names and command buffer labels for capture tools like RenderDoc.
+ * Everything here is a no-op in release builds, and in debug builds when VK_EXT_debug_utils is not available.
+ */
+
+use ash::vk;
+
+use crate::vk_bundles::DeviceBundle;
+
+/* Label colours, so the passes are easy to tell apart in a capture */
+pub const UPLOAD_COLOUR: [f32; 4] = [0.9, 0.6, 0.1, 1.0];
+pub const DRAW_COLOUR: [f32; 4] = [0.2, 0.6, 0.9, 1.0];
+
+#[cfg(debug_assertions)]
+pub fn name_object<H: vk::Handle>(device: &DeviceBundle, handle: H, name: &str) {
+    let Some(debug_utils) = device.debug_utils.as_ref() else { return; };
+
+    let name = std::ffi::CString::new(name).unwrap_or_default();
+    let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+        .object_handle(handle)
+        .object_name(&name);
+
+    unsafe { let _ = debug_utils.set_debug_utils_object_name(&name_info); }
+}
+
+#[cfg(debug_assertions)]
+pub fn begin_label(device: &DeviceBundle, cb: vk::CommandBuffer, name: &str, colour: [f32; 4]) {
+    let Some(debug_utils) = device.debug_utils.as_ref() else { return; };
+
+    let name = std::ffi::CString::new(name).unwrap_or_default();
+    let label = vk::DebugUtilsLabelEXT::default()
+        .label_name(&name)
+        .color(colour);
+
+    unsafe { debug_utils.cmd_begin_debug_utils_label(cb, &label); }
+}
+
+#[cfg(debug_assertions)]
+pub fn end_label(device: &DeviceBundle, cb: vk::CommandBuffer) {
+    let Some(debug_utils) = device.debug_utils.as_ref() else { return; };
+
+    unsafe { debug_utils.cmd_end_debug_utils_label(cb); }
+}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+pub fn name_object<H: vk::Handle>(_device: &DeviceBundle, _handle: H, _name: &str) {}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+pub fn begin_label(_device: &DeviceBundle, _cb: vk::CommandBuffer, _name: &str, _colour: [f32; 4]) {}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+pub fn end_label(_device: &DeviceBundle, _cb: vk::CommandBuffer) {}
+
+/* Name an object with a formatted name, the formatting is compiled out together with the call */
+#[macro_export]
+macro_rules! debug_name {
+    ($device:expr, $handle:expr, $($name:tt)+) => {
+        #[cfg(debug_assertions)]
+        $crate::rhi::debug::name_object($device, $handle, &format!($($name)+));
+    };
+}
@@ -0,0 +1,158 @@
+/*
+ * Bookkeeping of the Vulkan memory, buffers and images that are alive, and of the ranges handed out by rhi::allocator.
+ * Every object is recorded with a label and size when it is created and forgotten when it is destroyed,
+ * whatever is still recorded when VkBase is dropped has leaked.
+ */
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ash::vk::Handle;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TrackedKind {
+    DeviceMemory,
+    Buffer,
+    Image,
+
+    /* A range of an allocator block, the block itself is tracked as a buffer */
+    BufferRange,
+}
+
+impl TrackedKind {
+    pub const ALL: [TrackedKind; 4] = [TrackedKind::DeviceMemory, TrackedKind::Buffer, TrackedKind::Image, TrackedKind::BufferRange];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrackedKind::DeviceMemory => "device memory",
+            TrackedKind::Buffer => "buffers",
+            TrackedKind::Image => "images",
+            TrackedKind::BufferRange => "buffer ranges",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackedObject {
+    pub kind: TrackedKind,
+    pub handle: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub label: String,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct CategoryUsage {
+    pub count: usize,
+    pub current: u64,
+    pub peak: u64,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    /* Keyed by kind, raw handle and offset, only ranges share a handle */
+    objects: HashMap<(TrackedKind, u64, u64), TrackedObject>,
+    usage: HashMap<TrackedKind, CategoryUsage>,
+}
+
+/* Lives in DeviceBundle, which is shared, so the state is behind a lock */
+#[derive(Default)]
+pub struct MemoryTracker {
+    state: Mutex<TrackerState>,
+}
+
+impl MemoryTracker {
+    pub fn track<H: Handle>(&self, kind: TrackedKind, handle: H, offset: u64, size: u64, label: &str) {
+        let handle = handle.as_raw();
+        let mut state = self.state.lock().unwrap();
+
+        let replaced = state.objects.insert((kind, handle, offset), TrackedObject { kind, handle, offset, size, label: label.to_string() });
+        if let Some(replaced) = replaced {
+            log::warn!("Memory tracker: {} {:#x} was tracked twice, as '{}' and '{}'", kind.name(), handle, replaced.label, label);
+            Self::remove_usage(&mut state, &replaced);
+        }
+
+        let usage = state.usage.entry(kind).or_default();
+        usage.count += 1;
+        usage.current += size;
+        usage.peak = usage.peak.max(usage.current);
+    }
+
+    pub fn untrack<H: Handle>(&self, kind: TrackedKind, handle: H, offset: u64) {
+        let handle = handle.as_raw();
+        let mut state = self.state.lock().unwrap();
+
+        match state.objects.remove(&(kind, handle, offset)) {
+            Some(object) => Self::remove_usage(&mut state, &object),
+            None => log::warn!("Memory tracker: {} {:#x} at offset {} was never tracked", kind.name(), handle, offset),
+        }
+    }
+
+    fn remove_usage(state: &mut TrackerState, object: &TrackedObject) {
+        let usage = state.usage.entry(object.kind).or_default();
+        usage.count -= 1;
+        usage.current -= object.size;
+    }
+
+    pub fn usage(&self, kind: TrackedKind) -> CategoryUsage {
+        self.state.lock().unwrap().usage.get(&kind).copied().unwrap_or_default()
+    }
+
+    /* Largest first */
+    pub fn alive(&self) -> Vec<TrackedObject> {
+        let mut objects: Vec<_> = self.state.lock().unwrap().objects.values().cloned().collect();
+        objects.sort_by(|a, b| b.size.cmp(&a.size).then(a.label.cmp(&b.label)));
+        objects
+    }
+
+    pub fn report(&self) -> String {
+        TrackedKind::ALL.iter().map(|kind| {
+            let usage = self.usage(*kind);
+            format!("{}: {} alive, {} KiB, peak {} KiB", kind.name(), usage.count, usage.current / 1024, usage.peak / 1024)
+        }).collect::<Vec<_>>().join("\n")
+    }
+
+    /* Logs whatever is still alive as errors, returns the number of leaked objects */
+    pub fn report_leaks(&self) -> usize {
+        let alive = self.alive();
+
+        for object in alive.iter() {
+            log::error!("Leaked {} {:#x} at offset {}: '{}', {} bytes", object.kind.name(), object.handle, object.offset, object.label, object.size);
+        }
+
+        alive.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{self, Handle};
+
+    use super::{MemoryTracker, TrackedKind};
+
+    #[test]
+    fn test_usage_and_leaks() {
+        let tracker = MemoryTracker::default();
+        let buffer = vk::Buffer::from_raw(1);
+
+        tracker.track(TrackedKind::Buffer, buffer, 0, 1024, "block");
+        tracker.track(TrackedKind::BufferRange, buffer, 0, 256, "vertices");
+        tracker.track(TrackedKind::BufferRange, buffer, 256, 512, "indices");
+        tracker.untrack(TrackedKind::BufferRange, buffer, 0);
+
+        let ranges = tracker.usage(TrackedKind::BufferRange);
+        assert_eq!(ranges.count, 1);
+        assert_eq!(ranges.current, 512);
+        assert_eq!(ranges.peak, 768);
+
+        let alive = tracker.alive();
+        assert_eq!(alive.len(), 2);
+        assert_eq!(alive[0].label, "block");
+        assert_eq!(alive[1].label, "indices");
+
+        tracker.untrack(TrackedKind::BufferRange, buffer, 256);
+        tracker.untrack(TrackedKind::Buffer, buffer, 0);
+        assert!(tracker.alive().is_empty());
+        assert_eq!(tracker.usage(TrackedKind::Buffer).peak, 1024);
+    }
+}
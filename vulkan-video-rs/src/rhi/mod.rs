@@ -1,5 +1,13 @@
 mod shader;
 pub mod core;
+pub mod debug;
+pub mod adapter;
 pub mod allocator;
+pub mod memory_tracker;
+pub mod pipeline_cache;
+pub mod profiler;
+pub mod release;
+pub mod ring;
+pub mod transfer;
 
 pub use shader::*;
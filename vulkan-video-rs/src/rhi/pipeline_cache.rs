@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use crate::vk_bundles::DeviceBundle;
+
+/*
+ * On disk the driver's cache data is prefixed by our own header:
+ *   magic (4) | format version (4) | vendor id (4) | device id (4) | driver version (4) | pipeline cache uuid (16) | data size (8)
+ * The driver version is not part of the Vulkan cache header, so without it a driver update would hand stale data to the driver.
+ */
+const MAGIC: [u8; 4] = *b"VKPC";
+const FORMAT_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 44;
+
+/* Size of VkPipelineCacheHeaderVersionOne at the start of the driver's data */
+const VK_HEADER_SIZE: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct CacheHeader {
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub driver_version: u32,
+    pub uuid: [u8; vk::UUID_SIZE],
+}
+
+impl CacheHeader {
+    pub fn from_properties(properties: &vk::PhysicalDeviceProperties) -> Self {
+        Self {
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            uuid: properties.pipeline_cache_uuid,
+        }
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + data.len());
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.vendor_id.to_le_bytes());
+        out.extend_from_slice(&self.device_id.to_le_bytes());
+        out.extend_from_slice(&self.driver_version.to_le_bytes());
+        out.extend_from_slice(&self.uuid);
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    /* Returns the driver data if the file was written by this device and driver */
+    pub fn validate<'a>(&self, file: &'a [u8]) -> Result<&'a [u8]> {
+        if file.len() < HEADER_SIZE {
+            return Err(anyhow!("File is smaller than the header."));
+        }
+
+        let read_u32 = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap());
+
+        if file[0..4] != MAGIC || read_u32(4) != FORMAT_VERSION {
+            return Err(anyhow!("Unknown file format."));
+        }
+
+        let header = CacheHeader {
+            vendor_id: read_u32(8),
+            device_id: read_u32(12),
+            driver_version: read_u32(16),
+            uuid: file[20..36].try_into().unwrap(),
+        };
+
+        if header != *self {
+            return Err(anyhow!("Cache was written by a different device or driver."));
+        }
+
+        let size = u64::from_le_bytes(file[36..44].try_into().unwrap()) as usize;
+        let data = &file[HEADER_SIZE..];
+        if data.len() != size {
+            return Err(anyhow!("Expected {} bytes of cache data, found {}.", size, data.len()));
+        }
+
+        // The driver repeats vendor, device and uuid in its own header, check it agrees
+        if data.len() < VK_HEADER_SIZE {
+            return Err(anyhow!("Cache data is smaller than the Vulkan header."));
+        }
+
+        let read_data_u32 = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let vk_header_matches = read_data_u32(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+            && read_data_u32(8) == self.vendor_id
+            && read_data_u32(12) == self.device_id
+            && data[16..32] == self.uuid;
+
+        if !vk_header_matches {
+            return Err(anyhow!("Vulkan cache header does not match the device."));
+        }
+
+        Ok(data)
+    }
+}
+
+/* Create the pipeline cache, seeded from the file when it is valid for this device */
+pub fn load(device: &DeviceBundle, path: &Path) -> vk::PipelineCache {
+    let header = CacheHeader::from_properties(&device.properties);
+
+    let file = std::fs::read(path).ok();
+    let initial_data = match file.as_deref().map(|file| header.validate(file)) {
+        Some(Ok(data)) => data,
+        Some(Err(e)) => {
+            println!("Pipeline cache '{:?}' ignored: {}", path, e);
+            &[]
+        }
+        None => &[],
+    };
+
+    let create_info = vk::PipelineCacheCreateInfo::default()
+        .initial_data(initial_data);
+
+    match unsafe { device.logical.create_pipeline_cache(&create_info, None) } {
+        Ok(cache) => cache,
+        Err(e) => {
+            // A driver may still reject data that passed the header checks, start empty then
+            println!("Failed to create pipeline cache from '{:?}' ({}), starting empty.", path, e);
+            unsafe { device.logical.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default(), None).expect("Failed to create pipeline cache.") }
+        }
+    }
+}
+
+pub fn save(device: &DeviceBundle, cache: vk::PipelineCache, path: &Path) -> Result<()> {
+    let data = unsafe { device.logical.get_pipeline_cache_data(cache)? };
+    let header = CacheHeader::from_properties(&device.properties);
+
+    // Write next to the target and rename so an interrupted save cannot leave a truncated cache
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, header.encode(&data))?;
+    std::fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheHeader, VK_HEADER_SIZE};
+
+    fn header() -> CacheHeader {
+        CacheHeader { vendor_id: 0x10de, device_id: 0x2684, driver_version: 7, uuid: [3; 16] }
+    }
+
+    fn driver_data(header: &CacheHeader) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(VK_HEADER_SIZE as u32).to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&header.vendor_id.to_le_bytes());
+        data.extend_from_slice(&header.device_id.to_le_bytes());
+        data.extend_from_slice(&header.uuid);
+        data.extend_from_slice(&[0xab; 64]);
+        data
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let header = header();
+        let data = driver_data(&header);
+        let file = header.encode(&data);
+        assert_eq!(header.validate(&file).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn test_rejects_other_driver() {
+        let header = header();
+        let file = header.encode(&driver_data(&header));
+
+        let updated = CacheHeader { driver_version: 8, ..header };
+        assert!(updated.validate(&file).is_err());
+
+        let other_uuid = CacheHeader { uuid: [4; 16], ..header };
+        assert!(other_uuid.validate(&file).is_err());
+
+        assert!(header.validate(&file[..file.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+/*
+ * GPU timestamps around the uploads and passes of a frame, combined with CPU timings into a rolling profile.
+ * Queries are read back a few frames later without waiting, a frame whose queries are still pending is not profiled on the GPU.
+ */
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+use ash::vk;
+
+use crate::rhi::debug;
+use crate::vk_bundles::DeviceBundle;
+
+/* Scopes per frame, every scope takes a begin and an end query */
+const MAX_SCOPES: u32 = 32;
+
+/* Frames kept for the averages and the CSV dump */
+const HISTORY_FRAMES: usize = 240;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProfiledQueue {
+    Graphics,
+    Transfer,
+}
+
+struct Scope {
+    name: &'static str,
+    first_query: u32,
+    mask: u64,
+    ended: bool,
+}
+
+/* One query pool per frame slot, reused once the results of its previous frame have been read */
+struct QueryFrame {
+    pool: vk::QueryPool,
+    frame: u64,
+    scopes: Vec<Scope>,
+}
+
+pub struct Profiler {
+    frames: Vec<QueryFrame>,
+
+    /* Slot written this frame, None when its previous frame is still on the GPU */
+    current: Option<usize>,
+
+    /* Open scopes, None for the ones that write no queries */
+    open: Vec<Option<usize>>,
+
+    /* Valid bits of the timestamps on each queue, None when the queue can't write timestamps */
+    graphics_mask: Option<u64>,
+    transfer_mask: Option<u64>,
+
+    /* Nanoseconds per timestamp tick */
+    period: f32,
+
+    frame: u64,
+    pub history: ProfileHistory,
+}
+
+fn valid_bits_mask(bits: u32) -> Option<u64> {
+    match bits {
+        0 => None,
+        64.. => Some(u64::MAX),
+        bits => Some((1 << bits) - 1),
+    }
+}
+
+/* Timestamps wrap at their valid bits, so the difference is taken modulo the mask */
+pub fn ticks_to_ms(begin: u64, end: u64, mask: u64, period: f32) -> f32 {
+    let ticks = end.wrapping_sub(begin) & mask;
+    (ticks as f64 * period as f64 / 1.0e6) as f32
+}
+
+impl Profiler {
+    pub fn new(device: &DeviceBundle, num_frames: usize) -> Self {
+        let period = device.properties.limits.timestamp_period;
+        let supported = device.host_query_reset && period > 0.0;
+
+        let graphics_mask = if supported { valid_bits_mask(device.timestamp_valid_bits) } else { None };
+        let transfer_mask = if supported { valid_bits_mask(device.transfer_timestamp_valid_bits) } else { None };
+
+        if graphics_mask.is_none() {
+            log::info!("GPU timestamps are not supported, only CPU timings will be profiled.");
+        }
+
+        let frames = if graphics_mask.is_some() || transfer_mask.is_some() {
+            (0..num_frames).map(|_| {
+                let pool_info = vk::QueryPoolCreateInfo::default()
+                    .query_type(vk::QueryType::TIMESTAMP)
+                    .query_count(MAX_SCOPES * 2);
+
+                let pool = unsafe {
+                    let pool = device.logical.create_query_pool(&pool_info, None).expect("Failed to create a timestamp query pool.");
+                    device.logical.reset_query_pool(pool, 0, MAX_SCOPES * 2);
+                    pool
+                };
+
+                debug::name_object(device, pool, "Profiler timestamps");
+
+                QueryFrame { pool, frame: 0, scopes: Vec::new() }
+            }).collect()
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            frames,
+            current: None,
+            open: Vec::new(),
+            graphics_mask,
+            transfer_mask,
+            period,
+            frame: 0,
+            history: ProfileHistory::new(HISTORY_FRAMES),
+        }
+    }
+
+    /* Start a new frame, reading back the results of the frame that last used this slot */
+    pub fn begin_frame(&mut self, device: &DeviceBundle) {
+        self.frame += 1;
+        self.open.clear();
+        self.current = None;
+
+        if self.frames.is_empty() {
+            return;
+        }
+
+        let slot = (self.frame % self.frames.len() as u64) as usize;
+        let query_frame = &mut self.frames[slot];
+
+        if let Some(last) = query_frame.scopes.last() {
+            let count = last.first_query + 2;
+            let mut results = vec![[0u64; 2]; count as usize];
+
+            // NOT_READY is expected while some queries are pending, the availability values tell which ones are done
+            let _ = unsafe {
+                device.logical.get_query_pool_results(query_frame.pool, 0, &mut results,
+                                                      vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY)
+            };
+
+            let ready = query_frame.scopes.iter().filter(|scope| scope.ended).all(|scope| {
+                results[scope.first_query as usize][1] != 0 && results[scope.first_query as usize + 1][1] != 0
+            });
+
+            if !ready {
+                return;
+            }
+
+            for scope in query_frame.scopes.iter().filter(|scope| scope.ended) {
+                let begin = results[scope.first_query as usize][0];
+                let end = results[scope.first_query as usize + 1][0];
+                self.history.record_gpu(query_frame.frame, scope.name, ticks_to_ms(begin, end, scope.mask, self.period));
+            }
+
+            unsafe { device.logical.reset_query_pool(query_frame.pool, 0, count); }
+            query_frame.scopes.clear();
+        }
+
+        query_frame.frame = self.frame;
+        self.current = Some(slot);
+    }
+
+    pub fn begin_scope(&mut self, device: &DeviceBundle, cb: vk::CommandBuffer, queue: ProfiledQueue, name: &'static str) {
+        let mask = match queue {
+            ProfiledQueue::Graphics => self.graphics_mask,
+            ProfiledQueue::Transfer => self.transfer_mask,
+        };
+
+        let scope = match (self.current, mask) {
+            (Some(slot), Some(mask)) if self.frames[slot].scopes.len() < MAX_SCOPES as usize => {
+                let query_frame = &mut self.frames[slot];
+                let first_query = query_frame.scopes.len() as u32 * 2;
+
+                unsafe { device.logical.cmd_write_timestamp(cb, vk::PipelineStageFlags::TOP_OF_PIPE, query_frame.pool, first_query); }
+
+                query_frame.scopes.push(Scope { name, first_query, mask, ended: false });
+                Some(query_frame.scopes.len() - 1)
+            }
+
+            _ => None,
+        };
+
+        self.open.push(scope);
+    }
+
+    pub fn end_scope(&mut self, device: &DeviceBundle, cb: vk::CommandBuffer) {
+        let Some(Some(index)) = self.open.pop() else { return; };
+        let Some(slot) = self.current else { return; };
+
+        let query_frame = &mut self.frames[slot];
+        let scope = &mut query_frame.scopes[index];
+
+        unsafe { device.logical.cmd_write_timestamp(cb, vk::PipelineStageFlags::BOTTOM_OF_PIPE, query_frame.pool, scope.first_query + 1); }
+        scope.ended = true;
+    }
+
+    pub fn record_cpu(&mut self, name: &'static str, duration: Duration) {
+        self.history.record_cpu(self.frame, name, duration.as_secs_f32() * 1.0e3);
+    }
+
+    pub fn destroy(&mut self, device: &DeviceBundle) {
+        for query_frame in self.frames.drain(..) {
+            unsafe { device.logical.destroy_query_pool(query_frame.pool, None); }
+        }
+    }
+}
+
+/* Timings of a frame in milliseconds, scopes with the same name in a frame are added up */
+#[derive(Default, Debug)]
+pub struct FrameSample {
+    pub frame: u64,
+    pub cpu: Vec<(&'static str, f32)>,
+    pub gpu: Vec<(&'static str, f32)>,
+}
+
+fn add_timing(timings: &mut Vec<(&'static str, f32)>, name: &'static str, ms: f32) {
+    match timings.iter_mut().find(|(timing, _)| *timing == name) {
+        Some((_, total)) => *total += ms,
+        None => timings.push((name, ms)),
+    }
+}
+
+/* Average of each name over the samples it appears in, in order of first appearance */
+fn average<'a>(timings: impl Iterator<Item = &'a Vec<(&'static str, f32)>>) -> Vec<(&'static str, f32)> {
+    let mut totals: Vec<(&'static str, f32, u32)> = Vec::new();
+
+    for (name, ms) in timings.flatten() {
+        match totals.iter_mut().find(|(total, _, _)| total == name) {
+            Some((_, total, count)) => { *total += ms; *count += 1; }
+            None => totals.push((name, *ms, 1)),
+        }
+    }
+
+    totals.into_iter().map(|(name, total, count)| (name, total / count as f32)).collect()
+}
+
+pub struct ProfileHistory {
+    samples: VecDeque<FrameSample>,
+    capacity: usize,
+}
+
+impl ProfileHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self { samples: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /* GPU results arrive a few frames late, samples older than the history are dropped */
+    fn sample_mut(&mut self, frame: u64) -> Option<&mut FrameSample> {
+        if self.samples.back().is_none_or(|sample| sample.frame < frame) {
+            if self.samples.len() == self.capacity {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(FrameSample { frame, ..Default::default() });
+        }
+
+        self.samples.iter_mut().rev().find(|sample| sample.frame == frame)
+    }
+
+    pub fn record_cpu(&mut self, frame: u64, name: &'static str, ms: f32) {
+        if let Some(sample) = self.sample_mut(frame) {
+            add_timing(&mut sample.cpu, name, ms);
+        }
+    }
+
+    pub fn record_gpu(&mut self, frame: u64, name: &'static str, ms: f32) {
+        if let Some(sample) = self.sample_mut(frame) {
+            add_timing(&mut sample.gpu, name, ms);
+        }
+    }
+
+    pub fn cpu_averages(&self) -> Vec<(&'static str, f32)> {
+        average(self.samples.iter().map(|sample| &sample.cpu))
+    }
+
+    pub fn gpu_averages(&self) -> Vec<(&'static str, f32)> {
+        average(self.samples.iter().map(|sample| &sample.gpu))
+    }
+
+    /* One line, short enough for the window title */
+    pub fn summary(&self) -> String {
+        let format = |timings: Vec<(&'static str, f32)>| {
+            timings.iter().map(|(name, ms)| format!("{} {:.2}", name, ms)).collect::<Vec<_>>().join(", ")
+        };
+
+        let mut summary = format!("CPU ms: {}", format(self.cpu_averages()));
+
+        let gpu = self.gpu_averages();
+        if !gpu.is_empty() {
+            let _ = write!(summary, " | GPU ms: {}", format(gpu));
+        }
+
+        summary
+    }
+
+    /* A row per frame and a column per timing, missing timings are left empty */
+    pub fn to_csv(&self) -> String {
+        let mut cpu_names: Vec<&'static str> = Vec::new();
+        let mut gpu_names: Vec<&'static str> = Vec::new();
+
+        for sample in self.samples.iter() {
+            for (name, _) in sample.cpu.iter() {
+                if !cpu_names.contains(name) { cpu_names.push(name); }
+            }
+            for (name, _) in sample.gpu.iter() {
+                if !gpu_names.contains(name) { gpu_names.push(name); }
+            }
+        }
+
+        let mut csv = String::from("frame");
+        for name in cpu_names.iter() {
+            let _ = write!(csv, ",cpu {}", name);
+        }
+        for name in gpu_names.iter() {
+            let _ = write!(csv, ",gpu {}", name);
+        }
+        csv.push('\n');
+
+        let column = |timings: &Vec<(&'static str, f32)>, name: &'static str| {
+            timings.iter().find(|(timing, _)| *timing == name).map(|(_, ms)| format!("{:.4}", ms)).unwrap_or_default()
+        };
+
+        for sample in self.samples.iter() {
+            let _ = write!(csv, "{}", sample.frame);
+            for name in cpu_names.iter() {
+                let _ = write!(csv, ",{}", column(&sample.cpu, name));
+            }
+            for name in gpu_names.iter() {
+                let _ = write!(csv, ",{}", column(&sample.gpu, name));
+            }
+            csv.push('\n');
+        }
+
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ticks_to_ms, ProfileHistory};
+
+    #[test]
+    fn test_ticks_to_ms() {
+        assert_eq!(ticks_to_ms(1_000, 3_000_000, u64::MAX, 1.0), 2.999);
+
+        // A 36 bit counter that wrapped between the two timestamps
+        let mask = (1 << 36) - 1;
+        assert_eq!(ticks_to_ms(mask - 499_999, 500_000, mask, 2.0), 2.0);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = ProfileHistory::new(3);
+
+        for frame in 1..=4 {
+            history.record_cpu(frame, "update", frame as f32);
+        }
+
+        // GPU results of frame 3 arrive late, the two scenes of frame 4 add up and frame 1 was dropped
+        history.record_gpu(4, "Scene", 1.0);
+        history.record_gpu(4, "Scene", 2.0);
+        history.record_gpu(3, "Scene", 1.0);
+        history.record_gpu(1, "Scene", 10.0);
+
+        assert_eq!(history.cpu_averages(), vec![("update", 3.0)]);
+        assert_eq!(history.gpu_averages(), vec![("Scene", 2.0)]);
+        assert_eq!(history.summary(), "CPU ms: update 3.00 | GPU ms: Scene 2.00");
+        assert_eq!(history.to_csv(), "frame,cpu update,gpu Scene\n2,2.0000,\n3,3.0000,1.0000\n4,4.0000,3.0000\n");
+    }
+}
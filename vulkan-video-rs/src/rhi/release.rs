@@ -0,0 +1,171 @@
+/*
+ * Owned handles to resources that the GPU may still be reading when they are dropped.
+ * Dropping an Owned puts its resources in a ReleaseQueue, VkBase destroys them once every frame that could have
+ * used them has finished, and flushes whatever is left when it is dropped itself.
+ */
+
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use ash::vk;
+
+use crate::vk_bundles::{BufferBundle, ImageBundle, TextureBundle};
+
+pub enum Resource {
+    /* A range of an allocator block */
+    BufferRange(BufferBundle),
+
+    /* An image made by the allocator */
+    Image(ImageBundle),
+    ImageView(vk::ImageView),
+    Sampler(vk::Sampler),
+
+    /* From VkBase::descriptor_pool */
+    DescriptorSets(Vec<vk::DescriptorSet>),
+}
+
+pub trait Release {
+    fn release(self, resources: &mut Vec<Resource>);
+}
+
+impl Release for BufferBundle {
+    fn release(self, resources: &mut Vec<Resource>) {
+        resources.push(Resource::BufferRange(self));
+    }
+}
+
+impl Release for TextureBundle {
+    fn release(self, resources: &mut Vec<Resource>) {
+        resources.push(Resource::ImageView(self.image_view));
+        resources.push(Resource::Sampler(self.sampler));
+        resources.push(Resource::Image(self.resource));
+    }
+}
+
+impl Release for Vec<vk::DescriptorSet> {
+    fn release(self, resources: &mut Vec<Resource>) {
+        resources.push(Resource::DescriptorSets(self));
+    }
+}
+
+/* Shared by every Owned, only touched from the render thread */
+#[derive(Clone, Default)]
+pub struct ReleaseQueue {
+    dropped: Rc<RefCell<Vec<Resource>>>,
+    closed: Rc<Cell<bool>>,
+}
+
+impl ReleaseQueue {
+    pub fn own<T: Release>(&self, value: T) -> Owned<T> {
+        Owned { value: Some(value), queue: self.clone() }
+    }
+
+    pub fn take(&self) -> Vec<Resource> {
+        mem::take(&mut *self.dropped.borrow_mut())
+    }
+
+    /* Nothing takes from the queue anymore, whatever is dropped after this would leak */
+    pub fn close(&self) {
+        self.closed.set(true);
+    }
+}
+
+pub struct Owned<T: Release> {
+    value: Option<T>,
+    queue: ReleaseQueue,
+}
+
+impl<T: Release> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().unwrap()
+    }
+}
+
+impl<T: Release> Drop for Owned<T> {
+    fn drop(&mut self) {
+        debug_assert!(!self.queue.closed.get(), "Owned resource dropped after VkBase, it is never destroyed");
+
+        if let Some(value) = self.value.take() {
+            value.release(&mut self.queue.dropped.borrow_mut());
+        }
+    }
+}
+
+/* Dropped resources, one list per frame in flight */
+pub struct DeferredReleases {
+    frames: Vec<Vec<Resource>>,
+}
+
+impl DeferredReleases {
+    pub fn new(frames: usize) -> Self {
+        Self { frames: (0..frames).map(|_| Vec::new()).collect() }
+    }
+
+    /* Call once the fence of frame has been waited on. Whatever was dropped since the last call can still be in use by
+     * the frames in flight, so it waits for frame to come around again. Returns what is safe to destroy now. */
+    pub fn retire(&mut self, frame: usize, queue: &ReleaseQueue) -> Vec<Resource> {
+        mem::replace(&mut self.frames[frame], queue.take())
+    }
+
+    /* Everything, for when the device is idle */
+    pub fn drain(&mut self, queue: &ReleaseQueue) -> Vec<Resource> {
+        let mut resources: Vec<_> = self.frames.iter_mut().flat_map(mem::take).collect();
+        resources.extend(queue.take());
+        resources
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{self, Handle};
+
+    use super::{DeferredReleases, ReleaseQueue, Resource};
+
+    fn sets(raw: u64) -> Vec<vk::DescriptorSet> {
+        vec![vk::DescriptorSet::from_raw(raw)]
+    }
+
+    #[test]
+    fn test_deferred_until_frame_comes_around() {
+        let queue = ReleaseQueue::default();
+        let mut deferred = DeferredReleases::new(2);
+
+        let owned = queue.own(sets(1));
+        assert_eq!(owned[0], vk::DescriptorSet::from_raw(1));
+        drop(owned);
+
+        assert!(deferred.retire(0, &queue).is_empty());
+        assert!(deferred.retire(1, &queue).is_empty());
+
+        let ready = deferred.retire(0, &queue);
+        assert!(matches!(ready.as_slice(), [Resource::DescriptorSets(sets)] if sets[0] == vk::DescriptorSet::from_raw(1)));
+    }
+
+    #[test]
+    fn test_drain() {
+        let queue = ReleaseQueue::default();
+        let mut deferred = DeferredReleases::new(2);
+
+        drop(queue.own(sets(1)));
+        deferred.retire(0, &queue);
+        drop(queue.own(sets(2)));
+
+        assert_eq!(deferred.drain(&queue).len(), 2);
+        assert!(deferred.drain(&queue).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "dropped after VkBase")]
+    #[cfg(debug_assertions)]
+    fn test_drop_after_close() {
+        let queue = ReleaseQueue::default();
+        let owned = queue.own(sets(1));
+
+        queue.close();
+        drop(owned);
+    }
+}
@@ -0,0 +1,170 @@
+/*
+ * Transient upload memory, one region per frame in flight in a single persistently mapped buffer.
+ * Everything pushed during a frame lives until that region comes around again, which waits for the fences of the
+ * submissions that read it. Pushed ranges can be bound as dynamic uniforms, vertex or index data, or copied from.
+ * Staging data too large for the region, like a big mesh, gets a buffer of its own with the same lifetime.
+ */
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use crate::utils::buffer;
+use crate::vk_bundles::{BufferBundle, DeviceBundle};
+
+/* Bump allocation within one region */
+#[derive(Debug)]
+struct RingRegion {
+    head: u64,
+
+    /* Submissions that read from the region this time around */
+    fences: Vec<vk::Fence>,
+
+    /* Staging buffers that didn't fit, destroyed with the region's contents */
+    overflow: Vec<BufferBundle>,
+}
+
+impl RingRegion {
+    fn alloc(&mut self, size: u64, align: u64, capacity: u64) -> Option<u64> {
+        let offset = self.head.next_multiple_of(align);
+        if offset + size > capacity {
+            return None;
+        }
+
+        self.head = offset + size;
+        Some(offset)
+    }
+}
+
+pub struct UploadRing {
+    pub buffer: BufferBundle,
+    mapped: *mut u8,
+
+    regions: Vec<RingRegion>,
+    region_size: u64,
+    current: usize,
+
+    /* Satisfies dynamic uniform offsets and buffer to image copies */
+    align: u64,
+}
+
+impl UploadRing {
+    pub fn new(device: &DeviceBundle, frames: usize, region_size: u64) -> Self {
+        let usage = vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::UNIFORM_BUFFER
+            | vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER;
+        let properties = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+
+        // Read by both the graphics queue and a dedicated transfer queue
+        let buffer = buffer::create_shared_buffer(device, region_size * frames as u64, usage, properties, "Upload ring").expect("Failed to create the upload ring.");
+        crate::debug_name!(device, buffer.buffer, "Upload ring");
+
+        let mapped = unsafe {
+            device.logical.map_memory(buffer.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()).expect("Failed to map the upload ring.") as *mut u8
+        };
+
+        Self {
+            buffer,
+            mapped,
+            regions: (0..frames).map(|_| RingRegion { head: 0, fences: Vec::new(), overflow: Vec::new() }).collect(),
+            region_size,
+            current: 0,
+            align: device.properties.limits.min_uniform_buffer_offset_alignment.max(16),
+        }
+    }
+
+    /* Move on to the next region, waiting for the GPU if it is still reading it */
+    pub fn begin_frame(&mut self, device: &DeviceBundle) {
+        self.current = (self.current + 1) % self.regions.len();
+        let region = &mut self.regions[self.current];
+
+        if !region.fences.is_empty() {
+            unsafe {
+                device.logical.wait_for_fences(&region.fences, true, u64::MAX).expect("Failed to wait for the upload ring.");
+            }
+        }
+
+        for overflow in region.overflow.drain(..) {
+            buffer::destroy_buffer(device, &overflow);
+        }
+
+        region.fences.clear();
+        region.head = 0;
+    }
+
+    /* A submission that reads what was pushed this frame */
+    pub fn track_fence(&mut self, fence: vk::Fence) {
+        let fences = &mut self.regions[self.current].fences;
+        if !fences.contains(&fence) {
+            fences.push(fence);
+        }
+    }
+
+    pub fn push<T: Copy>(&mut self, data: &T) -> Result<BufferBundle> {
+        self.push_slice(std::slice::from_ref(data))
+    }
+
+    pub fn push_slice<T: Copy>(&mut self, data: &[T]) -> Result<BufferBundle> {
+        let size = std::mem::size_of_val(data) as u64;
+        let align = self.align.max(std::mem::align_of::<T>() as u64);
+
+        let offset = self.regions[self.current].alloc(size, align, self.region_size)
+            .ok_or_else(|| anyhow!("Upload ring: {} bytes don't fit in the {} bytes left this frame", size, self.region_size - self.regions[self.current].head))?;
+
+        let offset = self.current as u64 * self.region_size + offset;
+
+        unsafe {
+            let data_ptr = self.mapped.add(offset as usize) as *mut T;
+            data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+
+        Ok(BufferBundle { buffer: self.buffer.buffer, memory: self.buffer.memory, offset, size })
+    }
+
+    /* For data that is only copied from, falls back to a buffer of its own when the region is full */
+    pub fn stage_slice<T: Copy>(&mut self, device: &DeviceBundle, data: &[T]) -> Result<BufferBundle> {
+        if let Ok(staging) = self.push_slice(data) {
+            return Ok(staging);
+        }
+
+        let size = std::mem::size_of_val(data) as u64;
+        let properties = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let overflow = buffer::create_buffer(device, size, vk::BufferUsageFlags::TRANSFER_SRC, properties, "Upload ring overflow")?;
+        crate::debug_name!(device, overflow.buffer, "Upload ring overflow");
+
+        unsafe {
+            let data_ptr = device.logical.map_memory(overflow.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())? as *mut T;
+            data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
+            device.logical.unmap_memory(overflow.memory);
+        }
+
+        let staging = BufferBundle { buffer: overflow.buffer, memory: overflow.memory, offset: 0, size };
+        self.regions[self.current].overflow.push(overflow);
+
+        Ok(staging)
+    }
+
+    pub fn destroy(&mut self, device: &DeviceBundle) {
+        for region in self.regions.iter_mut() {
+            for overflow in region.overflow.drain(..) {
+                buffer::destroy_buffer(device, &overflow);
+            }
+        }
+
+        unsafe { device.logical.unmap_memory(self.buffer.memory); }
+        buffer::destroy_buffer(device, &self.buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingRegion;
+
+    #[test]
+    fn test_region_alloc() {
+        let mut region = RingRegion { head: 0, fences: Vec::new(), overflow: Vec::new() };
+
+        assert_eq!(region.alloc(10, 64, 256), Some(0));
+        assert_eq!(region.alloc(100, 64, 256), Some(64));
+        assert_eq!(region.alloc(64, 64, 256), Some(192));
+        assert_eq!(region.alloc(1, 64, 256), None);
+    }
+}
@@ -0,0 +1,205 @@
+use ash::vk;
+
+use crate::vk_bundles::{BufferBundle, DeviceBundle};
+
+#[derive(Clone, Copy, Debug)]
+pub enum TransferTarget {
+    Buffer { buffer: vk::Buffer, offset: u64, size: u64 },
+
+    /*
+     * Images written by uploads are CONCURRENT over both families (utils::image::create_texture_image), so they only
+     * change layout after the copies and the graphics queue has nothing to acquire
+     */
+    Image { image: vk::Image, aspect_mask: vk::ImageAspectFlags, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout },
+}
+
+/* A resource written by an upload, with how the graphics queue reads it afterwards */
+#[derive(Clone, Copy, Debug)]
+pub struct QueueTransfer {
+    pub target: TransferTarget,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub dst_access: vk::AccessFlags,
+}
+
+/* Copies recorded for the transfer queue, submitted with VkBase::submit_upload */
+pub struct UploadBatch {
+    pub cb: vk::CommandBuffer,
+    pub transfers: Vec<QueueTransfer>,
+
+    /* Render value of the last frame that read anything the copies overwrite, 0 when no frame did */
+    pub render_value: u64,
+}
+
+impl UploadBatch {
+    pub fn new(cb: vk::CommandBuffer) -> Self {
+        Self { cb, transfers: Vec::new(), render_value: 0 }
+    }
+
+    /* The copies overwrite something the frame with render_value read */
+    pub fn read_by(&mut self, render_value: u64) {
+        self.render_value = self.render_value.max(render_value);
+    }
+
+    pub fn release_buffer(&mut self, buffer: vk::Buffer, offset: u64, size: u64, dst_stage: vk::PipelineStageFlags, dst_access: vk::AccessFlags) {
+        self.transfers.push(QueueTransfer {
+            target: TransferTarget::Buffer { buffer, offset, size },
+            dst_stage,
+            dst_access,
+        });
+    }
+
+    pub fn release_bundle(&mut self, bundle: &BufferBundle, dst_stage: vk::PipelineStageFlags, dst_access: vk::AccessFlags) {
+        self.release_buffer(bundle.buffer, bundle.offset, bundle.size, dst_stage, dst_access);
+    }
+
+    pub fn release_image(&mut self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, dst_stage: vk::PipelineStageFlags, dst_access: vk::AccessFlags) {
+        self.transfers.push(QueueTransfer {
+            target: TransferTarget::Image { image, aspect_mask, old_layout, new_layout },
+            dst_stage,
+            dst_access,
+        });
+    }
+}
+
+/* Which half of a queue family ownership transfer a barrier is */
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Half {
+    /* Recorded on the transfer queue after the copies */
+    Release,
+
+    /* Recorded on the graphics queue before the resource is used */
+    Acquire,
+
+    /* Both queues are the same family, a plain barrier is enough */
+    Local,
+}
+
+fn record_barriers(device: &DeviceBundle, cb: vk::CommandBuffer, transfers: &[QueueTransfer], half: Half) {
+    if transfers.is_empty() {
+        return;
+    }
+
+    let (src_family, dst_family) = match half {
+        Half::Local => (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+        _ => (device.transfer_queue_family_index, device.queue_family_index),
+    };
+
+    // The release only makes the writes available, the acquire makes them visible to the readers
+    let src_access = if half == Half::Acquire { vk::AccessFlags::empty() } else { vk::AccessFlags::TRANSFER_WRITE };
+    let dst_access = |transfer: &QueueTransfer| if half == Half::Release { vk::AccessFlags::empty() } else { transfer.dst_access };
+
+    let mut buffer_barriers = Vec::new();
+    let mut image_barriers = Vec::new();
+
+    for transfer in transfers {
+        match transfer.target {
+            TransferTarget::Buffer { buffer, offset, size } => {
+                buffer_barriers.push(vk::BufferMemoryBarrier::default()
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access(transfer))
+                    .src_queue_family_index(src_family)
+                    .dst_queue_family_index(dst_family)
+                    .buffer(buffer)
+                    .offset(offset)
+                    .size(size));
+            }
+
+            TransferTarget::Image { image, aspect_mask, old_layout, new_layout } => {
+                if half == Half::Acquire {
+                    continue;
+                }
+
+                let sub_res = vk::ImageSubresourceRange { aspect_mask, base_mip_level: 0, level_count: 1,
+                                                          base_array_layer: 0, layer_count: 1 };
+
+                image_barriers.push(vk::ImageMemoryBarrier::default()
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access(transfer))
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(sub_res));
+            }
+        }
+    }
+
+    if buffer_barriers.is_empty() && image_barriers.is_empty() {
+        return;
+    }
+
+    let readers = transfers.iter().fold(vk::PipelineStageFlags::empty(), |stages, transfer| stages | transfer.dst_stage);
+
+    let (src_stage, dst_stage) = match half {
+        Half::Release => (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+        Half::Acquire => (vk::PipelineStageFlags::TOP_OF_PIPE, readers),
+        Half::Local   => (vk::PipelineStageFlags::TRANSFER, readers),
+    };
+
+    unsafe {
+        device.logical.cmd_pipeline_barrier(cb, src_stage, dst_stage, vk::DependencyFlags::empty(), &[], &buffer_barriers, &image_barriers);
+    }
+}
+
+/* End of the upload command buffer, hands the written resources over to the graphics queue */
+pub fn record_release(device: &DeviceBundle, cb: vk::CommandBuffer, transfers: &[QueueTransfer]) {
+    let half = if device.has_dedicated_transfer() { Half::Release } else { Half::Local };
+    record_barriers(device, cb, transfers, half);
+}
+
+/*
+ * Start of the upload command buffer. Without timeline semaphores nothing waits for the frames submitted before it,
+ * so the copies wait for their reads on the graphics queue they share
+ */
+pub fn record_wait_for_readers(device: &DeviceBundle, cb: vk::CommandBuffer) {
+    if device.timeline_semaphores {
+        return;
+    }
+
+    let readers = vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER;
+
+    // Overwriting what was read only needs the reads to be done, there is nothing to make visible
+    unsafe {
+        device.logical.cmd_pipeline_barrier(cb, readers, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &[]);
+    }
+}
+
+/* Start of a graphics command buffer, only needed when the upload ran on another queue family */
+pub fn record_acquire(device: &DeviceBundle, cb: vk::CommandBuffer, transfers: &[QueueTransfer]) {
+    if device.has_dedicated_transfer() {
+        record_barriers(device, cb, transfers, Half::Acquire);
+    }
+}
+
+/*
+ * Start of an upload that overwrites an image the graphics queue samples. On the same queue the barrier waits for the
+ * earlier reads, a dedicated transfer queue can't name the fragment stage and relies on VkBase::submit_upload waiting
+ * for the frames that read it instead
+ */
+pub fn record_image_write(device: &DeviceBundle, cb: vk::CommandBuffer, image: vk::Image, aspect_mask: vk::ImageAspectFlags) {
+    let (src_stage, src_access) = if device.has_dedicated_transfer() {
+        (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty())
+    } else {
+        (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ)
+    };
+
+    let sub_res = vk::ImageSubresourceRange { aspect_mask, base_mip_level: 0, level_count: 1,
+                                              base_array_layer: 0, layer_count: 1 };
+
+    let image_barriers = [
+        vk::ImageMemoryBarrier::default()
+            .src_access_mask(src_access)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(sub_res)
+    ];
+
+    unsafe {
+        device.logical.cmd_pipeline_barrier(cb, src_stage, vk::PipelineStageFlags::TRANSFER, vk::DependencyFlags::empty(), &[], &[], &image_barriers);
+    }
+}
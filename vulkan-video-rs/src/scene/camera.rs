@@ -1,5 +1,15 @@
+use std::f32::consts::FRAC_PI_2;
+
+use crate::geometry::mat4::Mat4;
 use crate::geometry::vec3::Vec3;
 
+const FOV_Y: f32 = FRAC_PI_2;
+const NEAR: f32 = 0.1;
+const FAR: f32 = 30.0;
+
+/* Half the height the orthographic projection shows, what the perspective one shows at the starting distance of 10 */
+const ORTHO_HALF_HEIGHT: f32 = 10.0;
+
 
 pub enum CameraAction {
     Right,
@@ -18,11 +28,17 @@ pub enum CameraAction {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct CameraParams {
     pub location: Vec3,
     pub direction: Vec3,
-    pub up: Vec3
+    pub up: Vec3,
 
+    /* Pixel rectangle (x, y, width, height) of the pane the camera renders into */
+    pub viewport: [f32; 4],
+
+    pub view: Mat4,
+    pub projection: Mat4,
 }
 
 pub struct Camera {
@@ -30,6 +46,9 @@ pub struct Camera {
 
 
     right: Vec3,
+
+    /* Toggled with CameraAction::ToggleType */
+    orthographic: bool,
 }
 
 impl Camera {
@@ -37,12 +56,30 @@ impl Camera {
     pub fn new(location: Vec3, direction: Vec3) -> Self {
 
         Self {
-            params: CameraParams { location, direction, up: Vec3::Y },
-            right: Vec3::X
+            params: CameraParams { location, direction, up: Vec3::Y, viewport: [0.0, 0.0, 1.0, 1.0], view: Mat4::IDENT, projection: Mat4::IDENT },
+            right: Vec3::X,
+            orthographic: false,
         }
     }
 
 
+    /* The projection follows the aspect ratio of the viewport */
+    pub fn update_matrices(&mut self) {
+        let [_, _, width, height] = self.params.viewport;
+
+        self.params.view = Mat4::look_at(self.params.location, self.params.direction, self.params.up);
+        self.params.projection = self.projection(width / height.max(1.0));
+    }
+
+    pub fn projection(&self, aspect: f32) -> Mat4 {
+        if self.orthographic {
+            let (w, h) = (ORTHO_HALF_HEIGHT * aspect, ORTHO_HALF_HEIGHT);
+            Mat4::orthographic(-w, w, -h, h, NEAR, FAR)
+        } else {
+            Mat4::perspective(FOV_Y, aspect, NEAR, FAR)
+        }
+    }
+
     pub fn update(&mut self, action: CameraAction, delta: f32) {
         match action {
             CameraAction::Right => self.params.location += delta * self.right,
@@ -57,7 +94,7 @@ impl Camera {
             CameraAction::SnapDirY => todo!(),
             CameraAction::SnapPosX => todo!(),
             CameraAction::SnapPosY => todo!(),
-            CameraAction::ToggleType => todo!(),
+            CameraAction::ToggleType => self.orthographic = !self.orthographic,
         }
     }
 
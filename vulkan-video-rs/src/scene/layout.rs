@@ -0,0 +1,264 @@
+use anyhow::Result;
+use ash::vk;
+
+use crate::rhi::debug;
+use crate::rhi::release::Owned;
+use crate::rhi::ring::UploadRing;
+use crate::scene::camera::{Camera, CameraParams};
+use crate::vk_base::VkBase;
+use crate::vk_bundles::BufferBundle;
+
+/* Gap in pixels left between neighbouring panes so the clear colour shows as a divider */
+const PANE_GAP: f32 = 2.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LayoutKind {
+    /* Only the focused pane, covering the whole window */
+    Single,
+
+    /* Panes side by side, the value is the width fraction of the left pane */
+    SplitVertical(f32),
+
+    /* Panes stacked, the value is the height fraction of the top pane */
+    SplitHorizontal(f32),
+
+    Grid { rows: u32, cols: u32 },
+}
+
+/* Normalised window coordinates, (0, 0) is the top left corner */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PaneRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PaneRect {
+    pub const FULL: Self = Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    pub fn to_pixels(&self, extent: vk::Extent2D) -> vk::Rect2D {
+        let w = extent.width as f32;
+        let h = extent.height as f32;
+
+        let x0 = (self.x * w).round();
+        let y0 = (self.y * h).round();
+        let x1 = ((self.x + self.width) * w).round();
+        let y1 = ((self.y + self.height) * h).round();
+
+        // Only inset the edges that touch another pane
+        let gap = PANE_GAP / 2.0;
+        let x0 = if self.x > 0.0 { x0 + gap } else { x0 };
+        let y0 = if self.y > 0.0 { y0 + gap } else { y0 };
+        let x1 = if self.x + self.width < 1.0 { x1 - gap } else { x1 };
+        let y1 = if self.y + self.height < 1.0 { y1 - gap } else { y1 };
+
+        vk::Rect2D {
+            offset: vk::Offset2D { x: x0 as i32, y: y0 as i32 },
+            extent: vk::Extent2D { width: (x1 - x0).max(1.0) as u32, height: (y1 - y0).max(1.0) as u32 },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PaneContent {
+    Scene,
+    DepthTexture,
+    Meshes,
+    Rects,
+    DepthMesh,
+}
+
+pub struct Pane {
+    pub contents: Vec<PaneContent>,
+    pub camera: Camera,
+
+    /* The camera is a dynamic uniform in the upload ring, pushed again every frame */
+    pub camera_offset: u32,
+    pub descriptor_sets: Owned<Vec<vk::DescriptorSet>>,
+}
+
+impl Pane {
+    pub fn new(base: &VkBase, camera: Camera, contents: Vec<PaneContent>) -> Self {
+        let camera_range = BufferBundle { offset: 0, size: std::mem::size_of::<CameraParams>() as u64, ..base.upload_ring.buffer };
+
+        let descriptor_sets = VkBase::create_descriptor_sets(&base.device, base.descriptor_pool, base.global_descriptor_set_layout, base.max_in_flight);
+        for descriptor_set in descriptor_sets.iter() {
+            VkBase::update_descriptor_set_buffers(&base.device, *descriptor_set, &[&camera_range], vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 0);
+            debug::name_object(&base.device, *descriptor_set, "Pane camera descriptor set");
+        }
+        let descriptor_sets = base.release_queue.own(descriptor_sets);
+
+        Self {
+            contents,
+            camera,
+            camera_offset: 0,
+            descriptor_sets,
+        }
+    }
+
+    pub fn shows(&self, content: PaneContent) -> bool {
+        self.contents.contains(&content)
+    }
+
+    pub fn push_camera(&mut self, ring: &mut UploadRing, viewport: vk::Rect2D) -> Result<()> {
+        self.camera.params.viewport = [
+            viewport.offset.x as f32,
+            viewport.offset.y as f32,
+            viewport.extent.width as f32,
+            viewport.extent.height as f32,
+        ];
+        self.camera.update_matrices();
+
+        self.camera_offset = ring.push(&self.camera.params)?.offset as u32;
+        Ok(())
+    }
+}
+
+pub struct ViewportLayout {
+    pub kind: LayoutKind,
+    pub panes: Vec<Pane>,
+    pub focused: usize,
+}
+
+impl ViewportLayout {
+    pub fn new(kind: LayoutKind, panes: Vec<Pane>) -> Self {
+        Self { kind, panes, focused: 0 }
+    }
+
+    /* The panes currently on screen with their rectangles, in draw order */
+    pub fn visible(&self) -> Vec<(usize, PaneRect)> {
+        if self.kind == LayoutKind::Single {
+            return vec![(self.focused, PaneRect::FULL)];
+        }
+
+        Self::pane_rects(self.kind).into_iter().take(self.panes.len()).enumerate().collect()
+    }
+
+    pub fn pane_rects(kind: LayoutKind) -> Vec<PaneRect> {
+        match kind {
+            LayoutKind::Single => vec![PaneRect::FULL],
+
+            LayoutKind::SplitVertical(ratio) => {
+                let ratio = ratio.clamp(0.05, 0.95);
+                vec![
+                    PaneRect { x: 0.0, y: 0.0, width: ratio, height: 1.0 },
+                    PaneRect { x: ratio, y: 0.0, width: 1.0 - ratio, height: 1.0 },
+                ]
+            }
+
+            LayoutKind::SplitHorizontal(ratio) => {
+                let ratio = ratio.clamp(0.05, 0.95);
+                vec![
+                    PaneRect { x: 0.0, y: 0.0, width: 1.0, height: ratio },
+                    PaneRect { x: 0.0, y: ratio, width: 1.0, height: 1.0 - ratio },
+                ]
+            }
+
+            LayoutKind::Grid { rows, cols } => {
+                let rows = rows.max(1);
+                let cols = cols.max(1);
+                let width = 1.0 / cols as f32;
+                let height = 1.0 / rows as f32;
+
+                (0..rows * cols).map(|i| {
+                    let (r, c) = (i / cols, i % cols);
+                    PaneRect { x: c as f32 * width, y: r as f32 * height, width, height }
+                }).collect()
+            }
+        }
+    }
+
+    pub fn set_kind(&mut self, kind: LayoutKind) {
+        self.kind = kind;
+
+        // Keep the focus on a pane that is still visible
+        let visible = self.visible();
+        if !visible.iter().any(|(i, _)| *i == self.focused) {
+            self.focused = visible[0].0;
+        }
+    }
+
+    pub fn focus_next(&mut self) {
+        if self.kind == LayoutKind::Single {
+            self.focused = (self.focused + 1) % self.panes.len();
+            return;
+        }
+
+        let visible = self.visible();
+        let pos = visible.iter().position(|(i, _)| *i == self.focused).unwrap_or(0);
+        self.focused = visible[(pos + 1) % visible.len()].0;
+    }
+
+    /* Focus the pane under the cursor, coordinates are in window pixels */
+    pub fn focus_at(&mut self, x: f32, y: f32, extent: vk::Extent2D) {
+        let nx = x / extent.width.max(1) as f32;
+        let ny = y / extent.height.max(1) as f32;
+
+        if let Some((i, _)) = self.visible().into_iter().find(|(_, rect)| rect.contains(nx, ny)) {
+            self.focused = i;
+        }
+    }
+
+    pub fn focused(&self) -> &Pane {
+        &self.panes[self.focused]
+    }
+
+    pub fn focused_mut(&mut self) -> &mut Pane {
+        &mut self.panes[self.focused]
+    }
+
+    pub fn viewport(rect: &PaneRect, extent: vk::Extent2D) -> (vk::Viewport, vk::Rect2D) {
+        let scissor = rect.to_pixels(extent);
+
+        let viewport = vk::Viewport {
+            x: scissor.offset.x as f32,
+            y: scissor.offset.y as f32,
+            width: scissor.extent.width as f32,
+            height: scissor.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+
+        (viewport, scissor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+
+    use super::{LayoutKind, PaneRect, ViewportLayout};
+
+    #[test]
+    fn test_grid_covers_window() {
+        let rects = ViewportLayout::pane_rects(LayoutKind::Grid { rows: 2, cols: 3 });
+        assert_eq!(rects.len(), 6);
+
+        let area: f32 = rects.iter().map(|r| r.width * r.height).sum();
+        assert!((area - 1.0).abs() < 1e-5);
+
+        assert!(rects[4].contains(0.5, 0.75));
+    }
+
+    #[test]
+    fn test_split_pixels() {
+        let extent = vk::Extent2D { width: 800, height: 600 };
+        let rects = ViewportLayout::pane_rects(LayoutKind::SplitVertical(0.25));
+
+        let left = rects[0].to_pixels(extent);
+        let right = rects[1].to_pixels(extent);
+
+        assert_eq!(left.offset.x, 0);
+        assert_eq!(left.extent.height, 600);
+        assert!(right.offset.x as u32 >= left.extent.width);
+        assert_eq!(right.offset.x as u32 + right.extent.width, 800);
+
+        let full = PaneRect::FULL.to_pixels(extent);
+        assert_eq!((full.extent.width, full.extent.height), (800, 600));
+    }
+}
@@ -1,20 +1,34 @@
+use std::cell::Cell;
+use std::f32::consts::PI;
+use std::path::Path;
 use std::time::Instant;
 
+use anyhow::Result;
 use ash::vk;
 use winit::event::ElementState;
 use winit::keyboard::KeyCode;
 
+use crate::drawable::drawable_instanced::{InstancedMesh, MeshInstance};
+use crate::geometry::mat4::Mat4;
+use crate::geometry::quat::Quat;
 use crate::geometry::vec3::Vec3;
-use crate::mesh::prism;
-use crate::rhi::allocator::{Allocator, BufferType};
+use crate::geometry::vec4::Vec4;
+use crate::mesh::{arrow, cylinder, export, plane, prism, sphere, torus, Shading};
+use crate::rhi::core::GraphicsPSO;
+use crate::rhi::debug;
+use crate::rhi::release::Owned;
+use crate::rhi::ring::UploadRing;
+use crate::rhi::transfer::UploadBatch;
 use crate::vk_bundles::{BufferBundle, DeviceBundle};
-use crate::{drawable::drawable_mesh::DrawableMesh, vk_base::VkBase};
+use crate::drawable::drawable_common::Drawable;
+use crate::vk_base::VkBase;
 use crate::shader::ShaderSpecialMesh;
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 struct SpecialMeshShaderParams {
+    view: Mat4,
     time: f32,
-    aspect: f32,
     global_camera: f32
 }
 
@@ -22,54 +36,79 @@ pub struct SimpleScene
 {
     pub time            : Instant,
 
-    pub static_meshes  : Vec<DrawableMesh>,
-    pub dynamic_meshes : Vec<DrawableMesh>,
+    pub static_meshes  : Vec<InstancedMesh>,
+    pub dynamic_meshes : Vec<InstancedMesh>,
 
-    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub descriptor_sets: Owned<Vec<vk::DescriptorSet>>,
 
-    staging: BufferBundle,
-    uniform: BufferBundle,
+    /* Dynamic offset of this frame's parameters in the upload ring */
+    params_offset: u32,
 
     use_global_camera: bool,
+    wireframe: bool,
     going_down: bool,
     translation_amount: f32,
+
+    /* Render value of the last frame that drew the meshes */
+    read_value: Cell<u64>,
 }
 
 impl SimpleScene
 {
-    pub fn new(base: &VkBase, allocator: &mut Allocator) -> SimpleScene {
+    pub fn new(base: &mut VkBase) -> SimpleScene {
+
+        // Unit prisms around the origin, the instances place, scale and colour them
+        let prism = || prism::make_prism(Vec3::ZERO, Vec3::of(1.0), Vec3::of(1.0));
+        let debug_prism = prism::make_debug_prism(Vec3::ZERO, Vec3::of(1.0));
+
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
 
-        let floor = prism::make_prism(Vec3::new(0.0, -5.0, 0.0), Vec3::new(20.0, 1.0, 20.0), Vec3::of(0.2));
+        let floor = MeshInstance::new(Mat4::translation(Vec3::new(0.0, -5.0, 0.0)) * Mat4::scale(Vec3::new(20.0, 1.0, 20.0)), Vec4::new(0.2, 0.2, 0.2, 1.0));
 
-        let prism_b = prism::make_debug_prism(Vec3::new(0.0, 0.0, -5.0), Vec3::new(2.0, 3.0, 8.0));
+        let prism_b = MeshInstance::new(Mat4::translation(Vec3::new(0.0, 0.0, -5.0)) * Mat4::scale(Vec3::new(2.0, 3.0, 8.0)), white);
 
-        let mut cube_c = prism::make_prism(Vec3::new(5.0, 0.0, 0.0), Vec3::of(0.5), Vec3::new(0.0, 0.0, 1.0));
-        cube_c.rotate_x(45_f32.to_radians());
-        cube_c.rotate_z(45_f32.to_radians());
-        cube_c.recompute_normals();
+        let cube_c = MeshInstance::new(
+            Mat4::translation(Vec3::new(5.0, 0.0, 0.0)) * Mat4::rotation_z(45_f32.to_radians()) * Mat4::rotation_x(-45_f32.to_radians()) * Mat4::scale(Vec3::of(0.5)),
+            Vec4::new(0.0, 0.0, 1.0, 1.0));
 
-        let cube_d = prism::make_prism(Vec3::new(-5.0, 0.0, 0.0), Vec3::of(0.5), Vec3::new(10.0, 0.0, 0.0));
-        let cube_e = prism::make_debug_prism(Vec3::new(0.0, 0.0, 30.0), Vec3::of(5.0));
+        let cube_d = MeshInstance::new(Mat4::translation(Vec3::new(-5.0, 0.0, 0.0)) * Mat4::scale(Vec3::of(0.5)), Vec4::new(10.0, 0.0, 0.0, 1.0));
+        let cube_e = MeshInstance::new(Mat4::translation(Vec3::new(0.0, 0.0, 30.0)) * Mat4::scale(Vec3::of(5.0)), white);
 
 
-        let static_meshes = vec![
-            DrawableMesh::new(&base.device, floor),
+        // A shelf of the procedural primitives behind the camera and arrows along the axes at the origin
+        let shelf = Vec3::new(0.0, -3.5, 10.0);
+        let primitives = [
+            sphere::make_uv_sphere(shelf + Vec3::new(-6.0, 0.0, 0.0), 1.0, 24, 12, Vec3::new(1.0, 0.5, 0.2), Shading::Smooth),
+            sphere::make_ico_sphere(shelf + Vec3::new(-3.0, 0.0, 0.0), 1.0, 1, Vec3::new(0.2, 0.8, 0.4), Shading::Flat),
+            cylinder::make_cylinder(shelf, 0.75, 2.0, 24, Vec3::new(0.3, 0.5, 1.0), Shading::Smooth),
+            cylinder::make_cone(shelf + Vec3::new(3.0, 0.0, 0.0), 1.0, 2.0, 24, Vec3::new(1.0, 1.0, 0.3), Shading::Smooth),
+            torus::make_torus(shelf + Vec3::new(6.0, 0.0, 0.0), 0.75, 0.25, 32, 12, Vec3::new(0.8, 0.3, 0.8), Shading::Smooth),
+            plane::make_plane_grid(shelf - Vec3::Y * 0.99, 15.0, 3.0, 15, 3, Vec3::of(0.5)),
+            arrow::make_arrow(Vec3::ZERO, Vec3::X * 2.0, 0.05, 0.12, 0.3, 12, Vec3::new(1.0, 0.0, 0.0), Shading::Smooth),
+            arrow::make_arrow(Vec3::ZERO, Vec3::Y * 2.0, 0.05, 0.12, 0.3, 12, Vec3::new(0.0, 1.0, 0.0), Shading::Smooth),
+            arrow::make_arrow(Vec3::ZERO, Vec3::Z * 2.0, 0.05, 0.12, 0.3, 12, Vec3::new(0.0, 0.0, 1.0), Shading::Smooth),
         ];
 
+        let mut static_meshes = vec![
+            InstancedMesh::new(&base.device, &mut base.allocator, prism(), vec![floor]),
+        ];
+        for mesh in primitives {
+            static_meshes.push(InstancedMesh::new(&base.device, &mut base.allocator, mesh, vec![MeshInstance::new(Mat4::IDENT, white)]));
+        }
+
         let dynamic_meshes = vec![
-            DrawableMesh::new(&base.device, prism_b),
-            DrawableMesh::new(&base.device, cube_c),
-            DrawableMesh::new(&base.device, cube_d),
-            DrawableMesh::new(&base.device, cube_e),
+            InstancedMesh::new(&base.device, &mut base.allocator, debug_prism, vec![prism_b, cube_e]),
+            InstancedMesh::new(&base.device, &mut base.allocator, prism(), vec![cube_c, cube_d]),
         ];
 
-        let staging = allocator.alloc(BufferType::Staging, std::mem::size_of::<SpecialMeshShaderParams>() as u64).unwrap();
-        let uniform = allocator.alloc(BufferType::Uniform, std::mem::size_of::<SpecialMeshShaderParams>() as u64).unwrap();
+        let params_range = BufferBundle { offset: 0, size: std::mem::size_of::<SpecialMeshShaderParams>() as u64, ..base.upload_ring.buffer };
 
         let descriptor_sets = VkBase::create_descriptor_sets(&base.device, base.descriptor_pool, base.graphics_pipelines[ShaderSpecialMesh::ID].ubo.as_ref().unwrap()[1], base.max_in_flight);
         for descriptor_set in descriptor_sets.iter() {
-            VkBase::update_descriptor_set_buffers(&base.device, *descriptor_set, &[&uniform], 0);
+            VkBase::update_descriptor_set_buffers(&base.device, *descriptor_set, &[&params_range], vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 0);
+            debug::name_object(&base.device, *descriptor_set, "SimpleScene descriptor set");
         }
+        let descriptor_sets = base.release_queue.own(descriptor_sets);
 
         let time = Instant::now();
 
@@ -78,13 +117,14 @@ impl SimpleScene
             time,
             static_meshes,
             dynamic_meshes,
-            staging,
-            uniform,
+            params_offset: 0,
 
             descriptor_sets,
             use_global_camera: false,
+            wireframe: false,
             going_down: false,
             translation_amount: 0.0,
+            read_value: Cell::new(0),
         }
     }
 
@@ -102,6 +142,12 @@ impl SimpleScene
                 }
             }
 
+            KeyCode::KeyF => {
+                for scene in scenes.iter_mut() {
+                    scene.wireframe = !scene.wireframe;
+                }
+            }
+
             _ => {
 
             }
@@ -110,7 +156,8 @@ impl SimpleScene
 
     }
 
-    pub fn update(scenes: &mut [SimpleScene], base: &VkBase, cb: &vk::CommandBuffer, aspect_ratio: f32) {
+    /* Every frame, even when no upload batch could be started. Moves the dynamic instances and pushes them with the parameters */
+    pub fn update(scenes: &mut [SimpleScene], ring: &mut UploadRing) -> Result<()> {
         for scene in scenes.iter_mut() {
 
             let mut v = 1e-2;
@@ -128,71 +175,99 @@ impl SimpleScene
 
             let v = if scene.going_down { d * -v } else { d * v };
 
+            // The mesh transforms move every instance along, each instance spins around its own centre
+            let spin = Mat4::rotation_y(1e-2);
             for mesh in scene.dynamic_meshes.iter_mut() {
-                mesh.mesh.translate(v);
-                mesh.mesh.rotate_y(1e-2);
-                mesh.mesh.recompute_normals();
-            }
-
-
+                mesh.drawable.transform.position += v;
 
-            DrawableMesh::update(&base.device, &cb, &mut scene.dynamic_meshes);
-            DrawableMesh::update(&base.device, &cb, &mut scene.static_meshes);
+                for instance in mesh.instances.iter_mut() {
+                    let centre = Vec3::new(instance.model.w.x, instance.model.w.y, instance.model.w.z);
+                    instance.model = Mat4::translation(centre) * spin * Mat4::translation(Vec3::ZERO - centre) * instance.model;
+                }
+            }
 
+            // Turns on the spot once every 40 seconds
+            let time = scene.time.elapsed().as_secs_f32();
+            let direction = Quat::from_euler(Vec3::new(0.0, time * PI / 20.0, 0.0)).rotate(Vec3::Z);
 
             let params = SpecialMeshShaderParams {
-                time: scene.time.elapsed().as_secs_f32(),
-                aspect: aspect_ratio,
+                view: Mat4::look_at(Vec3::new(0.0, 0.0, 7.0), direction, Vec3::Y),
+                time,
                 global_camera: if scene.use_global_camera { 1.0 } else { -1.0 },
             };
 
-            unsafe {
-                let data_ptr = base.device.logical.map_memory(scene.staging.memory, scene.staging.offset, scene.staging.size, vk::MemoryMapFlags::empty()).unwrap() as *mut SpecialMeshShaderParams;
-                data_ptr.copy_from_nonoverlapping(&params as *const SpecialMeshShaderParams, scene.staging.size as usize);
-                base.device.logical.unmap_memory(scene.staging.memory);
-
-                let copy_region = [
-                    vk::BufferCopy::default()
-                        .src_offset(scene.staging.offset)
-                        .dst_offset(scene.uniform.offset)
-                        .size(scene.staging.size)
-                ];
-
-                base.device.logical.cmd_copy_buffer(*cb, scene.staging.buffer, scene.uniform.buffer, &copy_region);
+            scene.params_offset = ring.push(&params)?.offset as u32;
+
+            for mesh in scene.static_meshes.iter_mut().chain(scene.dynamic_meshes.iter_mut()) {
+                mesh.push_instances(ring)?;
             }
         }
 
+        Ok(())
     }
 
-    pub fn draw(scenes: &[SimpleScene], base: &mut VkBase, cb: &vk::CommandBuffer, current_image: usize, global_descriptor_set: vk::DescriptorSet) {
+    /* The meshes themselves only change when they are created */
+    pub fn upload(scenes: &mut [SimpleScene], device: &DeviceBundle, ring: &mut UploadRing, batch: &mut UploadBatch) {
+        for scene in scenes.iter_mut() {
+            debug::begin_label(device, batch.cb, "SimpleScene meshes", debug::UPLOAD_COLOUR);
+            for mesh in scene.dynamic_meshes.iter_mut().chain(scene.static_meshes.iter_mut()).filter(|mesh| mesh.drawable.dirty()) {
+                mesh.drawable.upload(device, batch, ring);
+                batch.read_by(scene.read_value.get());
+            }
+            debug::end_label(device, batch.cb);
+        }
+    }
 
-        let pso = &base.graphics_pipelines[ShaderSpecialMesh::ID];
+    /* Every instance baked where it is drawn this frame, mesh and instance transforms included, into one file */
+    pub fn export(&self, path: &Path) -> Result<()> {
+        let parts = self.static_meshes.iter().chain(self.dynamic_meshes.iter()).flat_map(|mesh| {
+            let transform = mesh.drawable.transform.matrix();
+            mesh.instances.iter().map(move |instance| (&mesh.drawable.mesh, transform * instance.model, instance.colour.xyz()))
+        });
 
-        unsafe {
-            base.device.logical.cmd_bind_pipeline(*cb, vk::PipelineBindPoint::GRAPHICS, pso.graphics);
-        }
+        export::save(&export::merge(parts)?, path)
+    }
+
+    fn pipeline_state(&self) -> GraphicsPSO {
+        let pso = GraphicsPSO::new(ShaderSpecialMesh::ID);
 
-        unsafe {
-            base.device.logical.cmd_bind_descriptor_sets(*cb, vk::PipelineBindPoint::GRAPHICS, pso.layout, 0, &[global_descriptor_set], &[]);
+        if self.wireframe {
+            pso.polygon_mode(vk::PolygonMode::LINE).cull_mode(vk::CullModeFlags::NONE)
+        } else {
+            pso
         }
+    }
+
+    pub fn draw(scenes: &[SimpleScene], base: &mut VkBase, cb: &vk::CommandBuffer, current_image: usize, global_descriptor_set: vk::DescriptorSet, global_offset: u32) {
 
         for scene in scenes {
+            debug::begin_label(&base.device, *cb, "SimpleScene", debug::DRAW_COLOUR);
+            scene.read_value.set(base.render_value + 1);
+
+            let state = scene.pipeline_state();
+            base.request_pipeline(state);
+            let pso = base.pipeline(&state);
+
             let sets = &scene.descriptor_sets[current_image..current_image+1];
             unsafe {
-                base.device.logical.cmd_bind_descriptor_sets(*cb, vk::PipelineBindPoint::GRAPHICS, pso.layout, 1, sets, &[]);
+                base.device.logical.cmd_bind_pipeline(*cb, vk::PipelineBindPoint::GRAPHICS, pso.graphics);
+                base.device.logical.cmd_bind_descriptor_sets(*cb, vk::PipelineBindPoint::GRAPHICS, pso.layout, 0, &[global_descriptor_set], &[global_offset]);
+                base.device.logical.cmd_bind_descriptor_sets(*cb, vk::PipelineBindPoint::GRAPHICS, pso.layout, 1, sets, &[scene.params_offset]);
             }
 
-            DrawableMesh::draw(&base.device, cb, pso, &scene.static_meshes);
-            DrawableMesh::draw(&base.device, cb, pso, &scene.dynamic_meshes);
-        }
-    }
+            debug::begin_label(&base.device, *cb, "Static meshes", debug::DRAW_COLOUR);
+            for mesh in scene.static_meshes.iter() {
+                mesh.draw(&base.device, *cb, pso.layout, &base.upload_ring);
+            }
+            debug::end_label(&base.device, *cb);
 
-    pub fn release(scenes: &mut [Self], base: &VkBase) {
-        for scene in scenes.iter_mut() {
-            DrawableMesh::release(&base.device, &mut scene.dynamic_meshes);
-            scene.dynamic_meshes.clear();
-            DrawableMesh::release(&base.device, &mut scene.static_meshes);
-            scene.static_meshes.clear();
+            debug::begin_label(&base.device, *cb, "Dynamic meshes", debug::DRAW_COLOUR);
+            for mesh in scene.dynamic_meshes.iter() {
+                mesh.draw(&base.device, *cb, pso.layout, &base.upload_ring);
+            }
+            debug::end_label(&base.device, *cb);
+
+            debug::end_label(&base.device, *cb);
         }
     }
 
@@ -5,31 +5,36 @@ use std::{
 use ash::vk;
 use comptime_register_macro::{register_shader, shaders_registry};
 
+use crate::debug_name;
+use crate::drawable::drawable_instanced::MeshInstance;
+use crate::geometry::mat4::Mat4;
 use crate::{geometry::vec3::Vec3, vk_bundles::{DescSetBinding, DeviceBundle, PipelineDescriptor}};
 
 #[register_shader("mesh")]
 pub struct ShaderMesh { }
 
+/* Drawn from the camera of the pane it is in, DrawableRegistry::draw binds it */
 impl ShaderMesh  {
-    const GLOBAL_UNIFORMS: bool = false;
+    const GLOBAL_UNIFORMS: bool = true;
 
     pub fn pipeline_descriptor() -> PipelineDescriptor {
         let ubo_layout_bindings = vec![];
 
+        // Mesh keeps its attributes as Vec3, which is padded to 16 bytes
         let vertex_bindings = vec![
             vk::VertexInputBindingDescription::default()
                 .binding(0)
-                .stride(std::mem::size_of::<[f32; 3]>() as u32)
+                .stride(std::mem::size_of::<Vec3>() as u32)
                 .input_rate(vk::VertexInputRate::VERTEX),
 
             vk::VertexInputBindingDescription::default()
                 .binding(1)
-                .stride(std::mem::size_of::<[f32; 3]>() as u32)
+                .stride(std::mem::size_of::<Vec3>() as u32)
                 .input_rate(vk::VertexInputRate::VERTEX),
 
             vk::VertexInputBindingDescription::default()
                 .binding(2)
-                .stride(std::mem::size_of::<[f32; 3]>() as u32)
+                .stride(std::mem::size_of::<Vec3>() as u32)
                 .input_rate(vk::VertexInputRate::VERTEX)
         ];
 
@@ -37,7 +42,7 @@ impl ShaderMesh  {
             vk::VertexInputAttributeDescription::default()
                 .binding(0)
                 .location(0)
-                .format(vk::Format::R32G32_SFLOAT),
+                .format(vk::Format::R32G32B32_SFLOAT),
 
             vk::VertexInputAttributeDescription::default()
                 .binding(1)
@@ -51,10 +56,19 @@ impl ShaderMesh  {
 
         ];
 
+        // The model transform of the mesh, see drawable_common::push_model
+        let push_constant_ranges = vec![
+            vk::PushConstantRange::default()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(std::mem::size_of::<Mat4>() as u32)
+        ];
+
         PipelineDescriptor {
             ubo_layout_bindings,
             vertex_bindings,
             vertex_attributes,
+            push_constant_ranges,
         }
     }
 }
@@ -68,7 +82,7 @@ impl ShaderSpecialMesh  {
         let ubo_layout_bindings = vec![
             DescSetBinding {
                 binding: 0,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
                 descriptor_count: 1,
                 stage_flags: vk::ShaderStageFlags::VERTEX,
             }
@@ -88,7 +102,12 @@ impl ShaderSpecialMesh  {
             vk::VertexInputBindingDescription::default()
                 .binding(2)
                 .stride(std::mem::size_of::<Vec3>() as u32)
-                .input_rate(vk::VertexInputRate::VERTEX)
+                .input_rate(vk::VertexInputRate::VERTEX),
+
+            vk::VertexInputBindingDescription::default()
+                .binding(3)
+                .stride(std::mem::size_of::<MeshInstance>() as u32)
+                .input_rate(vk::VertexInputRate::INSTANCE)
         ];
 
         let vertex_attributes = vec![
@@ -107,12 +126,51 @@ impl ShaderSpecialMesh  {
                 .location(2)
                 .format(vk::Format::R32G32B32_SFLOAT),
 
+            // The model matrix takes a location per column
+            vk::VertexInputAttributeDescription::default()
+                .binding(3)
+                .location(3)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(std::mem::offset_of!(MeshInstance, model.x) as u32),
+
+            vk::VertexInputAttributeDescription::default()
+                .binding(3)
+                .location(4)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(std::mem::offset_of!(MeshInstance, model.y) as u32),
+
+            vk::VertexInputAttributeDescription::default()
+                .binding(3)
+                .location(5)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(std::mem::offset_of!(MeshInstance, model.z) as u32),
+
+            vk::VertexInputAttributeDescription::default()
+                .binding(3)
+                .location(6)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(std::mem::offset_of!(MeshInstance, model.w) as u32),
+
+            vk::VertexInputAttributeDescription::default()
+                .binding(3)
+                .location(7)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(std::mem::offset_of!(MeshInstance, colour) as u32),
+        ];
+
+        // The model transform of the mesh, see drawable_common::push_model
+        let push_constant_ranges = vec![
+            vk::PushConstantRange::default()
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .offset(0)
+                .size(std::mem::size_of::<Mat4>() as u32)
         ];
 
         PipelineDescriptor {
             ubo_layout_bindings,
             vertex_bindings,
             vertex_attributes,
+            push_constant_ranges,
         }
     }
 }
@@ -152,6 +210,7 @@ impl ShaderRect {
             ubo_layout_bindings,
             vertex_bindings,
             vertex_attributes,
+            push_constant_ranges: vec![],
         }
     }
 }
@@ -199,6 +258,7 @@ impl ShaderTexture {
             ubo_layout_bindings,
             vertex_bindings,
             vertex_attributes,
+            push_constant_ranges: vec![],
         }
     }
 }
@@ -268,6 +328,7 @@ impl StaticShader {
         };
 
         let module = unsafe { device.logical.create_shader_module(&create_info, None).unwrap() };
+        debug_name!(device, module, "{}", spv_path.file_name().unwrap_or_default().to_string_lossy());
         return Some((code, module));
     }
 
@@ -2,18 +2,42 @@ use anyhow::Result;
 
 use ash::vk;
 
+use crate::rhi::memory_tracker::TrackedKind;
 use crate::{BufferBundle, DeviceBundle};
 
 use super::common::find_memory_type;
 
-pub fn create_buffer(device: &DeviceBundle, size: u64, usage: vk::BufferUsageFlags, properties: vk::MemoryPropertyFlags) -> Result<BufferBundle>{
+/* The buffer gets its own memory, both are recorded in the memory tracker under label */
+pub fn create_buffer(device: &DeviceBundle, size: u64, usage: vk::BufferUsageFlags, properties: vk::MemoryPropertyFlags, label: &str) -> Result<BufferBundle>{
 
     let buffer_create_info = vk::BufferCreateInfo::default()
         .size(size)
         .usage(usage)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-    let buffer = unsafe { device.logical.create_buffer(&buffer_create_info, None)? };
+    create_buffer_with(device, &buffer_create_info, properties, label)
+}
+
+/* Like create_buffer, but CONCURRENT over the graphics and transfer families when they differ */
+pub fn create_shared_buffer(device: &DeviceBundle, size: u64, usage: vk::BufferUsageFlags, properties: vk::MemoryPropertyFlags, label: &str) -> Result<BufferBundle>{
+    if !device.has_dedicated_transfer() {
+        return create_buffer(device, size, usage, properties, label);
+    }
+
+    let families = [device.queue_family_index, device.transfer_queue_family_index];
+    let buffer_create_info = vk::BufferCreateInfo::default()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::CONCURRENT)
+        .queue_family_indices(&families);
+
+    create_buffer_with(device, &buffer_create_info, properties, label)
+}
+
+fn create_buffer_with(device: &DeviceBundle, buffer_create_info: &vk::BufferCreateInfo, properties: vk::MemoryPropertyFlags, label: &str) -> Result<BufferBundle> {
+    let size = buffer_create_info.size;
+
+    let buffer = unsafe { device.logical.create_buffer(buffer_create_info, None)? };
     let mem_requirements = unsafe { device.logical.get_buffer_memory_requirements(buffer) };
     let memory_type = find_memory_type(mem_requirements.memory_type_bits, properties, device.mem_properties)?;
 
@@ -25,6 +49,19 @@ pub fn create_buffer(device: &DeviceBundle, size: u64, usage: vk::BufferUsageFla
 
     unsafe { device.logical.bind_buffer_memory(buffer, memory, 0)?; }
 
+    device.memory_tracker.track(TrackedKind::Buffer, buffer, 0, size, label);
+    device.memory_tracker.track(TrackedKind::DeviceMemory, memory, 0, mem_requirements.size, label);
 
     Ok( BufferBundle { buffer, memory, offset: 0, size } )
 }
+
+/* For buffers from create_buffer, the GPU must be done with it */
+pub fn destroy_buffer(device: &DeviceBundle, buffer: &BufferBundle) {
+    device.memory_tracker.untrack(TrackedKind::Buffer, buffer.buffer, 0);
+    device.memory_tracker.untrack(TrackedKind::DeviceMemory, buffer.memory, 0);
+
+    unsafe {
+        device.logical.destroy_buffer(buffer.buffer, None);
+        device.logical.free_memory(buffer.memory, None);
+    }
+}
@@ -4,13 +4,16 @@ use anyhow::Result;
 
 use ash::vk;
 
-use crate::{DeviceBundle, ImageBundle, TextureBundle};
+use crate::{BufferBundle, DeviceBundle, ImageBundle, TextureBundle};
 use crate::primitives::texture2d::PixelFormat;
+use crate::rhi::allocator::Allocator;
+use crate::rhi::memory_tracker::TrackedKind;
 
-use super::buffer::create_buffer;
 use super::common::find_memory_type;
 
-pub fn create_image(device: &DeviceBundle, width: u32, height: u32, format: vk::Format, tiling: vk::ImageTiling, usage: vk::ImageUsageFlags, properties: vk::MemoryPropertyFlags) -> Result<ImageBundle> {
+/* The image gets its own memory, for render targets and other large images. Textures are usually pooled by rhi::allocator */
+#[allow(clippy::too_many_arguments)]
+pub fn create_image(device: &DeviceBundle, width: u32, height: u32, format: vk::Format, samples: vk::SampleCountFlags, tiling: vk::ImageTiling, usage: vk::ImageUsageFlags, properties: vk::MemoryPropertyFlags, label: &str) -> Result<ImageBundle> {
     let image_ci = vk::ImageCreateInfo::default()
         .image_type(vk::ImageType::TYPE_2D)
         .extent(vk::Extent3D {width, height, depth: 1})
@@ -20,22 +23,38 @@ pub fn create_image(device: &DeviceBundle, width: u32, height: u32, format: vk::
         .tiling(tiling)
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .usage(usage)
-        .samples(vk::SampleCountFlags::TYPE_1)
+        .samples(samples)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
     let image = unsafe { device.logical.create_image(&image_ci, None)? };
     let mem_requirements = unsafe { device.logical.get_image_memory_requirements(image) };
     let memory_type = find_memory_type(mem_requirements.memory_type_bits, properties, device.mem_properties)?;
 
+    let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default().image(image);
     let allocate_info = vk::MemoryAllocateInfo::default()
         .allocation_size(mem_requirements.size)
-        .memory_type_index(memory_type);
+        .memory_type_index(memory_type)
+        .push_next(&mut dedicated_info);
 
     let memory = unsafe { device.logical.allocate_memory(&allocate_info, None)? };
 
     unsafe { device.logical.bind_image_memory(image, memory, 0)?; }
 
-    Ok ( ImageBundle {image, memory, format} )
+    device.memory_tracker.track(TrackedKind::Image, image, 0, mem_requirements.size, label);
+    device.memory_tracker.track(TrackedKind::DeviceMemory, memory, 0, mem_requirements.size, label);
+
+    Ok ( ImageBundle {image, memory, format, offset: 0, size: mem_requirements.size} )
+}
+
+/* For images from create_image, the GPU must be done with it */
+pub fn destroy_image(device: &DeviceBundle, image: &ImageBundle) {
+    device.memory_tracker.untrack(TrackedKind::Image, image.image, 0);
+    device.memory_tracker.untrack(TrackedKind::DeviceMemory, image.memory, 0);
+
+    unsafe {
+        device.logical.destroy_image(image.image, None);
+        device.logical.free_memory(image.memory, None);
+    }
 }
 
 pub fn create_image_view(device: &DeviceBundle, image: &ImageBundle, aspect_flags: vk::ImageAspectFlags, mip_levels: u32) -> Result<vk::ImageView> {
@@ -81,24 +100,34 @@ pub fn create_sampler(device: &DeviceBundle) -> Result<vk::Sampler> {
 }
 
 
-pub fn create_texture_image(device: &DeviceBundle, image_width: u32, image_height: u32, image_size: u64, format: PixelFormat) -> TextureBundle {
+pub fn create_texture_image(device: &DeviceBundle, allocator: &mut Allocator, image_width: u32, image_height: u32, format: PixelFormat, label: &str) -> TextureBundle {
 
     let (vk_format, aspect_flags) = format_properties(format);
 
-    let required_memory_properties = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
-    let staging = create_buffer(device, image_size, vk::BufferUsageFlags::TRANSFER_SRC, required_memory_properties).unwrap();
+    // Shared with a dedicated transfer queue, which uploads while the graphics queue keeps sampling between frames
+    let families = [device.queue_family_index, device.transfer_queue_family_index];
+    let sharing_mode = if device.has_dedicated_transfer() { vk::SharingMode::CONCURRENT } else { vk::SharingMode::EXCLUSIVE };
+
+    let image_ci = vk::ImageCreateInfo::default()
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D { width: image_width, height: image_height, depth: 1 })
+        .mip_levels(1)
+        .array_layers(1)
+        .format(vk_format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .sharing_mode(sharing_mode)
+        .queue_family_indices(if device.has_dedicated_transfer() { &families } else { &[] });
 
-    let resource = create_image(device, image_width, image_height, vk_format,
-                             vk::ImageTiling::OPTIMAL,
-                             vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
-                             vk::MemoryPropertyFlags::DEVICE_LOCAL).unwrap();
+    let resource = allocator.create_image(device, &image_ci, vk::MemoryPropertyFlags::DEVICE_LOCAL, label).unwrap();
 
     let sampler = create_sampler(device).unwrap();
     let image_view = create_image_view(device, &resource, aspect_flags, 1).unwrap();
 
     TextureBundle {
         resource,
-        staging,
         sampler,
         image_view,
         aspect_flags
@@ -255,7 +284,7 @@ pub fn copy_buffer_to_image(
     device: &DeviceBundle,
     command_buffer: vk::CommandBuffer,
     texture: &TextureBundle,
-    buffer: vk::Buffer,
+    buffer: &BufferBundle,
     width: u32,
     height: u32,
 ) {
@@ -268,6 +297,7 @@ pub fn copy_buffer_to_image(
 
     let buffer_image_regions = [
         vk::BufferImageCopy::default()
+            .buffer_offset(buffer.offset)
             .image_subresource(sub_res)
             .image_extent(vk::Extent3D { width, height, depth: 1})
             .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
@@ -276,7 +306,7 @@ pub fn copy_buffer_to_image(
     unsafe {
         device.logical.cmd_copy_buffer_to_image(
             command_buffer,
-            buffer,
+            buffer.buffer,
             texture.resource.image,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             &buffer_image_regions,
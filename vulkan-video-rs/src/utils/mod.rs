@@ -2,4 +2,4 @@ pub mod buffer;
 pub mod image;
 pub mod colours;
 pub mod keyboard;
-mod common;
+pub mod common;
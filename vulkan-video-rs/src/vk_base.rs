@@ -1,30 +1,66 @@
+use std::collections::HashMap;
 use std::ffi::{c_char, c_void, CStr, CString};
-
-use ash::{ext::debug_utils, khr};
-
+use std::path::Path;
+
+use ash::{ext::{self, debug_utils}, khr};
+
+use crate::options::Options;
+use crate::rhi::adapter;
+use crate::rhi::allocator::{self, Allocator, AllocatorSizeInfo};
+use crate::rhi::core::GraphicsPSO;
+use crate::rhi::debug;
+use crate::rhi::memory_tracker::MemoryTracker;
+use crate::rhi::pipeline_cache;
+use crate::rhi::profiler::{ProfiledQueue, Profiler};
+use crate::rhi::release::{DeferredReleases, ReleaseQueue, Resource};
+use crate::rhi::ring::UploadRing;
+use crate::rhi::transfer::{self, QueueTransfer, UploadBatch};
 use crate::shader::ShaderRegistry;
+use crate::utils::image::{create_image, create_image_view, destroy_image};
 use crate::vk_bundles::*;
 
 use ash::vk;
+use log::{Level, LevelFilter};
 use winit::{raw_window_handle::{HasDisplayHandle, HasWindowHandle}, window::Window};
 
+/* Descriptors of each type per swapchain image, enough for a set per viewport pane and frame in flight */
+const DESCRIPTOR_POOL_SCALE: u32 = 8;
+
+const PIPELINE_CACHE_FILE: &str = "pipeline_cache.bin";
 
 
 pub struct VkBase {
     pub _entry: ash::Entry,
     pub instance: ash::Instance,
-    pub debug_utils_loader: debug_utils::Instance,
-    pub debug_messenger: vk::DebugUtilsMessengerEXT,
+
+    /* None unless validation was requested and is available */
+    pub debug_utils: Option<DebugUtilsBundle>,
     pub surface: SurfaceBundle,
     pub device: DeviceBundle,
     pub swapchain: SwapchainBundle,
     pub image_views: Vec<vk::ImageView>,
     pub render_pass: vk::RenderPass,
+    pub render_targets: RenderTargetsBundle,
     pub framebuffers: Vec<vk::Framebuffer>,
     pub commands: Vec<CommandBundle>,
     pub spare_command: CommandBundle,
     pub descriptor_pool: vk::DescriptorPool,
+
+    /* Upload command buffers live in a pool of the transfer family, in_flight_buffers returns them here */
+    pub transfer_command: CommandBundle,
     pub in_flight_buffers: Vec<(vk::CommandBuffer, vk::Fence)>,
+
+    /* Signalled with upload_value by each upload, rendering waits for the latest value. Both timelines are null handles
+     * without DeviceBundle::timeline_semaphores */
+    pub upload_timeline: vk::Semaphore,
+    pub upload_value: u64,
+
+    /* Signalled with render_value by each frame, uploads wait for the last frame that read what they overwrite */
+    pub render_timeline: vk::Semaphore,
+    pub render_value: u64,
+
+    /* Resources released by the transfer queue that the next graphics command buffer has to acquire */
+    pub pending_acquires: Vec<QueueTransfer>,
     pub sync_objects: SyncObjectsBundle,
     pub current_frame: usize,
     pub is_framebuffer_resized: bool,
@@ -33,53 +69,86 @@ pub struct VkBase {
 
     pub global_descriptor_set_layout: vk::DescriptorSetLayout,
     pub shader_registry: ShaderRegistry,
+    pub pipeline_cache: vk::PipelineCache,
     pub graphics_pipelines: Vec<GraphicsPipelineBundle>,
 
+    /* Pipelines for non default states, created on request and sharing the descriptor set layouts of graphics_pipelines */
+    pub pipeline_variants: HashMap<GraphicsPSO, GraphicsPipelineBundle>,
+
+    pub profiler: Profiler,
+
+    /* Uniforms and staging data rewritten every frame */
+    pub upload_ring: UploadRing,
+
+    pub allocator: Allocator,
+
+    /* Owned resources land in release_queue when dropped and are destroyed once no frame in flight can use them */
+    pub release_queue: ReleaseQueue,
+    deferred_releases: DeferredReleases,
 }
 
 impl VkBase {
-    pub fn new(window: Window, max_in_flight: usize, asset_dir: &str, global_desc_set_binding: DescSetBinding) -> Self {
-        let (entry, instance) = VkBase::create_instance(&window);
-        let (debug_utils_loader, debug_messenger) = VkBase::setup_validation(&entry, &instance);
+    pub fn new(window: Window, max_in_flight: usize, asset_dir: &str, global_desc_set_binding: DescSetBinding, msaa_samples: vk::SampleCountFlags, allocator_sizes: AllocatorSizeInfo, options: &Options) -> Self {
+        let (entry, instance, debug_utils_enabled) = VkBase::create_instance(&window, options.validation);
+        let debug_utils = if debug_utils_enabled { Some(VkBase::setup_validation(&entry, &instance)) } else { None };
 
         let surface         = VkBase::create_surface(&entry, &instance, &window);
-        let device          = VkBase::select_phsyical_device(&instance, &surface);
+        let device          = VkBase::select_phsyical_device(&instance, &surface, options.gpu.as_ref(), debug_utils_enabled);
         let swapchain       = VkBase::create_swapchain(&instance, &device, &surface, &window, None);
         let image_views     = VkBase::create_image_views(&device, &swapchain);
         let max_in_flight   = if image_views.len() < max_in_flight { image_views.len() } else { max_in_flight };
-        let render_pass     = VkBase::create_render_pass(&device, &swapchain);
-        let framebuffers    = VkBase::create_framebuffers(&device, &render_pass, &image_views, &swapchain);
-        let commands        = VkBase::create_command_pools(&device, image_views.len(), 1);
-        let spare_command   = VkBase::create_command_pools(&device, 1, max_in_flight).remove(0);
+        let samples         = VkBase::supported_sample_count(&device, msaa_samples);
+        let render_targets  = VkBase::create_render_targets(&device, &swapchain, samples);
+        let render_pass     = VkBase::create_render_pass(&device, &swapchain, samples);
+        let framebuffers    = VkBase::create_framebuffers(&device, &render_pass, &image_views, &render_targets, &swapchain);
+        let commands        = VkBase::create_command_pools(&device, device.queue_family_index, image_views.len(), 1);
+        let spare_command   = VkBase::create_command_pools(&device, device.queue_family_index, 1, 0).remove(0);
+        let transfer_command = VkBase::create_command_pools(&device, device.transfer_queue_family_index, 1, max_in_flight).remove(0);
+        let upload_timeline = VkBase::create_timeline_semaphore(&device);
+        let render_timeline = VkBase::create_timeline_semaphore(&device);
         let sync_objects    = VkBase::create_sync_objects(&device, image_views.len());
 
+        // Slack over the frames in flight, so slots are rarely still on the GPU when they come around again
+        let profiler        = Profiler::new(&device, max_in_flight + 2);
+        let upload_ring     = UploadRing::new(&device, max_in_flight, allocator_sizes.upload_ring_frame);
+        let release_queue   = ReleaseQueue::default();
+        let allocator       = Allocator::new(&device, allocator_sizes, release_queue.clone());
+
         let descriptor_pool = VkBase::create_descriptor_pool(&device, swapchain.images.len());
 
         let global_descriptor_set_layout = VkBase::create_descriptor_set_layout(&device, &[global_desc_set_binding]);
 
         let shader_registry = ShaderRegistry::new(&device, asset_dir);
 
+        let pipeline_cache = pipeline_cache::load(&device, Path::new(PIPELINE_CACHE_FILE));
+
         let graphics_pipelines: Vec<_> = (0..shader_registry.static_shaders.len()).map(|i| {
-            VkBase::create_graphics_pipeline_impl(&device, &swapchain, &render_pass, global_descriptor_set_layout, &shader_registry, i, None)
+            VkBase::create_graphics_pipeline_impl(&device, pipeline_cache, &render_pass, samples, global_descriptor_set_layout, &shader_registry, GraphicsPSO::new(i), None)
         }).collect();
 
         Self {
             _entry: entry,
 
             instance,
-            debug_utils_loader,
-            debug_messenger,
+            debug_utils,
 
             surface,
             device,
             swapchain,
             image_views,
             render_pass,
+            render_targets,
 
             framebuffers,
             commands,
             spare_command,
+            transfer_command,
             in_flight_buffers: vec![],
+            upload_timeline,
+            upload_value: 0,
+            render_timeline,
+            render_value: 0,
+            pending_acquires: vec![],
 
             descriptor_pool,
 
@@ -92,10 +161,25 @@ impl VkBase {
 
             global_descriptor_set_layout,
             shader_registry,
-            graphics_pipelines
+            pipeline_cache,
+            graphics_pipelines,
+            pipeline_variants: HashMap::new(),
+
+            profiler,
+            upload_ring,
+
+            allocator,
+            release_queue,
+            deferred_releases: DeferredReleases::new(max_in_flight),
         }
     }
 
+    /* Start of a frame, before anything is pushed to the upload ring or profiled */
+    pub fn begin_frame(&mut self) {
+        self.profiler.begin_frame(&self.device);
+        self.upload_ring.begin_frame(&self.device);
+    }
+
     pub fn begin_renderpass_command_buffer(&mut self) -> Option<(vk::CommandBuffer, u32)>
     {
         let window_size = self.window.inner_size();
@@ -107,7 +191,7 @@ impl VkBase {
 
         if window_size.width != self.swapchain.extent.width || window_size.height != self.swapchain.extent.height
         {
-            self.recreate_swapchain_and_pipelines();
+            self.recreate_swapchain();
             return None;
         }
 
@@ -117,6 +201,9 @@ impl VkBase {
             self.device.logical.wait_for_fences(&wait_fences, true, std::u64::MAX)
                 .expect("Failed to wait for Fence!");
 
+            let resources = self.deferred_releases.retire(self.current_frame, &self.release_queue);
+            self.destroy_resources(resources);
+
             let result = self.swapchain.loader.acquire_next_image(
                 self.swapchain.swapchain, std::u64::MAX,
                 self.sync_objects.image_available_semaphores[self.current_frame],
@@ -126,7 +213,7 @@ impl VkBase {
                 Ok(image_index_info) => image_index_info,
                 Err(vk_result) => match vk_result {
                     vk::Result::ERROR_OUT_OF_DATE_KHR => {
-                        self.recreate_swapchain_and_pipelines();
+                        self.recreate_swapchain();
                         return None;
                     }
                     _ => panic!("Failed to acquire swapchain image!"),
@@ -150,11 +237,24 @@ impl VkBase {
                 .expect("Failed to begin recording Command Buffer at beginning!");
         }
 
-        let clear_values = [vk::ClearValue {
+        self.profiler.begin_scope(&self.device, cb, ProfiledQueue::Graphics, "Frame");
+
+        // Ownership transfers have to be outside of the render pass
+        transfer::record_acquire(&self.device, cb, &self.pending_acquires);
+        self.pending_acquires.clear();
+
+        let clear_color = vk::ClearValue {
             color: vk::ClearColorValue {
                 float32: [0.0, 0.0, 0.0, 1.0],
             },
-        }];
+        };
+
+        let clear_depth = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+        };
+
+        // The third value is only read when multisampling, for the resolve attachment which is never cleared
+        let clear_values = [clear_color, clear_depth, clear_color];
 
         let framebuffer = self.framebuffers[image_index as usize];
 
@@ -168,32 +268,188 @@ impl VkBase {
             self.device.logical.cmd_begin_render_pass(cb, &render_pass_begin_info, vk::SubpassContents::INLINE);
         }
 
+        // Viewport and scissor are dynamic state, every pass starts out covering the whole swapchain
+        self.set_viewport(&cb, self.full_viewport(), self.full_scissor());
+
         return Some((cb, image_index));
     }
 
+    /* Start recording uploads, None when every upload command buffer is still in flight */
+    pub fn begin_upload(&mut self) -> Option<UploadBatch> {
+        self.cleanup_in_flight_buffers();
+
+        if self.sync_objects.spare_fences.is_empty() {
+            return None;
+        }
+
+        let cb = self.transfer_command.buffers.pop()?;
+
+        let cb_begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        unsafe {
+            let _ = self.device.logical.reset_command_buffer(cb, vk::CommandBufferResetFlags::empty());
+            self.device.logical.begin_command_buffer(cb, &cb_begin_info).unwrap();
+        }
+
+        self.profiler.begin_scope(&self.device, cb, ProfiledQueue::Transfer, "Upload");
+        transfer::record_wait_for_readers(&self.device, cb);
+
+        Some(UploadBatch::new(cb))
+    }
+
+    /* Submit the uploads to the transfer queue, the next rendered frame waits for them */
+    pub fn submit_upload(&mut self, batch: UploadBatch) {
+        self.profiler.end_scope(&self.device, batch.cb);
+        transfer::record_release(&self.device, batch.cb, &batch.transfers);
+
+        unsafe { self.device.logical.end_command_buffer(batch.cb).unwrap(); }
+
+        self.upload_value += 1;
+
+        // The copies overwrite ranges and images earlier frames read. Frames finish in submission order, so waiting for
+        // the last one that read a destination covers the others. Uploads of what no frame drew yet wait for nothing,
+        // while a destination rewritten every frame, like the depth mesh, still waits for the frame before
+        let wait_semaphores = [self.render_timeline];
+        let wait_stages = [vk::PipelineStageFlags::TRANSFER];
+        let wait_values = [batch.render_value];
+
+        let cbs = [batch.cb];
+        let signal_semaphores = [self.upload_timeline];
+        let signal_values = [self.upload_value];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::default()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
+
+        // Without timelines the upload goes to the graphics queue, where it is ordered by submission and barriers
+        let mut submit_info = vk::SubmitInfo::default().command_buffers(&cbs);
+        if self.device.timeline_semaphores {
+            submit_info = submit_info
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .signal_semaphores(&signal_semaphores)
+                .push_next(&mut timeline_info);
+        }
+
+        let fence = self.sync_objects.spare_fences.pop().unwrap();
+        unsafe {
+            self.device.logical.reset_fences(&[fence]).expect("Failed to reset fences.");
+            self.device.logical.queue_submit(self.device.transfer_queue, &[submit_info], fence).expect("Failure submitting to the transfer queue.");
+        }
+
+        self.in_flight_buffers.push((batch.cb, fence));
+        self.upload_ring.track_fence(fence);
+
+        if self.device.has_dedicated_transfer() {
+            self.pending_acquires.extend(batch.transfers);
+        }
+    }
+
+    /* Nothing submitted may still use the resources */
+    fn destroy_resources(&mut self, resources: Vec<Resource>) {
+        for resource in resources {
+            let result = match resource {
+                Resource::BufferRange(bundle) => self.allocator.free(&self.device, &bundle),
+                Resource::Image(image) => self.allocator.destroy_image(&self.device, &image),
+                Resource::ImageView(view) => unsafe { self.device.logical.destroy_image_view(view, None); Ok(()) },
+                Resource::Sampler(sampler) => unsafe { self.device.logical.destroy_sampler(sampler, None); Ok(()) },
+                Resource::DescriptorSets(sets) => unsafe {
+                    self.device.logical.free_descriptor_sets(self.descriptor_pool, &sets).map_err(|e| e.into())
+                },
+            };
+
+            if let Err(e) = result {
+                println!("Failed to release a resource: {}", e);
+            }
+        }
+    }
+
+    pub fn memory_budget(&self) -> Vec<allocator::HeapBudget> {
+        allocator::memory_budget(&self.instance, &self.device)
+    }
+
+    /* Tracked usage per category, followed by the heap budgets */
+    pub fn print_memory_report(&self) {
+        println!("{}", self.device.memory_tracker.report());
+
+        for heap in self.memory_budget() {
+            println!("{}", heap.describe());
+        }
+    }
+
+    pub fn full_viewport(&self) -> vk::Viewport {
+        vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.swapchain.extent.width as f32,
+            height: self.swapchain.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0
+        }
+    }
+
+    pub fn full_scissor(&self) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D {x: 0, y: 0},
+            extent: self.swapchain.extent
+        }
+    }
+
+    pub fn set_viewport(&self, cb: &vk::CommandBuffer, viewport: vk::Viewport, scissor: vk::Rect2D) {
+        unsafe {
+            self.device.logical.cmd_set_viewport(*cb, 0, &[viewport]);
+            self.device.logical.cmd_set_scissor(*cb, 0, &[scissor]);
+        }
+    }
+
 
     pub fn render(&mut self, cb: &vk::CommandBuffer, image_index: u32)
     {
         let cb = *cb;
 
         let wait_fences = [self.sync_objects.in_flight_fences[self.current_frame]];
-        let wait_semaphores = [self.sync_objects.image_available_semaphores[self.current_frame]];
-        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let signal_semaphores = [self.sync_objects.render_finished_semaphores[image_index as usize]];
+        let wait_semaphores = [self.sync_objects.image_available_semaphores[self.current_frame], self.upload_timeline];
+        let wait_stages = [
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        ];
+        let render_finished = [self.sync_objects.render_finished_semaphores[image_index as usize]];
+        let signal_semaphores = [render_finished[0], self.render_timeline];
+
+        // Only the binary semaphores without timelines
+        let semaphore_count = if self.device.timeline_semaphores { 2 } else { 1 };
+
+        self.render_value += 1;
+
+        // Values of binary semaphores are ignored
+        let wait_values = [0, self.upload_value];
+        let signal_values = [0, self.render_value];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::default()
+            .wait_semaphore_values(&wait_values)
+            .signal_semaphore_values(&signal_values);
 
         unsafe {
             self.device.logical.cmd_end_render_pass(cb);
+        }
+
+        self.profiler.end_scope(&self.device, cb);
+
+        unsafe {
             let _ = self.device.logical.end_command_buffer(cb);
         }
 
         let cbs = [cb];
-        let submit_infos = [
-            vk::SubmitInfo::default()
-                .wait_semaphores(&wait_semaphores)
-                .wait_dst_stage_mask(&wait_stages)
-                .command_buffers(&cbs)
-                .signal_semaphores(&signal_semaphores)
-        ];
+        let mut submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores[..semaphore_count])
+            .wait_dst_stage_mask(&wait_stages[..semaphore_count])
+            .command_buffers(&cbs)
+            .signal_semaphores(&signal_semaphores[..semaphore_count]);
+
+        if self.device.timeline_semaphores {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+
+        let submit_infos = [submit_info];
 
         unsafe {
             self.device.logical.reset_fences(&wait_fences).expect("Failed to reset Fence!");
@@ -201,12 +457,14 @@ impl VkBase {
                 .expect("Failed to execute queue submit.");
         }
 
+        self.upload_ring.track_fence(self.sync_objects.in_flight_fences[self.current_frame]);
+
         let swapchains = [self.swapchain.swapchain];
 
         let image_indices = [image_index];
 
         let present_info = vk::PresentInfoKHR::default()
-            .wait_semaphores(&signal_semaphores)
+            .wait_semaphores(&render_finished)
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
@@ -233,7 +491,35 @@ impl VkBase {
     }
 
     pub fn recreate_graphics_pipeline(&mut self, graphics_pipeline: GraphicsPipelineBundle) -> GraphicsPipelineBundle {
-        return VkBase::create_graphics_pipeline_impl(&self.device, &self.swapchain, &self.render_pass, self.global_descriptor_set_layout, &self.shader_registry, graphics_pipeline.id, graphics_pipeline.ubo);
+        unsafe {
+            self.device.logical.destroy_pipeline(graphics_pipeline.graphics, None);
+            self.device.logical.destroy_pipeline_layout(graphics_pipeline.layout, None);
+        }
+
+        return VkBase::create_graphics_pipeline_impl(&self.device, self.pipeline_cache, &self.render_pass, self.render_targets.samples, self.global_descriptor_set_layout, &self.shader_registry, graphics_pipeline.pso, graphics_pipeline.ubo);
+    }
+
+    /* Make sure a pipeline exists for the state, the default state of every shader always exists */
+    pub fn request_pipeline(&mut self, pso: GraphicsPSO) {
+        if pso.is_default() || self.pipeline_variants.contains_key(&pso) {
+            return;
+        }
+
+        let ubo = self.graphics_pipelines[pso.shader_id].ubo.clone();
+        let pipeline = VkBase::create_graphics_pipeline_impl(&self.device, self.pipeline_cache, &self.render_pass, self.render_targets.samples, self.global_descriptor_set_layout, &self.shader_registry, pso, ubo);
+        self.pipeline_variants.insert(pso, pipeline);
+    }
+
+    /* The pipeline for a state, variants have to be created with request_pipeline first */
+    pub fn pipeline(&self, pso: &GraphicsPSO) -> &GraphicsPipelineBundle {
+        if pso.is_default() {
+            return &self.graphics_pipelines[pso.shader_id];
+        }
+
+        match self.pipeline_variants.get(pso) {
+            Some(pipeline) => pipeline,
+            None => panic!("Pipeline {:?} was not requested before use.", pso)
+        }
     }
 
     pub fn recreate_swapchain(&mut self) {
@@ -241,21 +527,85 @@ impl VkBase {
 
         self.cleanup_swapchain_partial();
 
+        let old_format    = self.swapchain.format;
         let old_swapchain = Some(self.swapchain.swapchain);
         self.swapchain    = VkBase::create_swapchain(&self.instance, &self.device, &self.surface, &self.window, old_swapchain);
 
-        self.image_views   = VkBase::create_image_views  (&self.device, &self.swapchain);
-        self.render_pass   = VkBase::create_render_pass  (&self.device, &self.swapchain);
-        self.framebuffers  = VkBase::create_framebuffers (&self.device, &self.render_pass, &self.image_views, &self.swapchain);
+        // The pipelines only depend on the render pass, which only changes if the surface format does
+        let format_changed = self.swapchain.format != old_format;
+        if format_changed {
+            unsafe { self.device.logical.destroy_render_pass(self.render_pass, None) };
+            self.render_pass = VkBase::create_render_pass(&self.device, &self.swapchain, self.render_targets.samples);
+        }
+
+        self.render_targets = VkBase::create_render_targets(&self.device, &self.swapchain, self.render_targets.samples);
+        self.image_views    = VkBase::create_image_views  (&self.device, &self.swapchain);
+        self.framebuffers   = VkBase::create_framebuffers (&self.device, &self.render_pass, &self.image_views, &self.render_targets, &self.swapchain);
         self.max_in_flight = if self.image_views.len() < self.max_in_flight { self.image_views.len() } else { self.max_in_flight };
 
         unsafe { self.swapchain.loader.destroy_swapchain(old_swapchain.unwrap(), None); };
+
+        if format_changed {
+            self.recreate_pipelines();
+        }
     }
 
-    fn recreate_swapchain_and_pipelines(&mut self) {
-        // TODO: Pushing and popping will be bad when there are more graphics
+    /* Sample counts usable by both the colour and depth attachments, lowest first */
+    pub fn supported_sample_counts(device: &DeviceBundle) -> Vec<vk::SampleCountFlags> {
+        let limits = &device.properties.limits;
+        let supported = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+        [
+            vk::SampleCountFlags::TYPE_1,
+            vk::SampleCountFlags::TYPE_2,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_8,
+            vk::SampleCountFlags::TYPE_16,
+            vk::SampleCountFlags::TYPE_32,
+            vk::SampleCountFlags::TYPE_64,
+        ].into_iter().filter(|samples| supported.contains(*samples)).collect()
+    }
+
+    /* The highest supported sample count not above the requested one */
+    pub fn supported_sample_count(device: &DeviceBundle, requested: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        VkBase::supported_sample_counts(device).into_iter()
+            .rev()
+            .find(|samples| samples.as_raw() <= requested.as_raw())
+            .unwrap_or(vk::SampleCountFlags::TYPE_1)
+    }
 
-        self.recreate_swapchain();
+    /* Switch the multisampling of the main render pass, this rebuilds the render pass, its targets and every pipeline */
+    pub fn set_msaa_samples(&mut self, requested: vk::SampleCountFlags) {
+        let samples = VkBase::supported_sample_count(&self.device, requested);
+        if samples == self.render_targets.samples {
+            return;
+        }
+
+        unsafe {
+            self.device.logical.device_wait_idle().expect("Failed to wait device idle!");
+
+            for &framebuffer in self.framebuffers.iter() {
+                self.device.logical.destroy_framebuffer(framebuffer, None);
+            }
+
+            VkBase::destroy_render_targets(&self.device, &self.render_targets);
+            self.device.logical.destroy_render_pass(self.render_pass, None);
+        }
+
+        self.render_targets = VkBase::create_render_targets(&self.device, &self.swapchain, samples);
+        self.render_pass    = VkBase::create_render_pass(&self.device, &self.swapchain, samples);
+        self.framebuffers   = VkBase::create_framebuffers(&self.device, &self.render_pass, &self.image_views, &self.render_targets, &self.swapchain);
+
+        self.recreate_pipelines();
+    }
+
+    fn recreate_pipelines(&mut self) {
+        self.recreate_pipelines_for(|_| true);
+    }
+
+    /* Rebuild the default and variant pipelines of every shader matching the filter */
+    fn recreate_pipelines_for<F: Fn(usize) -> bool>(&mut self, filter: F) {
+        // TODO: Pushing and popping will be bad when there are more graphics
 
         let count = self.graphics_pipelines.len();
 
@@ -263,16 +613,18 @@ impl VkBase {
 
         for _ in 0..count {
             let graphics_pipeline = self.graphics_pipelines.remove(0);
-            unsafe {
-                self.device.logical.destroy_pipeline(graphics_pipeline.graphics, None);
-                self.device.logical.destroy_pipeline_layout(graphics_pipeline.layout, None);
-            }
-
-            let graphics_pipeline = self.recreate_graphics_pipeline(graphics_pipeline);
+            let graphics_pipeline = if filter(graphics_pipeline.pso.shader_id) { self.recreate_graphics_pipeline(graphics_pipeline) } else { graphics_pipeline };
             new_pipes.push(graphics_pipeline);
         }
 
         self.graphics_pipelines = new_pipes;
+
+        let variants: Vec<_> = self.pipeline_variants.keys().filter(|pso| filter(pso.shader_id)).cloned().collect();
+        for pso in variants {
+            let graphics_pipeline = self.pipeline_variants.remove(&pso).unwrap();
+            let graphics_pipeline = self.recreate_graphics_pipeline(graphics_pipeline);
+            self.pipeline_variants.insert(pso, graphics_pipeline);
+        }
     }
 
 
@@ -282,16 +634,20 @@ impl VkBase {
                 self.device.logical.destroy_framebuffer(framebuffer, None);
             }
 
-            self.device.logical.destroy_render_pass(self.render_pass, None);
             for &image_view in self.image_views.iter() {
                 self.device.logical.destroy_image_view(image_view, None);
             }
+
+            VkBase::destroy_render_targets(&self.device, &self.render_targets);
         }
     }
 
     pub fn cleanup_swapchain(&self) {
         self.cleanup_swapchain_partial();
-        unsafe { self.swapchain.loader.destroy_swapchain(self.swapchain.swapchain, None); };
+        unsafe {
+            self.device.logical.destroy_render_pass(self.render_pass, None);
+            self.swapchain.loader.destroy_swapchain(self.swapchain.swapchain, None);
+        };
     }
 
     pub fn cleanup_in_flight_buffers(&mut self) {
@@ -304,7 +660,7 @@ impl VkBase {
             };
 
             if fence_status {
-                self.spare_command.buffers.push(command_buffer);
+                self.transfer_command.buffers.push(command_buffer);
                 self.sync_objects.spare_fences.push(fence);
                 self.in_flight_buffers.remove(idx);
             }
@@ -318,30 +674,18 @@ impl VkBase {
             if compiled_shader.details.outdated() {
                 compiled_shader.reload_and_compile(&self.device);
 
-                let pso = self.graphics_pipelines.remove(i);
-
-                let new_pso = VkBase::create_graphics_pipeline_impl(
-                    &self.device, &self.swapchain, &self.render_pass, self.global_descriptor_set_layout, &self.shader_registry,
-                    pso.id,
-                    pso.ubo
-                );
-
-                unsafe {
-                    let _ = self.device.logical.device_wait_idle();
-                    self.device.logical.destroy_pipeline(pso.graphics, None);
-                    self.device.logical.destroy_pipeline_layout(pso.layout, None);
-                }
-
-
-                self.graphics_pipelines.insert(i, new_pso);
+                unsafe { let _ = self.device.logical.device_wait_idle(); }
 
+                self.recreate_pipelines_for(|shader_id| shader_id == i);
             }
         }
     }
 
 
     /* Misc vulkan */
-    pub fn create_instance(window: &Window) -> (ash::Entry, ash::Instance) {
+    /* Also returns whether debug utils are enabled, validation is skipped when its layer is not installed.
+     * Debug builds enable debug utils without validation too, for object names in capture tools. */
+    pub fn create_instance(window: &Window, validation: bool) -> (ash::Entry, ash::Instance, bool) {
         // The entry contains the global vk functions
         let entry = unsafe { ash::Entry::load().unwrap() };
 
@@ -349,26 +693,46 @@ impl VkBase {
         let vk_layers = unsafe {entry.enumerate_instance_layer_properties().expect("Could not enumerate layers")};
 
         println!("Found {} layer(s).", vk_layers.len());
-        for layer in vk_layers {
+        for layer in vk_layers.iter() {
             println!("\t{:?}", layer.layer_name_as_c_str().unwrap())
         }
         println!();
 
         // Select layers to enable
-        let layers = [c"VK_LAYER_KHRONOS_validation"];
-        let layers_raw: Vec<*const c_char> = layers.iter().map(|raw_name| raw_name.as_ptr()).collect();
+        let validation_layer = c"VK_LAYER_KHRONOS_validation";
+        let has_validation_layer = vk_layers.iter().any(|layer| layer.layer_name_as_c_str() == Ok(validation_layer));
+
+        if validation && !has_validation_layer {
+            log::warn!("Validation was requested but {:?} is not installed, continuing without it.", validation_layer);
+        }
+
+        let validation = validation && has_validation_layer;
+        let layers_raw: Vec<*const c_char> = if validation { vec![validation_layer.as_ptr()] } else { vec![] };
 
         // List all the supported extensions
         let vk_extensions = unsafe { entry.enumerate_instance_extension_properties(None).unwrap() };
         println!("Found {} extension(s).", vk_extensions.len());
-        for extension in vk_extensions {
+        for extension in vk_extensions.iter() {
             println!("\t{:?}", extension.extension_name_as_c_str().unwrap())
         }
         println!();
 
+        // The validation layer provides debug utils itself when the loader does not
+        let has_debug_utils = |extensions: &[vk::ExtensionProperties]| extensions.iter().any(|extension| extension.extension_name_as_c_str() == Ok(debug_utils::NAME));
+        let debug_utils_available = has_debug_utils(&vk_extensions) || (validation && has_debug_utils(&unsafe {
+            entry.enumerate_instance_extension_properties(Some(validation_layer)).unwrap_or_default()
+        }));
+        let debug_utils_enabled = debug_utils_available && (validation || cfg!(debug_assertions));
+
+        if validation && !debug_utils_enabled {
+            log::warn!("{:?} is not available, validation messages will not be reported.", debug_utils::NAME);
+        }
+
         // Select the extensions
         let mut extensions = ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw()).unwrap().to_vec();
-        extensions.push(debug_utils::NAME.as_ptr());
+        if debug_utils_enabled {
+            extensions.push(debug_utils::NAME.as_ptr());
+        }
 
         // Create the instance
         let app_name = c"Vulkan Video";
@@ -387,7 +751,7 @@ impl VkBase {
 
         let instance = unsafe { entry.create_instance(&create_info, None).expect("Failed to create instance.") };
 
-        (entry, instance)
+        (entry, instance, debug_utils_enabled)
     }
 
     pub fn create_surface(entry: &ash::Entry, instance: &ash::Instance, window: &Window) -> SurfaceBundle {
@@ -402,63 +766,112 @@ impl VkBase {
         }
     }
 
-    /* Select device */
-    pub fn select_phsyical_device(instance: &ash::Instance, surface: &SurfaceBundle) -> DeviceBundle{
-        let devs = unsafe { instance.enumerate_physical_devices().unwrap() };
+    /* Select device, the one matching the selector or the best scoring one */
+    pub fn select_phsyical_device(instance: &ash::Instance, surface: &SurfaceBundle, selector: Option<&adapter::GpuSelector>, debug_utils_enabled: bool) -> DeviceBundle{
+        let adapters = adapter::enumerate_adapters(instance, surface);
 
-        let mut queues = Vec::new();
+        println!("Found {} device(s).", adapters.len());
+        for adapter in adapters.iter() {
+            println!("\t{}", adapter.describe());
+        }
 
-        println!("Found {} device(s).", devs.len());
-        for dev in devs.iter() {
-            let properties = unsafe { instance.get_physical_device_properties(*dev) };
-            let queue_props = unsafe { instance.get_physical_device_queue_family_properties(*dev) };
-            let features = unsafe { instance.get_physical_device_features(*dev) };
+        let adapter = adapter::choose_adapter(&adapters, selector).unwrap_or_else(|e| panic!("Failed to select a device: {}", e));
+        println!("Using {}", adapter.name);
+        println!();
 
-            println!("\t{:?}", properties.device_name_as_c_str().unwrap());
+        let queue_family_index = adapter.queue_family_index.unwrap();
 
-            if !features.sampler_anisotropy == vk::FALSE {
-                continue;
-            }
+        // Uploads on another family are ordered against rendering with timeline semaphores
+        let timeline_semaphores = adapter.timeline_semaphore;
+        let transfer_queue_family_index = match adapter.transfer_queue_family_index {
+            Some(index) if timeline_semaphores => index,
+            _ => queue_family_index,
+        };
 
-            for (i, queue) in queue_props.iter().enumerate() {
-                let surface_support = unsafe { surface.loader.get_physical_device_surface_support(*dev, i as u32, surface.surface).unwrap() };
-                if surface_support && queue.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                    queues.push((i as u32, *dev));
-                }
-            }
+        if !timeline_semaphores {
+            log::warn!("{} has no timeline semaphores, uploading through the graphics queue.", adapter.name);
+        }
+
+        let queue_priorities = [1.0];
+        let mut queue_infos = vec![vk::DeviceQueueCreateInfo::default()
+            .queue_family_index(queue_family_index)
+            .queue_priorities(&queue_priorities)];
 
+        if transfer_queue_family_index != queue_family_index {
+            println!("Uploading through dedicated transfer queue family {}.", transfer_queue_family_index);
+            queue_infos.push(vk::DeviceQueueCreateInfo::default()
+                .queue_family_index(transfer_queue_family_index)
+                .queue_priorities(&queue_priorities));
         }
 
-        println!();
+        let physical = adapter.physical;
 
-        let queue_info = vk::DeviceQueueCreateInfo::default()
-            .queue_family_index(queues[0].0)
-            .queue_priorities(&[1.0]);
+        // The budget extension is optional, without it only the heap sizes are known
+        let extensions = unsafe { instance.enumerate_device_extension_properties(physical).unwrap_or_default() };
+        let memory_budget = extensions.iter().any(|extension| extension.extension_name_as_c_str() == Ok(ext::memory_budget::NAME));
 
-        let device_extension_names_raw = [
+        let mut device_extension_names_raw = vec![
             khr::swapchain::NAME.as_ptr(),
         ];
 
+        if memory_budget {
+            device_extension_names_raw.push(ext::memory_budget::NAME.as_ptr());
+        }
+
+        // Non solid fill is optional, it is only needed by the wireframe pipeline states
         let physical_features = vk::PhysicalDeviceFeatures::default()
-            .sampler_anisotropy(true);
+            .sampler_anisotropy(true)
+            .fill_mode_non_solid(adapter.fill_mode_non_solid);
 
-        let device_create_info = vk::DeviceCreateInfo::default()
+        let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default()
+            .timeline_semaphore(timeline_semaphores)
+            .host_query_reset(adapter.host_query_reset);
+
+        let mut device_create_info = vk::DeviceCreateInfo::default()
             .enabled_features(&physical_features)
-            .queue_create_infos(std::slice::from_ref(&queue_info))
+            .queue_create_infos(&queue_infos)
             .enabled_extension_names(&device_extension_names_raw);
 
-        let physical = queues[0].1;
-        let queue_family_index = queues[0].0 as u32;
+        if adapter.vulkan12 {
+            device_create_info = device_create_info.push_next(&mut vulkan12_features);
+        }
+
         let device = unsafe { instance.create_device(physical, &device_create_info, None).unwrap() };
         let present_queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+        let transfer_queue = unsafe { device.get_device_queue(transfer_queue_family_index, 0) };
         let mem_properties = unsafe { instance.get_physical_device_memory_properties(physical) };
+        let properties = unsafe { instance.get_physical_device_properties(physical) };
+        let queue_props = unsafe { instance.get_physical_device_queue_family_properties(physical) };
+
+        let depth_format = [vk::Format::D32_SFLOAT, vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT].into_iter().find(|format| {
+            let format_properties = unsafe { instance.get_physical_device_format_properties(physical, *format) };
+            format_properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        }).expect("Failed to find a supported depth format!");
+
+        #[cfg(debug_assertions)]
+        let debug_utils = if debug_utils_enabled { Some(debug_utils::Device::new(instance, &device)) } else { None };
+        #[cfg(not(debug_assertions))]
+        let _ = debug_utils_enabled;
 
         DeviceBundle {
             logical: device,
             physical,
             queue_family_index,
             present_queue,
-            mem_properties
+            transfer_queue_family_index,
+            transfer_queue,
+            mem_properties,
+            properties,
+            features: physical_features,
+            depth_format,
+            host_query_reset: adapter.host_query_reset,
+            timestamp_valid_bits: queue_props[queue_family_index as usize].timestamp_valid_bits,
+            transfer_timestamp_valid_bits: queue_props[transfer_queue_family_index as usize].timestamp_valid_bits,
+            timeline_semaphores,
+            memory_budget,
+            memory_tracker: MemoryTracker::default(),
+            #[cfg(debug_assertions)]
+            debug_utils,
         }
     }
 
@@ -561,12 +974,18 @@ impl VkBase {
     }
 
     /* Setup the graphics pipeline */
-    pub fn create_graphics_pipeline_impl(device: &DeviceBundle, swapchain: &SwapchainBundle, renderpass: &vk::RenderPass, global_descriptor_set_layout: vk::DescriptorSetLayout, shader_registry: &ShaderRegistry, shader_id: usize, ubo: Option<Vec<vk::DescriptorSetLayout>>) -> GraphicsPipelineBundle {
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_graphics_pipeline_impl(device: &DeviceBundle, pipeline_cache: vk::PipelineCache, renderpass: &vk::RenderPass, samples: vk::SampleCountFlags, global_descriptor_set_layout: vk::DescriptorSetLayout, shader_registry: &ShaderRegistry, pso: GraphicsPSO, ubo: Option<Vec<vk::DescriptorSetLayout>>) -> GraphicsPipelineBundle {
+
+        let shader_id = pso.shader_id;
 
         let pipeline_desc = &shader_registry.static_shaders[shader_id].details.descriptor;
         let use_global    =  shader_registry.static_shaders[shader_id].details.global_uniforms;
 
-        let ubo = if ubo.is_some() || pipeline_desc.ubo_layout_bindings.len() == 0 { ubo } else {
+        let ubo = if ubo.is_some() { ubo } else if pipeline_desc.ubo_layout_bindings.is_empty() {
+            // Only the global set, which VkBase owns
+            if use_global { Some(vec![global_descriptor_set_layout]) } else { None }
+        } else {
             let local = Self::create_descriptor_set_layout(device, &pipeline_desc.ubo_layout_bindings);
             if use_global {
                 Some(vec![global_descriptor_set_layout, local])
@@ -597,41 +1016,33 @@ impl VkBase {
             .vertex_attribute_descriptions(&pipeline_desc.vertex_attributes);
 
         let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo::default()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .topology(pso.topology)
             .primitive_restart_enable(false);
 
-        let viewports = [vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: swapchain.extent.width as f32,
-            height: swapchain.extent.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0
-        }];
-
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D {x: 0, y: 0},
-            extent: swapchain.extent
-        }];
-
-        // let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::default();
-        //.dynamic_states(&dynamic_states);
+        // Set with cmd_set_viewport / cmd_set_scissor so a resize does not invalidate the pipeline
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo::default()
+            .dynamic_states(&dynamic_states);
 
         let viewport_state_info = vk::PipelineViewportStateCreateInfo::default()
-            .viewports(&viewports)
-            .scissors(&scissors);
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let polygon_mode = if pso.polygon_mode == vk::PolygonMode::FILL || device.features.fill_mode_non_solid == vk::TRUE { pso.polygon_mode } else {
+            println!("Warning: Polygon mode {:?} is not supported by the device, falling back to fill.", pso.polygon_mode);
+            vk::PolygonMode::FILL
+        };
 
         let rasterization_info = vk::PipelineRasterizationStateCreateInfo::default()
-            .cull_mode(vk::CullModeFlags::BACK)
+            .cull_mode(pso.cull_mode)
             .front_face(vk::FrontFace::CLOCKWISE)
-            .polygon_mode(vk::PolygonMode::FILL)
+            .polygon_mode(polygon_mode)
             .depth_clamp_enable(false)
             .rasterizer_discard_enable(false)
             .line_width(1.0);
 
         let multisample_state_info = vk::PipelineMultisampleStateCreateInfo::default()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+            .rasterization_samples(samples);
 
         let stencil_state = vk::StencilOpState {
             fail_op: vk::StencilOp::KEEP,
@@ -643,7 +1054,10 @@ impl VkBase {
             reference: 0,
         };
 
+        // The 2D shaders draw at depth 0, so they stay in front of what a pane drew before them
         let depth_state_create_info = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(true)
+            .depth_write_enable(true)
             .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
             .depth_bounds_test_enable(false)
             .stencil_test_enable(false)
@@ -653,16 +1067,7 @@ impl VkBase {
             .min_depth_bounds(0.0);
 
 
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::RGBA,
-        }];
+        let color_blend_attachment_states = [pso.blend.attachment_state()];
 
         let color_blend_state = vk::PipelineColorBlendStateCreateInfo::default()
             .logic_op(vk::LogicOp::CLEAR)
@@ -672,9 +1077,11 @@ impl VkBase {
             Some(ubo) => {
                 vk::PipelineLayoutCreateInfo::default()
                     .set_layouts(&ubo)
+                    .push_constant_ranges(&pipeline_desc.push_constant_ranges)
             },
 
             None => vk::PipelineLayoutCreateInfo::default()
+                .push_constant_ranges(&pipeline_desc.push_constant_ranges)
         };
 
         let pipeline_layout = unsafe { device.logical.create_pipeline_layout(&layout_create_info, None).unwrap() };
@@ -693,25 +1100,57 @@ impl VkBase {
                                       .render_pass(*renderpass)];
 
         let graphics_pipelines = unsafe {
-            device.logical.create_graphics_pipelines(vk::PipelineCache::null(), &graphic_pipeline_infos, None)
+            device.logical.create_graphics_pipelines(pipeline_cache, &graphic_pipeline_infos, None)
                 .expect("Failed to create Graphics Pipeline!.")
         };
 
 
+        #[cfg(debug_assertions)]
+        {
+            let shader_name = shader_registry.static_shaders[shader_id].details.vert_path.file_stem().unwrap_or_default().to_string_lossy();
+            crate::debug_name!(device, graphics_pipelines[0], "{} pipeline {:?}", shader_name, pso);
+            crate::debug_name!(device, pipeline_layout, "{} pipeline layout", shader_name);
+        }
+
         GraphicsPipelineBundle {
-            id: shader_id,
+            pso,
             graphics: graphics_pipelines[0],
             layout: pipeline_layout,
             ubo,
-            pipeline_desc: pipeline_desc.clone()
+            pipeline_desc: pipeline_desc.clone(),
+            global_uniforms: use_global,
         }
     }
 
-    pub fn create_render_pass(device: &DeviceBundle, swapchain: &SwapchainBundle) -> vk::RenderPass{
+    /* Attachments are colour, depth and, when multisampling, the swapchain image the colour resolves into */
+    pub fn create_render_pass(device: &DeviceBundle, swapchain: &SwapchainBundle, samples: vk::SampleCountFlags) -> vk::RenderPass{
+        let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+
+        // Multisampled colour is resolved at the end of the subpass so its samples never need storing
         let color_attachment = vk::AttachmentDescription::default()
             .format(swapchain.format)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(samples)
             .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(if multisampled { vk::AttachmentStoreOp::DONT_CARE } else { vk::AttachmentStoreOp::STORE })
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(if multisampled { vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL } else { vk::ImageLayout::PRESENT_SRC_KHR });
+
+        let depth_attachment = vk::AttachmentDescription::default()
+            .format(device.depth_format)
+            .samples(samples)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let resolve_attachment = vk::AttachmentDescription::default()
+            .format(swapchain.format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
             .store_op(vk::AttachmentStoreOp::STORE)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
@@ -722,15 +1161,40 @@ impl VkBase {
                                     .attachment(0)
                                     .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
 
-        let subpass = [vk::SubpassDescription::default()
-                       .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-                       .color_attachments(&color_attachment_ref)];
+        let depth_attachment_ref = vk::AttachmentReference::default()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+        let resolve_attachment_ref = [vk::AttachmentReference::default()
+                                      .attachment(2)
+                                      .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+
+        let subpass = vk::SubpassDescription::default()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_ref)
+            .depth_stencil_attachment(&depth_attachment_ref);
 
-        let render_pass_attachments = [color_attachment];
+        let subpass = if multisampled { [subpass.resolve_attachments(&resolve_attachment_ref)] } else { [subpass] };
+
+        // The depth (and multisampled colour) targets are shared by every frame in flight
+        let dependencies = [vk::SubpassDependency::default()
+                            .src_subpass(vk::SUBPASS_EXTERNAL)
+                            .dst_subpass(0)
+                            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+                            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+                            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)];
+
+        let render_pass_attachments = if multisampled {
+            vec![color_attachment, depth_attachment, resolve_attachment]
+        } else {
+            vec![color_attachment, depth_attachment]
+        };
 
         let renderpass_create_info = vk::RenderPassCreateInfo::default()
             .attachments(&render_pass_attachments)
-            .subpasses(&subpass);
+            .subpasses(&subpass)
+            .dependencies(&dependencies);
 
         unsafe {
             device.logical.create_render_pass(&renderpass_create_info, None)
@@ -738,11 +1202,15 @@ impl VkBase {
         }
     }
 
-    pub fn create_framebuffers(device: &DeviceBundle, render_pass: &vk::RenderPass, image_views: &Vec<vk::ImageView>, swapchain: &SwapchainBundle) -> Vec<vk::Framebuffer>{
+    pub fn create_framebuffers(device: &DeviceBundle, render_pass: &vk::RenderPass, image_views: &Vec<vk::ImageView>, render_targets: &RenderTargetsBundle, swapchain: &SwapchainBundle) -> Vec<vk::Framebuffer>{
         let mut framebuffers = vec![];
 
         for &image_view in image_views.iter() {
-            let attachments = [image_view];
+            let depth_view = render_targets.depth.image_view;
+            let attachments = match render_targets.color.as_ref() {
+                Some(color) => vec![color.image_view, depth_view, image_view],
+                None => vec![image_view, depth_view],
+            };
 
             let framebuffer_create_info = vk::FramebufferCreateInfo::default()
                 .render_pass(*render_pass)
@@ -762,10 +1230,51 @@ impl VkBase {
         framebuffers
     }
 
-    pub fn create_command_pools(device: &DeviceBundle, num: usize, num_buffers: usize) -> Vec<CommandBundle> {
+    pub fn create_render_targets(device: &DeviceBundle, swapchain: &SwapchainBundle, samples: vk::SampleCountFlags) -> RenderTargetsBundle {
+        let create_target = |format: vk::Format, usage: vk::ImageUsageFlags, aspect_flags: vk::ImageAspectFlags| {
+            let name = if aspect_flags == vk::ImageAspectFlags::COLOR { "Multisampled colour target" } else { "Depth target" };
+
+            let resource = create_image(device, swapchain.extent.width, swapchain.extent.height, format, samples,
+                                        vk::ImageTiling::OPTIMAL,
+                                        usage | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                                        vk::MemoryPropertyFlags::DEVICE_LOCAL, name).expect("Failed to create render target!");
+
+            let image_view = create_image_view(device, &resource, aspect_flags, 1).expect("Failed to create render target view!");
+
+            debug::name_object(device, resource.image, name);
+            debug::name_object(device, image_view, name);
+
+            RenderTargetBundle { resource, image_view }
+        };
+
+        // Without multisampling the colour is rendered straight into the swapchain image
+        let color = if samples == vk::SampleCountFlags::TYPE_1 { None } else {
+            Some(create_target(swapchain.format, vk::ImageUsageFlags::COLOR_ATTACHMENT, vk::ImageAspectFlags::COLOR))
+        };
+
+        let depth_aspect = match device.depth_format {
+            vk::Format::D32_SFLOAT | vk::Format::D16_UNORM => vk::ImageAspectFlags::DEPTH,
+            _ => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        };
+
+        let depth = create_target(device.depth_format, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, depth_aspect);
+
+        RenderTargetsBundle { samples, color, depth }
+    }
+
+    pub fn destroy_render_targets(device: &DeviceBundle, render_targets: &RenderTargetsBundle) {
+        let targets = render_targets.color.iter().chain(std::iter::once(&render_targets.depth));
+
+        for target in targets {
+            unsafe { device.logical.destroy_image_view(target.image_view, None); }
+            destroy_image(device, &target.resource);
+        }
+    }
+
+    pub fn create_command_pools(device: &DeviceBundle, queue_family_index: u32, num: usize, num_buffers: usize) -> Vec<CommandBundle> {
         let command_pool_create_info = vk::CommandPoolCreateInfo::default()
             .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-            .queue_family_index(device.queue_family_index);
+            .queue_family_index(queue_family_index);
 
         let mut commands = Vec::new();
 
@@ -788,6 +1297,21 @@ impl VkBase {
         commands
     }
 
+    /* A null handle when the device has no timeline semaphores */
+    pub fn create_timeline_semaphore(device: &DeviceBundle) -> vk::Semaphore {
+        if !device.timeline_semaphores {
+            return vk::Semaphore::null();
+        }
+
+        let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+
+        let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+
+        unsafe { device.logical.create_semaphore(&create_info, None).expect("Failed to create timeline semaphore!") }
+    }
+
     pub fn create_sync_objects(device: &DeviceBundle, num: usize) -> SyncObjectsBundle {
         let mut sync_objects = SyncObjectsBundle {
             image_available_semaphores: vec![],
@@ -829,12 +1353,13 @@ impl VkBase {
     /* Create descriptor sets */
     fn create_descriptor_pool(device: &DeviceBundle, swapchain_images_size: usize) -> vk::DescriptorPool {
         let pool_sizes = [
-            vk::DescriptorPoolSize { descriptor_count: swapchain_images_size as u32 * 2, ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER },
-            vk::DescriptorPoolSize { descriptor_count: swapchain_images_size as u32 * 2, ty: vk::DescriptorType::UNIFORM_BUFFER },
+            vk::DescriptorPoolSize { descriptor_count: swapchain_images_size as u32 * DESCRIPTOR_POOL_SCALE, ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER },
+            vk::DescriptorPoolSize { descriptor_count: swapchain_images_size as u32 * DESCRIPTOR_POOL_SCALE, ty: vk::DescriptorType::UNIFORM_BUFFER },
+            vk::DescriptorPoolSize { descriptor_count: swapchain_images_size as u32 * DESCRIPTOR_POOL_SCALE, ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC },
         ];
         let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::default()
-            .flags(vk::DescriptorPoolCreateFlags::empty())
-            .max_sets(swapchain_images_size as u32 * pool_sizes.len() as u32 * 2)
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(swapchain_images_size as u32 * pool_sizes.len() as u32 * DESCRIPTOR_POOL_SCALE)
             .pool_sizes(&pool_sizes);
 
         unsafe {
@@ -918,6 +1443,7 @@ impl VkBase {
         device: &DeviceBundle,
         descriptor_set: vk::DescriptorSet,
         buffers: &[&BufferBundle],
+        descriptor_type: vk::DescriptorType,
         dst_binding: u32,
     )  {
 
@@ -935,7 +1461,7 @@ impl VkBase {
                 .dst_binding(dst_binding)
                 .dst_array_element(0)
                 .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_type(descriptor_type)
                 .buffer_info(&descriptor_buffer_infos)
         ];
 
@@ -992,11 +1518,15 @@ impl VkBase {
     }
 
     /* Setup validation layer callbacks */
-    pub fn setup_validation(entry: &ash::Entry, instance: &ash::Instance) -> (debug_utils::Instance, vk::DebugUtilsMessengerEXT) {
-        let message_severity = vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-        // | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-        // | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-            | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+    pub fn setup_validation(entry: &ash::Entry, instance: &ash::Instance) -> DebugUtilsBundle {
+        // Only ask for the severities the logger will print
+        let message_severity = match log::max_level() {
+            LevelFilter::Off => vk::DebugUtilsMessageSeverityFlagsEXT::empty(),
+            LevelFilter::Error => vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            LevelFilter::Warn => vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            LevelFilter::Info => vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            _ => vk::DebugUtilsMessageSeverityFlagsEXT::ERROR | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::INFO | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+        };
 
         let message_type = vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
             | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
@@ -1007,11 +1537,12 @@ impl VkBase {
             .message_type(message_type)
             .pfn_user_callback(Some(vulkan_debug_utils_callback));
 
-        let debug_utils_loader = debug_utils::Instance::new(entry, instance);
-        let utils_messenger = unsafe {
-            debug_utils_loader.create_debug_utils_messenger(&messenger_ci, None).expect("Debug Utils Callback")
+        let loader = debug_utils::Instance::new(entry, instance);
+        let messenger = unsafe {
+            loader.create_debug_utils_messenger(&messenger_ci, None).expect("Debug Utils Callback")
         };
-        (debug_utils_loader, utils_messenger)
+
+        DebugUtilsBundle { loader, messenger }
     }
 
 }
@@ -1023,8 +1554,27 @@ impl Drop for VkBase {
             let _ = self.device.logical.device_wait_idle();
             self.cleanup_in_flight_buffers();
 
+            // Whoever owned VkBase has dropped its resources by now, the GPU is idle so they can all go
+            let resources = self.deferred_releases.drain(&self.release_queue);
+            self.destroy_resources(resources);
+            self.release_queue.close();
+            self.allocator.release(&self.device);
+
+            if let Err(e) = pipeline_cache::save(&self.device, self.pipeline_cache, Path::new(PIPELINE_CACHE_FILE)) {
+                println!("Failed to save the pipeline cache: {}", e);
+            }
+            self.device.logical.destroy_pipeline_cache(self.pipeline_cache, None);
+
+            self.profiler.destroy(&self.device);
+            self.upload_ring.destroy(&self.device);
+
+            for (_, variant) in self.pipeline_variants.drain() {
+                self.device.logical.destroy_pipeline(variant.graphics, None);
+                self.device.logical.destroy_pipeline_layout(variant.layout, None);
+            }
+
             for i in 0..self.graphics_pipelines.len() {
-                let shader_id = self.graphics_pipelines[i].id;
+                let shader_id = self.graphics_pipelines[i].pso.shader_id;
                 if let Some(ubo) = self.graphics_pipelines[i].ubo.as_ref() {
                     // The first ubo will always be the global uniform if it has been requested
                     let idx = if self.shader_registry.static_shaders[shader_id].details.global_uniforms { 1 } else { 0 };
@@ -1064,15 +1614,28 @@ impl Drop for VkBase {
                 self.device.logical.destroy_command_pool(command.pool, None);
             }
 
-            self.device.logical.free_command_buffers(self.spare_command.pool, &self.spare_command.buffers);
             self.device.logical.destroy_command_pool(self.spare_command.pool, None);
 
+            self.device.logical.free_command_buffers(self.transfer_command.pool, &self.transfer_command.buffers);
+            self.device.logical.destroy_command_pool(self.transfer_command.pool, None);
+            self.device.logical.destroy_semaphore(self.upload_timeline, None);
+            self.device.logical.destroy_semaphore(self.render_timeline, None);
+
             self.device.logical.destroy_descriptor_pool(self.descriptor_pool, None);
 
+            // Everything made through utils and the allocator has been destroyed by now
+            log::info!("{}", self.device.memory_tracker.report());
+            let leaks = self.device.memory_tracker.report_leaks();
+            if leaks > 0 {
+                log::error!("{} Vulkan objects were still alive at shutdown", leaks);
+            }
+
             self.device.logical.destroy_device(None);
             self.surface.loader.destroy_surface(self.surface.surface, None);
 
-            self.debug_utils_loader.destroy_debug_utils_messenger(self.debug_messenger, None);
+            if let Some(debug_utils) = self.debug_utils.as_ref() {
+                debug_utils.loader.destroy_debug_utils_messenger(debug_utils.messenger, None);
+            }
             self.instance.destroy_instance(None);
         }
     }
@@ -1084,12 +1647,12 @@ unsafe extern "system" fn vulkan_debug_utils_callback(
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
     _p_user_data: *mut c_void,
 ) -> vk::Bool32 {
-    let severity = match message_severity {
-        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "[Verbose]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "[Warning]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "[Error]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "[Info]",
-        _ => "[Unknown]",
+    let level = match message_severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => Level::Trace,
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => Level::Warn,
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => Level::Error,
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => Level::Info,
+        _ => Level::Debug,
     };
     let types = match message_type {
         vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "[General]",
@@ -1098,7 +1661,7 @@ unsafe extern "system" fn vulkan_debug_utils_callback(
         _ => "[Unknown]",
     };
     let message = CStr::from_ptr((*p_callback_data).p_message);
-    println!("[Debug]{}{}{}", severity, types, message.to_string_lossy());
+    log::log!(target: "vulkan", level, "{}{}", types, message.to_string_lossy());
 
     vk::FALSE
 }
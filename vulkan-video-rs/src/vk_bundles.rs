@@ -1,17 +1,58 @@
 use ash::vk;
 use ash::khr;
+use ash::ext::debug_utils;
+
+use crate::rhi::core::GraphicsPSO;
+use crate::rhi::memory_tracker::MemoryTracker;
 
 pub struct SurfaceBundle {
     pub surface: vk::SurfaceKHR,
     pub loader: khr::surface::Instance
 }
 
+pub struct DebugUtilsBundle {
+    pub loader: debug_utils::Instance,
+    pub messenger: vk::DebugUtilsMessengerEXT,
+}
+
 pub struct DeviceBundle {
     pub logical: ash::Device,
     pub physical: vk::PhysicalDevice,
     pub queue_family_index: u32,
     pub present_queue: vk::Queue,
-    pub mem_properties: vk::PhysicalDeviceMemoryProperties
+
+    /* Same as the graphics family and queue when the device has no dedicated transfer family or no timeline semaphores */
+    pub transfer_queue_family_index: u32,
+    pub transfer_queue: vk::Queue,
+    pub mem_properties: vk::PhysicalDeviceMemoryProperties,
+    pub properties: vk::PhysicalDeviceProperties,
+    pub features: vk::PhysicalDeviceFeatures,
+    pub depth_format: vk::Format,
+
+    /* Timestamp support for the profiler, zero valid bits when a queue can't write timestamps */
+    pub host_query_reset: bool,
+    pub timestamp_valid_bits: u32,
+    pub transfer_timestamp_valid_bits: u32,
+
+    /* Uploads and rendering wait on VkBase::upload_timeline and render_timeline, otherwise both go to the graphics queue
+     * in submission order */
+    pub timeline_semaphores: bool,
+
+    /* VK_EXT_memory_budget is enabled, see rhi::allocator::memory_budget */
+    pub memory_budget: bool,
+
+    /* Every buffer, image and memory allocation made through utils and rhi::allocator */
+    pub memory_tracker: MemoryTracker,
+
+    /* Used to name objects and label command buffers, see rhi::debug */
+    #[cfg(debug_assertions)]
+    pub debug_utils: Option<debug_utils::Device>,
+}
+
+impl DeviceBundle {
+    pub fn has_dedicated_transfer(&self) -> bool {
+        self.transfer_queue_family_index != self.queue_family_index
+    }
 }
 
 pub struct SwapchainBundle {
@@ -35,15 +76,19 @@ pub struct DescSetBinding {
 pub struct PipelineDescriptor {
     pub ubo_layout_bindings: Vec<DescSetBinding>,
     pub vertex_bindings: Vec<vk::VertexInputBindingDescription>,
-    pub vertex_attributes: Vec<vk::VertexInputAttributeDescription>
+    pub vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
 }
 
 pub struct GraphicsPipelineBundle {
-    pub id: usize,
+    pub pso: GraphicsPSO,
     pub graphics: vk::Pipeline,
     pub layout: vk::PipelineLayout,
     pub ubo: Option<Vec<vk::DescriptorSetLayout>>,
-    pub pipeline_desc: PipelineDescriptor
+    pub pipeline_desc: PipelineDescriptor,
+
+    /* Set 0 is the global camera set, bound per pane with its dynamic offset */
+    pub global_uniforms: bool,
 }
 
 pub struct SyncObjectsBundle {
@@ -53,6 +98,7 @@ pub struct SyncObjectsBundle {
     pub spare_fences: Vec<vk::Fence>,
 }
 
+#[derive(Debug)]
 pub struct BufferBundle {
     pub buffer: vk::Buffer,
     pub memory: vk::DeviceMemory,
@@ -64,8 +110,23 @@ pub struct ImageBundle {
     pub image: vk::Image,
     pub memory: vk::DeviceMemory,
     pub format: vk::Format,
+
+    /* Where the image is bound, memory can be a block shared with other images */
+    pub offset: u64,
+    pub size: u64,
 }
 
+pub struct RenderTargetBundle {
+    pub resource: ImageBundle,
+    pub image_view: vk::ImageView,
+}
+
+/* Attachments owned by the renderer, the swapchain image is the resolve target when multisampling */
+pub struct RenderTargetsBundle {
+    pub samples: vk::SampleCountFlags,
+    pub color: Option<RenderTargetBundle>,
+    pub depth: RenderTargetBundle,
+}
 
 pub struct CommandBundle {
     pub pool: vk::CommandPool,
@@ -77,5 +138,4 @@ pub struct TextureBundle {
     pub sampler: vk::Sampler,
     pub image_view: vk::ImageView,
     pub aspect_flags: vk::ImageAspectFlags,
-    pub staging: BufferBundle,
 }